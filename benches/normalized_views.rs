@@ -0,0 +1,198 @@
+//! Compares the join-based `note_tags` and `note_links` views against the correlated
+//! subqueries they replaced, on a generated database the size of a large library.
+//!
+//! Run with `cargo bench --bench normalized_views`. Before measuring, each query is
+//! checked to return the same rows with both shapes.
+
+use bear_query::{BearDb, Value};
+use criterion::{Criterion, criterion_group, criterion_main};
+use rusqlite::{Connection, OpenFlags, params};
+use std::hint::black_box;
+use std::path::Path;
+
+const NOTES: i64 = 20_000;
+const TAGS: i64 = 500;
+const TAGS_PER_NOTE: i64 = 3;
+const LINKS_PER_NOTE: i64 = 2;
+
+/// The normalizing views as they were before `note_tags` and `note_links` became joins
+const CORRELATED_VIEWS: &str = r"
+WITH
+  core_data AS (
+    SELECT unixepoch('2001-01-01') as epoch
+  ),
+  notes AS (
+    SELECT
+      n.ZUNIQUEIDENTIFIER as id,
+      n.Z_PK as core_db_id,
+      n.ZTITLE as title,
+      n.ZTEXT as content,
+      datetime(n.ZMODIFICATIONDATE + cd.epoch, 'unixepoch') as modified,
+      datetime(n.ZCREATIONDATE + cd.epoch, 'unixepoch') as created,
+      n.ZPINNED as is_pinned,
+      n.ZTRASHED as is_trashed,
+      n.ZARCHIVED as is_archived
+    FROM ZSFNOTE as n, core_data as cd
+  ),
+  tags AS (
+    SELECT
+      t.Z_PK as id,
+      t.ZTITLE as name,
+      datetime(t.ZMODIFICATIONDATE + cd.epoch, 'unixepoch') as modified
+    FROM ZSFNOTETAG as t, core_data as cd
+  ),
+  note_tags AS (
+    SELECT
+      (SELECT n.ZUNIQUEIDENTIFIER FROM ZSFNOTE n WHERE n.Z_PK = nt.Z_5NOTES) as note_id,
+      nt.Z_13TAGS as tag_id
+    FROM Z_5TAGS as nt
+  ),
+  note_links AS (
+    SELECT
+      (SELECT n.ZUNIQUEIDENTIFIER FROM ZSFNOTE n WHERE n.Z_PK = nl.ZLINKEDBY) as from_note_id,
+      (SELECT n.ZUNIQUEIDENTIFIER FROM ZSFNOTE n WHERE n.Z_PK = nl.ZLINKINGTO) as to_note_id
+    FROM ZSFNOTEBACKLINK as nl
+  )
+";
+
+/// Tag-heavy and link-heavy queries, ordered so both shapes return rows in the same order
+const QUERIES: &[(&str, &str)] = &[
+  (
+    "tag_counts",
+    "SELECT t.name, COUNT(*) AS notes
+     FROM note_tags nt
+     JOIN tags t ON t.id = nt.tag_id
+     JOIN notes n ON n.id = nt.note_id
+     WHERE n.is_trashed = 0
+     GROUP BY t.name
+     ORDER BY t.name",
+  ),
+  (
+    "notes_with_tag",
+    "SELECT n.title
+     FROM notes n
+     JOIN note_tags nt ON nt.note_id = n.id
+     WHERE nt.tag_id = 42
+     ORDER BY n.core_db_id",
+  ),
+  (
+    "backlink_counts",
+    "SELECT nl.to_note_id, COUNT(*) AS backlinks
+     FROM note_links nl
+     GROUP BY nl.to_note_id
+     ORDER BY backlinks DESC, nl.to_note_id
+     LIMIT 100",
+  ),
+];
+
+/// Writes a Bear-like database with `NOTES` notes, each with a few tags and links
+fn generate_database(path: &Path) -> rusqlite::Result<()> {
+  let mut conn = Connection::open(path)?;
+  conn.execute_batch(
+    r"
+    CREATE TABLE ZSFNOTE (
+      Z_PK INTEGER PRIMARY KEY,
+      ZUNIQUEIDENTIFIER TEXT,
+      ZTITLE TEXT,
+      ZTEXT TEXT,
+      ZMODIFICATIONDATE REAL,
+      ZCREATIONDATE REAL,
+      ZPINNED INTEGER,
+      ZTRASHED INTEGER,
+      ZARCHIVED INTEGER
+    );
+    CREATE TABLE ZSFNOTETAG (Z_PK INTEGER PRIMARY KEY, ZTITLE TEXT, ZMODIFICATIONDATE REAL);
+    CREATE TABLE Z_5TAGS (Z_5NOTES INTEGER, Z_13TAGS INTEGER);
+    CREATE TABLE ZSFNOTEBACKLINK (ZLINKEDBY INTEGER, ZLINKINGTO INTEGER);
+    CREATE INDEX Z_5TAGS_Z_13TAGS_INDEX ON Z_5TAGS (Z_13TAGS, Z_5NOTES);
+    CREATE INDEX ZSFNOTEBACKLINK_ZLINKEDBY_INDEX ON ZSFNOTEBACKLINK (ZLINKEDBY);
+    CREATE INDEX ZSFNOTEBACKLINK_ZLINKINGTO_INDEX ON ZSFNOTEBACKLINK (ZLINKINGTO);
+  ",
+  )?;
+
+  // A fixed linear congruential generator keeps the database the same between runs
+  let mut seed: u64 = 0x2545_F491_4F6C_DD1D;
+  let mut next = |bound: i64| {
+    seed = seed
+      .wrapping_mul(6_364_136_223_846_793_005)
+      .wrapping_add(1_442_695_040_888_963_407);
+    ((seed >> 33) % bound as u64) as i64
+  };
+
+  let tx = conn.transaction()?;
+  {
+    let mut insert_tag = tx.prepare("INSERT INTO ZSFNOTETAG VALUES (?1, ?2, ?3)")?;
+    for tag in 1..=TAGS {
+      insert_tag.execute(params![tag, format!("tag-{}", tag), tag as f64])?;
+    }
+
+    let mut insert_note =
+      tx.prepare("INSERT INTO ZSFNOTE VALUES (?1, ?2, ?3, ?4, ?5, ?5, 0, ?6, 0)")?;
+    let mut insert_note_tag = tx.prepare("INSERT INTO Z_5TAGS VALUES (?1, ?2)")?;
+    let mut insert_link = tx.prepare("INSERT INTO ZSFNOTEBACKLINK VALUES (?1, ?2)")?;
+    for note in 1..=NOTES {
+      insert_note.execute(params![
+        note,
+        format!("{:08X}-0000-0000-0000-{:012X}", note, note),
+        format!("Note {}", note),
+        format!("# Note {}\n\nSome content for note {}.", note, note),
+        (note * 3600) as f64,
+        (next(20) == 0) as i64,
+      ])?;
+      for _ in 0..TAGS_PER_NOTE {
+        insert_note_tag.execute(params![note, next(TAGS) + 1])?;
+      }
+      for _ in 0..LINKS_PER_NOTE {
+        insert_link.execute(params![note, next(NOTES) + 1])?;
+      }
+    }
+  }
+  tx.commit()
+}
+
+/// Runs a query and collects every row
+fn run(
+  conn: &Connection,
+  sql: &str,
+) -> Vec<Vec<Value>> {
+  let mut stmt = conn.prepare(sql).expect("valid benchmark query");
+  let columns = stmt.column_count();
+  stmt
+    .query_map([], |row| (0..columns).map(|i| row.get(i)).collect())
+    .expect("query runs")
+    .collect::<rusqlite::Result<_>>()
+    .expect("rows can be read")
+}
+
+fn normalized_views(c: &mut Criterion) {
+  let dir = tempfile::tempdir().expect("temporary directory");
+  let path = dir.path().join("database.sqlite");
+  generate_database(&path).expect("synthetic database");
+
+  let db = BearDb::open_for_bench(&path).expect("synthetic database opens");
+  let conn = Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+    .expect("synthetic database opens");
+
+  for (name, query) in QUERIES {
+    // The library's current views, exactly as it expands them
+    let joins = db.explain(query).expect("query plans").sql().to_string();
+    let correlated = format!("{}\n{}", CORRELATED_VIEWS, query);
+    assert_eq!(
+      run(&conn, &joins),
+      run(&conn, &correlated),
+      "{} returns different rows with joins",
+      name
+    );
+
+    let mut group = c.benchmark_group(*name);
+    group.sample_size(10);
+    group.bench_function("correlated", |b| {
+      b.iter(|| run(&conn, black_box(&correlated)))
+    });
+    group.bench_function("joins", |b| b.iter(|| run(&conn, black_box(&joins))));
+    group.finish();
+  }
+}
+
+criterion_group!(benches, normalized_views);
+criterion_main!(benches);
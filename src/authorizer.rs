@@ -0,0 +1,319 @@
+/// Read-only enforcement for queries against Bear's database
+///
+/// This module installs a SQLite authorizer on each query connection. SQLite asks it
+/// about every action while a statement is prepared, so anything other than reading
+/// Bear's tables is rejected before it can run, with a `BearError::ForbiddenStatement`
+/// naming what was blocked.
+use crate::BearError;
+use rusqlite::Connection;
+use rusqlite::hooks::{AuthAction, AuthContext, Authorization};
+use std::sync::{Arc, Mutex};
+
+/// Pragmas that take a table or index name as their argument and only read the schema
+const SCHEMA_PRAGMAS: &[&str] = &[
+  "table_info",
+  "table_xinfo",
+  "table_list",
+  "index_list",
+  "index_info",
+  "index_xinfo",
+  "foreign_key_list",
+];
+
+/// Pragmas that only report a setting or list something when called without an
+/// argument. Others, like `optimize` or `wal_checkpoint`, can write to the database
+/// even without one.
+const READ_ONLY_PRAGMAS: &[&str] = &[
+  "application_id",
+  "auto_vacuum",
+  "collation_list",
+  "compile_options",
+  "data_version",
+  "database_list",
+  "encoding",
+  "foreign_keys",
+  "freelist_count",
+  "function_list",
+  "journal_mode",
+  "module_list",
+  "page_count",
+  "page_size",
+  "pragma_list",
+  "query_only",
+  "schema_version",
+  "user_version",
+];
+
+/// Functions that are never allowed, even though SQLite keeps them disabled by default
+const FORBIDDEN_FUNCTIONS: &[&str] = &["load_extension"];
+
+/// The first action the authorizer denied on a connection
+#[derive(Debug, Clone, PartialEq)]
+struct Blocked {
+  action: &'static str,
+  target: Option<String>,
+}
+
+/// Records what the authorizer of a connection blocked, to explain the SQL error
+/// SQLite returns for it.
+pub(crate) struct Authorizer {
+  blocked: Arc<Mutex<Option<Blocked>>>,
+}
+
+impl Authorizer {
+  /// Installs the read-only authorizer on a connection.
+  pub(crate) fn install(conn: &Connection) -> Self {
+    let blocked = Arc::new(Mutex::new(None));
+    let record = Arc::clone(&blocked);
+
+    conn.authorizer(Some(move |context: AuthContext<'_>| {
+      if is_allowed(&context) {
+        return Authorization::Allow;
+      }
+      if let Ok(mut blocked) = record.lock() {
+        blocked.get_or_insert_with(|| describe(&context.action));
+      }
+      Authorization::Deny
+    }));
+
+    Self { blocked }
+  }
+
+  /// Turns the error of a query the authorizer blocked, or of SQL holding more than
+  /// one statement, into `BearError::ForbiddenStatement`.
+  pub(crate) fn explain(
+    &self,
+    error: BearError,
+  ) -> BearError {
+    let blocked = self
+      .blocked
+      .lock()
+      .ok()
+      .and_then(|mut blocked| blocked.take());
+    match (blocked, error) {
+      // rusqlite prepares the rest of the SQL to find a second statement, so a
+      // second statement the authorizer blocks is reported as itself instead
+      (
+        _,
+        BearError::SqlError {
+          source: rusqlite::Error::MultipleStatement,
+          ..
+        },
+      ) => BearError::ForbiddenStatement {
+        action: "MULTIPLE STATEMENTS".to_string(),
+        target: None,
+      },
+      (Some(Blocked { action, target }), BearError::SqlError { .. }) => {
+        BearError::ForbiddenStatement {
+          action: action.to_string(),
+          target,
+        }
+      }
+      (_, error) => error,
+    }
+  }
+}
+
+/// Allows selecting from Bear's tables and calling functions, nothing else
+fn is_allowed(context: &AuthContext<'_>) -> bool {
+  match context.action {
+    AuthAction::Select | AuthAction::Recursive => true,
+    // Reads of CTEs and subqueries (like the normalized views) aren't in a database
+    AuthAction::Read { table_name, .. } => {
+      context.database_name.is_none() || is_readable_table(table_name)
+    }
+    AuthAction::Function { function_name } => !FORBIDDEN_FUNCTIONS
+      .iter()
+      .any(|name| function_name.eq_ignore_ascii_case(name)),
+    AuthAction::Pragma {
+      pragma_name,
+      pragma_value,
+    } => {
+      let listed = |pragmas: &[&str]| {
+        pragmas
+          .iter()
+          .any(|name| pragma_name.eq_ignore_ascii_case(name))
+      };
+      listed(SCHEMA_PRAGMAS) || (pragma_value.is_none() && listed(READ_ONLY_PRAGMAS))
+    }
+    _ => false,
+  }
+}
+
+/// Core Data tables (`ZSFNOTE`, `Z_5TAGS`, ...) and the schema table
+fn is_readable_table(table_name: &str) -> bool {
+  table_name.starts_with(['Z', 'z'])
+    || table_name.eq_ignore_ascii_case("sqlite_master")
+    || table_name.eq_ignore_ascii_case("sqlite_schema")
+}
+
+/// Names a denied action and the table, file, pragma or function it targets
+fn describe(action: &AuthAction<'_>) -> Blocked {
+  let (action, target) = match *action {
+    // Creating, altering or dropping anything first writes to the schema table
+    AuthAction::Insert { table_name }
+    | AuthAction::Update { table_name, .. }
+    | AuthAction::Delete { table_name }
+      if table_name.starts_with("sqlite_") =>
+    {
+      ("SCHEMA CHANGE", None)
+    }
+    AuthAction::Insert { table_name } => ("INSERT", Some(table_name)),
+    AuthAction::Update { table_name, .. } => ("UPDATE", Some(table_name)),
+    AuthAction::Delete { table_name } => ("DELETE", Some(table_name)),
+    AuthAction::Read { table_name, .. } => ("READ", Some(table_name)),
+    AuthAction::Pragma { pragma_name, .. } => ("PRAGMA", Some(pragma_name)),
+    AuthAction::Function { function_name } => ("FUNCTION", Some(function_name)),
+    AuthAction::Attach { filename } => ("ATTACH", Some(filename)),
+    AuthAction::Detach { database_name } => ("DETACH", Some(database_name)),
+    AuthAction::Transaction { .. } => ("TRANSACTION", None),
+    AuthAction::Savepoint { savepoint_name, .. } => ("SAVEPOINT", Some(savepoint_name)),
+    AuthAction::CreateTable { table_name }
+    | AuthAction::CreateTempTable { table_name }
+    | AuthAction::CreateVtable { table_name, .. } => ("CREATE TABLE", Some(table_name)),
+    AuthAction::CreateIndex { index_name, .. } | AuthAction::CreateTempIndex { index_name, .. } => {
+      ("CREATE INDEX", Some(index_name))
+    }
+    AuthAction::CreateTrigger { trigger_name, .. }
+    | AuthAction::CreateTempTrigger { trigger_name, .. } => ("CREATE TRIGGER", Some(trigger_name)),
+    AuthAction::CreateView { view_name } | AuthAction::CreateTempView { view_name } => {
+      ("CREATE VIEW", Some(view_name))
+    }
+    AuthAction::DropTable { table_name }
+    | AuthAction::DropTempTable { table_name }
+    | AuthAction::DropVtable { table_name, .. } => ("DROP TABLE", Some(table_name)),
+    AuthAction::DropIndex { index_name, .. } | AuthAction::DropTempIndex { index_name, .. } => {
+      ("DROP INDEX", Some(index_name))
+    }
+    AuthAction::DropTrigger { trigger_name, .. }
+    | AuthAction::DropTempTrigger { trigger_name, .. } => ("DROP TRIGGER", Some(trigger_name)),
+    AuthAction::DropView { view_name } | AuthAction::DropTempView { view_name } => {
+      ("DROP VIEW", Some(view_name))
+    }
+    AuthAction::AlterTable { table_name, .. } => ("ALTER TABLE", Some(table_name)),
+    AuthAction::Reindex { index_name } => ("REINDEX", Some(index_name)),
+    AuthAction::Analyze { table_name } => ("ANALYZE", Some(table_name)),
+    _ => ("UNKNOWN", None),
+  };
+
+  Blocked {
+    action,
+    target: target.map(str::to_string),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Opens an in-memory database with a Bear table and the authorizer installed
+  fn guarded_connection() -> (Connection, Authorizer) {
+    let conn = Connection::open_in_memory().unwrap();
+    conn
+      .execute_batch(
+        "CREATE TABLE ZSFNOTE (Z_PK INTEGER PRIMARY KEY, ZTITLE TEXT); CREATE TABLE other (x);",
+      )
+      .unwrap();
+    let authorizer = Authorizer::install(&conn);
+    (conn, authorizer)
+  }
+
+  /// Prepares SQL and returns what blocked it, if anything
+  fn blocked(sql: &str) -> Option<(String, Option<String>)> {
+    let (conn, authorizer) = guarded_connection();
+    let error = conn.prepare(sql).err()?;
+    match authorizer.explain(error.into()) {
+      BearError::ForbiddenStatement { action, target } => Some((action, target)),
+      error => panic!("Expected ForbiddenStatement, got: {:?}", error),
+    }
+  }
+
+  fn forbidden(
+    action: &str,
+    target: Option<&str>,
+  ) -> Option<(String, Option<String>)> {
+    Some((action.to_string(), target.map(str::to_string)))
+  }
+
+  #[test]
+  fn test_allows_reads_of_bear_tables() {
+    assert_eq!(
+      blocked(
+        "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 3) SELECT ZTITLE, upper(ZTITLE), i FROM ZSFNOTE, n"
+      ),
+      None
+    );
+    assert_eq!(blocked("SELECT name FROM sqlite_master"), None);
+    assert_eq!(blocked("PRAGMA table_info(ZSFNOTE)"), None);
+    assert_eq!(blocked("PRAGMA user_version"), None);
+  }
+
+  #[test]
+  fn test_denies_everything_else() {
+    assert_eq!(
+      blocked("INSERT INTO ZSFNOTE (ZTITLE) VALUES ('x')"),
+      forbidden("INSERT", Some("ZSFNOTE"))
+    );
+    assert_eq!(
+      blocked("UPDATE ZSFNOTE SET ZTITLE = 'x'"),
+      forbidden("UPDATE", Some("ZSFNOTE"))
+    );
+    assert_eq!(
+      blocked("DELETE FROM ZSFNOTE"),
+      forbidden("DELETE", Some("ZSFNOTE"))
+    );
+    assert_eq!(
+      blocked("DROP TABLE ZSFNOTE"),
+      forbidden("SCHEMA CHANGE", None)
+    );
+    assert_eq!(
+      blocked("CREATE TABLE ZNEW (x)"),
+      forbidden("SCHEMA CHANGE", None)
+    );
+    assert_eq!(
+      blocked("SELECT x FROM other"),
+      forbidden("READ", Some("other"))
+    );
+    assert_eq!(
+      blocked("ATTACH DATABASE ':memory:' AS other_db"),
+      forbidden("ATTACH", Some(":memory:"))
+    );
+    assert_eq!(
+      blocked("PRAGMA user_version = 2"),
+      forbidden("PRAGMA", Some("user_version"))
+    );
+    assert_eq!(
+      blocked("SELECT load_extension('evil')"),
+      forbidden("FUNCTION", Some("load_extension"))
+    );
+    assert_eq!(blocked("BEGIN"), forbidden("TRANSACTION", None));
+
+    // Pragmas that write or checkpoint even without a value
+    for pragma in [
+      "optimize",
+      "wal_checkpoint",
+      "incremental_vacuum",
+      "shrink_memory",
+    ] {
+      assert_eq!(
+        blocked(&format!("PRAGMA {pragma}")),
+        forbidden("PRAGMA", Some(pragma))
+      );
+    }
+  }
+
+  #[test]
+  fn test_multiple_statements() {
+    assert_eq!(
+      blocked("SELECT 1; SELECT 2"),
+      forbidden("MULTIPLE STATEMENTS", None)
+    );
+    assert_eq!(blocked("SELECT 1; -- trailing comment"), None);
+
+    // A second statement that is forbidden on its own is reported as itself
+    assert_eq!(
+      blocked("SELECT 1; DELETE FROM ZSFNOTE"),
+      forbidden("DELETE", Some("ZSFNOTE"))
+    );
+  }
+}
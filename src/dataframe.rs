@@ -2,11 +2,183 @@
 ///
 /// This module handles converting rusqlite query results into Polars DataFrames.
 /// It uses column-wise construction for optimal performance.
-use crate::{BearError, Queryable};
+use crate::{BearError, QueryLimits, Queryable, datetime, timezone};
 use polars::prelude::*;
 
+use rusqlite::Params;
 use rusqlite::types::ValueRef;
 
+/// Normalized columns holding timestamps, converted to `Datetime(Microseconds, <zone>)`
+const DATETIME_COLUMNS: &[&str] = &["modified", "created"];
+
+/// Normalized columns holding 0/1 flags, converted to `Boolean`
+const BOOLEAN_COLUMNS: &[&str] = &["is_pinned", "is_trashed", "is_archived"];
+
+/// Options for converting query results into a DataFrame.
+///
+/// By default the normalized timestamp columns (`modified`, `created`) become
+/// `Datetime(Microseconds, <zone>)`, in the zone of
+/// [`BearDb::with_time_zone()`](crate::BearDb::with_time_zone) (UTC unless set), and
+/// the flag columns (`is_pinned`, `is_trashed`,
+/// `is_archived`) become `Boolean`, matched by their name in the result. Columns
+/// whose values don't fit keep the type inferred from SQLite.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use bear_query::{BearDb, QueryOptions};
+/// # use bear_query::polars_prelude::{DataType, Schema};
+/// # fn main() -> Result<(), bear_query::BearError> {
+/// let db = BearDb::new()?;
+///
+/// // Computed timestamps aren't recognized by name, so ask for them
+/// let df = db.query_with_options(
+///   "SELECT title, max(modified, created) AS touched FROM notes",
+///   QueryOptions::new().parse_dates(["touched"]),
+/// )?;
+///
+/// // Or give the exact types of some columns
+/// let schema = Schema::from_iter([("words".into(), DataType::UInt32)]);
+/// let df = db.query_with_options(
+///   "SELECT title, word_count(content) AS words FROM notes",
+///   QueryOptions::new().schema(schema),
+/// )?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct QueryOptions {
+  parse_dates: Vec<String>,
+  schema: Option<Schema>,
+  mixed_types: MixedTypes,
+  limits: Option<QueryLimits>,
+  time_zone: Option<timezone::TimeZone>,
+}
+
+/// How to build a column whose values have different SQLite types.
+///
+/// SQLite columns aren't typed, so an expression like
+/// `CASE WHEN ... THEN title ELSE word_count(content) END` can return text in one
+/// row and an integer in the next. INTEGER and REAL values together are not mixed
+/// as long as every integer fits an f64 exactly (up to 2^53 in magnitude): they
+/// become a Float64 column. Larger integers next to REAL values make a mixed column,
+/// so they don't silently change value.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use bear_query::{BearDb, MixedTypes, QueryOptions};
+/// # fn main() -> Result<(), bear_query::BearError> {
+/// let db = BearDb::new()?;
+///
+/// let df = db.query_with_options(
+///   "SELECT coalesce(title, core_db_id) AS label FROM notes",
+///   QueryOptions::new().mixed_types(MixedTypes::Error),
+/// )?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MixedTypes {
+  /// Fail with `BearError::MixedColumnTypes`
+  Error,
+  /// Build a String column: numbers are written out in full and BLOBs as lowercase
+  /// hex, so no value is lost (default)
+  #[default]
+  CoerceToString,
+  /// Build a Struct column with one field per SQLite type present (`integer`,
+  /// `real`, `text`, `blob`); each row sets the field of its value's type
+  Struct,
+}
+
+impl QueryOptions {
+  /// Creates options with the default type conversions.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Parses these columns as `Datetime(Microseconds, <zone>)`, like `modified`.
+  ///
+  /// Accepts SQLite's `datetime()` and `date()` text, RFC 3339 text and Unix
+  /// timestamps in seconds. A value that can't be parsed is an error.
+  pub fn parse_dates<I, S>(
+    mut self,
+    columns: I,
+  ) -> Self
+  where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+  {
+    self.parse_dates.extend(columns.into_iter().map(Into::into));
+    self
+  }
+
+  /// Gives explicit types for some columns, taking precedence over `parse_dates()`
+  /// and the defaults.
+  ///
+  /// `Datetime` columns are parsed like `parse_dates()`, in the schema's time unit
+  /// and time zone; other types are a strict cast of the inferred column. Schema
+  /// fields that aren't in the result are ignored.
+  pub fn schema(
+    mut self,
+    schema: Schema,
+  ) -> Self {
+    self.schema = Some(schema);
+    self
+  }
+
+  /// Sets how columns mixing SQLite types are built (default
+  /// `MixedTypes::CoerceToString`).
+  pub fn mixed_types(
+    mut self,
+    strategy: MixedTypes,
+  ) -> Self {
+    self.mixed_types = strategy;
+    self
+  }
+
+  /// Sets the time limits of this query, instead of those of the `BearDb`.
+  pub fn limits(
+    mut self,
+    limits: QueryLimits,
+  ) -> Self {
+    self.limits = Some(limits);
+    self
+  }
+
+  /// The time limits of this query, if set
+  pub(crate) fn query_limits(&self) -> Option<&QueryLimits> {
+    self.limits.as_ref()
+  }
+
+  /// Sets the time zone of this query's datetime columns, instead of that of the
+  /// `BearDb`. The values are the same instants, displayed in another zone.
+  pub fn time_zone(
+    mut self,
+    time_zone: impl Into<timezone::TimeZone>,
+  ) -> Self {
+    self.time_zone = Some(time_zone.into());
+    self
+  }
+
+  /// Uses `time_zone` for datetime columns unless a zone is already set
+  pub(crate) fn or_time_zone(
+    mut self,
+    time_zone: timezone::TimeZone,
+  ) -> Self {
+    self.time_zone.get_or_insert(time_zone);
+    self
+  }
+
+  /// The Polars time zone of datetime columns
+  fn polars_time_zone(&self) -> PolarsResult<Option<TimeZone>> {
+    match self.time_zone.unwrap_or_default() {
+      timezone::TimeZone::Utc => Ok(Some(TimeZone::UTC)),
+      zone => TimeZone::opt_try_new(Some(zone.name())),
+    }
+  }
+}
+
 /// Represents a value that can be stored in a column during DataFrame construction
 #[derive(Debug, Clone)]
 enum ColumnValue {
@@ -17,6 +189,19 @@ enum ColumnValue {
   Blob(Vec<u8>),
 }
 
+impl ColumnValue {
+  /// The SQLite storage class of the value
+  fn type_name(&self) -> &'static str {
+    match self {
+      ColumnValue::Null => "NULL",
+      ColumnValue::Integer(_) => "INTEGER",
+      ColumnValue::Real(_) => "REAL",
+      ColumnValue::Text(_) => "TEXT",
+      ColumnValue::Blob(_) => "BLOB",
+    }
+  }
+}
+
 impl From<ValueRef<'_>> for ColumnValue {
   fn from(value: ValueRef) -> Self {
     match value {
@@ -38,16 +223,72 @@ impl From<ValueRef<'_>> for ColumnValue {
 /// - TEXT -> String
 /// - BLOB -> Binary
 ///
+/// Columns mixing TEXT, BLOB and numbers are built according to
+/// [`QueryOptions::mixed_types()`]. Then applies the type conversions of `options`
+/// (see [`QueryOptions`]).
+///
 /// # Arguments
 /// * `queryable` - The Queryable wrapper that prepends normalizing CTEs
 /// * `sql` - The user's SQL query (will have CTEs prepended automatically)
+/// * `params` - Values bound to the query's `?1`/`:name` parameters
+/// * `options` - Column type conversions
 ///
 /// # Returns
 /// A Polars DataFrame containing the query results, or a BearError on failure
 pub fn query_to_dataframe(
   queryable: &Queryable,
   sql: &str,
+  params: impl Params,
+  options: &QueryOptions,
 ) -> Result<DataFrame, BearError> {
+  let mut dataframe = None;
+  for_each_batch(queryable, sql, params, None, options, |batch| {
+    dataframe = Some(batch);
+    Ok(())
+  })?;
+  Ok(dataframe.unwrap_or_else(DataFrame::empty))
+}
+
+/// Converts rusqlite query results into Polars DataFrames of at most `batch_size`
+/// rows, passing each to `f` as soon as it is full.
+///
+/// Columns are built like in [`query_to_dataframe()`], from the rows of each batch.
+/// A query returning no rows produces no batch.
+///
+/// # Arguments
+/// * `queryable` - The Queryable wrapper that prepends normalizing CTEs
+/// * `sql` - The user's SQL query (will have CTEs prepended automatically)
+/// * `params` - Values bound to the query's `?1`/`:name` parameters
+/// * `batch_size` - The number of rows per DataFrame (at least 1)
+/// * `options` - Column type conversions
+/// * `f` - Called with each batch; an error stops the query
+pub fn query_to_dataframes<F>(
+  queryable: &Queryable,
+  sql: &str,
+  params: impl Params,
+  batch_size: usize,
+  options: &QueryOptions,
+  f: F,
+) -> Result<(), BearError>
+where
+  F: FnMut(DataFrame) -> Result<(), BearError>,
+{
+  for_each_batch(queryable, sql, params, Some(batch_size.max(1)), options, f)
+}
+
+/// Collects rows column-wise (much faster than row-wise construction), passing a
+/// DataFrame to `f` every `batch_size` rows, or once with every row without one
+fn for_each_batch<F>(
+  queryable: &Queryable,
+  sql: &str,
+  params: impl Params,
+  batch_size: Option<usize>,
+  options: &QueryOptions,
+  mut f: F,
+) -> Result<(), BearError>
+where
+  F: FnMut(DataFrame) -> Result<(), BearError>,
+{
   let mut stmt = queryable.prepare(sql)?;
 
   // Get column names
@@ -58,50 +299,170 @@ pub fn query_to_dataframe(
     .collect();
   let column_count = column_names.len();
 
-  // Prepare vectors for each column (column-wise construction is much faster)
+  // Prepare vectors for each column
   let mut columns: Vec<Vec<ColumnValue>> = vec![Vec::new(); column_count];
+  let mut batch_rows = 0;
+  let mut budget = queryable.result_budget();
 
   // Collect data row by row, distributing into columns
-  let rows = stmt
-    .query_map([], |row| {
-      let mut values = Vec::new();
-      for i in 0..column_count {
-        // Get the actual value with its type preserved
-        let value = row
-          .get_ref(i)
-          .map(ColumnValue::from)
-          .unwrap_or(ColumnValue::Null);
-        values.push(value);
-      }
-      Ok(values)
-    })
-    .map_err(|e| BearError::SqlError { source: e })?;
+  let mut rows = stmt.query(params)?;
+  while let Some(row) = rows.next()? {
+    budget.add_row(row, column_count)?;
+    for (i, column) in columns.iter_mut().enumerate() {
+      // Get the actual value with its type preserved
+      let value = row
+        .get_ref(i)
+        .map(ColumnValue::from)
+        .unwrap_or(ColumnValue::Null);
+      column.push(value);
+    }
 
-  for row_result in rows {
-    let row_values = row_result.map_err(|e| BearError::SqlError { source: e })?;
-    for (col_idx, value) in row_values.into_iter().enumerate() {
-      columns[col_idx].push(value);
+    batch_rows += 1;
+    if batch_size == Some(batch_rows) {
+      let batch = std::mem::replace(&mut columns, vec![Vec::new(); column_count]);
+      f(build_dataframe(&column_names, batch, options)?)?;
+      batch_rows = 0;
     }
   }
 
-  // Build Series from column vectors based on their types
-  let series: Vec<_> = column_names
-    .into_iter()
+  if batch_rows > 0 || batch_size.is_none() {
+    f(build_dataframe(&column_names, columns, options)?)?;
+  }
+  Ok(())
+}
+
+/// Builds Series from column vectors based on their types
+fn build_dataframe(
+  column_names: &[String],
+  columns: Vec<Vec<ColumnValue>>,
+  options: &QueryOptions,
+) -> Result<DataFrame, BearError> {
+  let series = column_names
+    .iter()
     .zip(columns)
-    .map(|(name, data)| {
-      // Infer the column type from the data
-      build_series(name, data)
-    })
-    .collect();
+    .map(|(name, data)| build_column(name.clone(), data, options))
+    .collect::<Result<Vec<_>, _>>()?;
 
   Ok(DataFrame::new(series)?)
 }
 
+/// Builds a column with the type requested by `options`, or the default for its name
+fn build_column(
+  name: String,
+  values: Vec<ColumnValue>,
+  options: &QueryOptions,
+) -> Result<Column, BearError> {
+  if let Some(dtype) = options.schema.as_ref().and_then(|schema| schema.get(&name)) {
+    return Ok(match dtype {
+      DataType::Datetime(unit, tz) => datetime_column(name, &values, *unit, tz.clone())?,
+      dtype => build_series(name, values, options.mixed_types)?.strict_cast(dtype)?,
+    });
+  }
+
+  if options.parse_dates.contains(&name) {
+    return Ok(datetime_column(
+      name,
+      &values,
+      TimeUnit::Microseconds,
+      options.polars_time_zone()?,
+    )?);
+  }
+
+  if DATETIME_COLUMNS.contains(&name.as_str())
+    && let Ok(column) = datetime_column(
+      name.clone(),
+      &values,
+      TimeUnit::Microseconds,
+      options.polars_time_zone()?,
+    )
+  {
+    return Ok(column);
+  }
+
+  if BOOLEAN_COLUMNS.contains(&name.as_str())
+    && let Some(column) = boolean_column(&name, &values)
+  {
+    return Ok(column);
+  }
+
+  // Infer the column type from the data
+  build_series(name, values, options.mixed_types)
+}
+
+/// Builds a Datetime column, failing on the first value that isn't a timestamp
+fn datetime_column(
+  name: String,
+  values: &[ColumnValue],
+  unit: TimeUnit,
+  tz: Option<TimeZone>,
+) -> PolarsResult<Column> {
+  let data = values
+    .iter()
+    .map(|value| {
+      let Some(nanos) = parse_timestamp(value) else {
+        return match value {
+          ColumnValue::Null => Ok(None),
+          value => Err(polars_err!(
+            ComputeError: "column '{}': cannot parse {:?} as a datetime", name, value
+          )),
+        };
+      };
+      let ticks = match unit {
+        TimeUnit::Nanoseconds => nanos,
+        TimeUnit::Microseconds => nanos.div_euclid(1_000),
+        TimeUnit::Milliseconds => nanos.div_euclid(1_000_000),
+      };
+      i64::try_from(ticks)
+        .map(Some)
+        .map_err(|_| polars_err!(ComputeError: "column '{}': datetime out of range", name))
+    })
+    .collect::<PolarsResult<Vec<Option<i64>>>>()?;
+
+  Ok(
+    Int64Chunked::new(name.into(), data)
+      .into_datetime(unit, tz)
+      .into_series()
+      .into(),
+  )
+}
+
+/// Returns a value's timestamp in nanoseconds since the Unix epoch
+fn parse_timestamp(value: &ColumnValue) -> Option<i128> {
+  match value {
+    ColumnValue::Text(text) => datetime::parse_text(text).map(|dt| dt.unix_timestamp_nanos()),
+    ColumnValue::Integer(seconds) => Some(i128::from(*seconds) * 1_000_000_000),
+    ColumnValue::Real(seconds) if seconds.is_finite() => Some((seconds * 1e9) as i128),
+    _ => None,
+  }
+}
+
+/// Builds a Boolean column if every value is 0, 1 or NULL
+fn boolean_column(
+  name: &str,
+  values: &[ColumnValue],
+) -> Option<Column> {
+  let data = values
+    .iter()
+    .map(|value| match value {
+      ColumnValue::Integer(0) => Some(Some(false)),
+      ColumnValue::Integer(1) => Some(Some(true)),
+      ColumnValue::Null => Some(None),
+      _ => None,
+    })
+    .collect::<Option<Vec<Option<bool>>>>()?;
+
+  Some(Series::new(name.into(), data).into())
+}
+
+/// The largest magnitude below which every integer is exactly representable as an f64
+const MAX_EXACT_F64_INTEGER: u64 = 1 << 53;
+
 /// Builds a Polars Series from a column of values, inferring the appropriate type
 fn build_series(
   name: String,
   values: Vec<ColumnValue>,
-) -> Column {
+  mixed_types: MixedTypes,
+) -> Result<Column, BearError> {
   // Determine the predominant type (ignoring nulls)
   let mut has_integer = false;
   let mut has_real = false;
@@ -118,6 +479,21 @@ fn build_series(
     }
   }
 
+  // Integers only join REAL values in a Float64 column if none of them changes value
+  let exact_as_f64 = values.iter().all(|value| match value {
+    ColumnValue::Integer(i) => i.unsigned_abs() <= MAX_EXACT_F64_INTEGER,
+    _ => true,
+  });
+  let numeric = if exact_as_f64 {
+    [has_integer || has_real, false]
+  } else {
+    [has_integer, has_real]
+  };
+  let kinds = numeric.into_iter().chain([has_text, has_blob]);
+  if kinds.filter(|&present| present).count() > 1 {
+    return mixed_column(name, values, mixed_types);
+  }
+
   // Priority: Real > Integer > Text > Blob
   if has_real {
     // Float64 column
@@ -127,10 +503,10 @@ fn build_series(
         ColumnValue::Real(f) => Some(f),
         ColumnValue::Integer(i) => Some(i as f64), // Promote integer to float
         ColumnValue::Null => None,
-        _ => None, // Mixed types, treat as null
+        _ => None,
       })
       .collect();
-    Series::new(name.into(), data).into()
+    Ok(Series::new(name.into(), data).into())
   } else if has_integer {
     // Int64 column
     let data: Vec<Option<i64>> = values
@@ -138,10 +514,10 @@ fn build_series(
       .map(|v| match v {
         ColumnValue::Integer(i) => Some(i),
         ColumnValue::Null => None,
-        _ => None, // Mixed types, treat as null
+        _ => None,
       })
       .collect();
-    Series::new(name.into(), data).into()
+    Ok(Series::new(name.into(), data).into())
   } else if has_text {
     // String column
     let data: Vec<Option<String>> = values
@@ -152,7 +528,7 @@ fn build_series(
         _ => None,
       })
       .collect();
-    Series::new(name.into(), data).into()
+    Ok(Series::new(name.into(), data).into())
   } else if has_blob {
     // Binary column
     let data: Vec<Option<Vec<u8>>> = values
@@ -163,11 +539,97 @@ fn build_series(
         _ => None,
       })
       .collect();
-    Series::new(name.into(), data).into()
+    Ok(Series::new(name.into(), data).into())
   } else {
     // All nulls - create a null string column
     let data: Vec<Option<String>> = values.into_iter().map(|_| None).collect();
-    Series::new(name.into(), data).into()
+    Ok(Series::new(name.into(), data).into())
+  }
+}
+
+/// Builds a column from values of different SQLite types
+fn mixed_column(
+  name: String,
+  values: Vec<ColumnValue>,
+  strategy: MixedTypes,
+) -> Result<Column, BearError> {
+  match strategy {
+    MixedTypes::Error => {
+      let mut types: Vec<String> = Vec::new();
+      for value in &values {
+        let type_name = value.type_name().to_string();
+        if !matches!(value, ColumnValue::Null) && !types.contains(&type_name) {
+          types.push(type_name);
+        }
+      }
+      Err(BearError::MixedColumnTypes {
+        column: name,
+        types,
+      })
+    }
+    MixedTypes::CoerceToString => {
+      let data: Vec<Option<String>> = values
+        .into_iter()
+        .map(|v| match v {
+          ColumnValue::Null => None,
+          ColumnValue::Integer(i) => Some(i.to_string()),
+          // Debug formatting is the shortest text that parses back to the same f64
+          ColumnValue::Real(f) => Some(format!("{:?}", f)),
+          ColumnValue::Text(s) => Some(s),
+          ColumnValue::Blob(b) => Some(b.iter().map(|byte| format!("{:02x}", byte)).collect()),
+        })
+        .collect();
+      Ok(Series::new(name.into(), data).into())
+    }
+    MixedTypes::Struct => {
+      let len = values.len();
+      let field = |type_name: &str| values.iter().any(|v| v.type_name() == type_name);
+
+      let mut fields = Vec::new();
+      if field("INTEGER") {
+        let data: Vec<Option<i64>> = values
+          .iter()
+          .map(|v| match v {
+            ColumnValue::Integer(i) => Some(*i),
+            _ => None,
+          })
+          .collect();
+        fields.push(Series::new("integer".into(), data));
+      }
+      if field("REAL") {
+        let data: Vec<Option<f64>> = values
+          .iter()
+          .map(|v| match v {
+            ColumnValue::Real(f) => Some(*f),
+            _ => None,
+          })
+          .collect();
+        fields.push(Series::new("real".into(), data));
+      }
+      if field("TEXT") {
+        let data: Vec<Option<&str>> = values
+          .iter()
+          .map(|v| match v {
+            ColumnValue::Text(s) => Some(s.as_str()),
+            _ => None,
+          })
+          .collect();
+        fields.push(Series::new("text".into(), data));
+      }
+      if field("BLOB") {
+        let data: Vec<Option<&[u8]>> = values
+          .iter()
+          .map(|v| match v {
+            ColumnValue::Blob(b) => Some(b.as_slice()),
+            _ => None,
+          })
+          .collect();
+        fields.push(Series::new("blob".into(), data));
+      }
+
+      let column = StructChunked::from_series(name.into(), len, fields.iter())?;
+      Ok(column.into_series().into())
+    }
   }
 }
 
@@ -293,7 +755,13 @@ WITH
     let conn = create_test_db();
     let queryable = create_test_queryable(&conn);
 
-    let df = query_to_dataframe(&queryable, "SELECT id, title FROM notes").unwrap();
+    let df = query_to_dataframe(
+      &queryable,
+      "SELECT id, title FROM notes",
+      [],
+      &QueryOptions::default(),
+    )
+    .unwrap();
 
     assert_eq!(df.height(), 3); // 3 notes total
     assert_eq!(df.width(), 2); // 2 columns (id, title)
@@ -309,8 +777,13 @@ WITH
     let conn = create_test_db();
     let queryable = create_test_queryable(&conn);
 
-    let df =
-      query_to_dataframe(&queryable, "SELECT title FROM notes WHERE is_trashed = 0").unwrap();
+    let df = query_to_dataframe(
+      &queryable,
+      "SELECT title FROM notes WHERE is_trashed = 0",
+      [],
+      &QueryOptions::default(),
+    )
+    .unwrap();
 
     assert_eq!(df.height(), 2); // Only 2 non-trashed notes
   }
@@ -328,6 +801,8 @@ WITH
       JOIN note_tags nt ON n.id = nt.note_id
       JOIN tags t ON nt.tag_id = t.id
     ",
+      [],
+      &QueryOptions::default(),
     )
     .unwrap();
 
@@ -340,7 +815,13 @@ WITH
     let conn = create_test_db();
     let queryable = create_test_queryable(&conn);
 
-    let df = query_to_dataframe(&queryable, "SELECT * FROM notes WHERE id = 999").unwrap();
+    let df = query_to_dataframe(
+      &queryable,
+      "SELECT * FROM notes WHERE id = 999",
+      [],
+      &QueryOptions::default(),
+    )
+    .unwrap();
 
     assert_eq!(df.height(), 0); // No results
     assert_eq!(df.width(), 9); // But still has all columns from notes
@@ -351,7 +832,13 @@ WITH
     let conn = create_test_db();
     let queryable = create_test_queryable(&conn);
 
-    let df = query_to_dataframe(&queryable, "SELECT COUNT(*) as count FROM notes").unwrap();
+    let df = query_to_dataframe(
+      &queryable,
+      "SELECT COUNT(*) as count FROM notes",
+      [],
+      &QueryOptions::default(),
+    )
+    .unwrap();
 
     assert_eq!(df.height(), 1);
     assert_eq!(df.width(), 1);
@@ -362,34 +849,437 @@ WITH
     let conn = create_test_db();
     let queryable = create_test_queryable(&conn);
 
-    let df = query_to_dataframe(&queryable, "SELECT modified FROM notes WHERE id = 1").unwrap();
+    let df = query_to_dataframe(
+      &queryable,
+      "SELECT modified FROM notes WHERE id = 2",
+      [],
+      &QueryOptions::default(),
+    )
+    .unwrap();
 
     assert_eq!(df.height(), 1);
 
-    // Check that timestamp was converted (should be "2001-01-01 00:00:00")
+    // Core Data 31536000 is 2002-01-01, stored as microseconds since the Unix epoch
     let series = df.column("modified").unwrap();
-    let value = series.get(0).unwrap();
+    assert_eq!(
+      series.dtype(),
+      &DataType::Datetime(TimeUnit::Microseconds, Some(TimeZone::UTC))
+    );
+    let micros = series
+      .as_materialized_series()
+      .to_physical_repr()
+      .i64()
+      .unwrap()
+      .get(0);
+    assert_eq!(micros, Some(1_009_843_200_000_000));
+  }
 
-    // The value should be a string starting with "2001-01-01"
-    match value {
-      AnyValue::String(s) => {
-        assert!(
-          s.starts_with("2001-01-01"),
-          "Expected timestamp to start with 2001-01-01, got: {}",
-          s
-        );
-      }
-      AnyValue::StringOwned(s) => {
-        assert!(
-          s.as_str().starts_with("2001-01-01"),
-          "Expected timestamp to start with 2001-01-01, got: {}",
-          s
-        );
+  #[test]
+  fn test_time_zone() {
+    let conn = create_test_db();
+    let queryable = create_test_queryable(&conn);
+
+    let options = QueryOptions::new().time_zone(timezone::TimeZone::named("Asia/Tokyo").unwrap());
+    let df = query_to_dataframe(
+      &queryable,
+      "SELECT modified FROM notes WHERE id = 2",
+      [],
+      &options,
+    )
+    .unwrap();
+
+    // The same instant, displayed in Tokyo
+    let series = df.column("modified").unwrap();
+    assert_eq!(
+      series.dtype(),
+      &DataType::Datetime(
+        TimeUnit::Microseconds,
+        TimeZone::opt_try_new(Some("Asia/Tokyo")).unwrap()
+      )
+    );
+    assert_eq!(
+      series
+        .as_materialized_series()
+        .to_physical_repr()
+        .i64()
+        .unwrap()
+        .get(0),
+      Some(1_009_843_200_000_000)
+    );
+    assert!(
+      format!("{}", df).contains("2002-01-01 09:00:00 JST"),
+      "{}",
+      df
+    );
+
+    // An offset set on the options wins over the handle's zone
+    let options = QueryOptions::new()
+      .time_zone(time::UtcOffset::from_hms(-8, 0, 0).unwrap())
+      .or_time_zone(timezone::TimeZone::Utc);
+    let df = query_to_dataframe(
+      &queryable,
+      "SELECT modified FROM notes WHERE id = 2",
+      [],
+      &options,
+    )
+    .unwrap();
+    assert!(format!("{}", df).contains("2001-12-31 16:00:00"), "{}", df);
+  }
+
+  #[test]
+  fn test_flag_columns_are_boolean() {
+    let conn = create_test_db();
+    let queryable = create_test_queryable(&conn);
+
+    let df = query_to_dataframe(
+      &queryable,
+      "SELECT is_pinned, is_trashed, is_pinned + 1 AS not_a_flag FROM notes ORDER BY id",
+      [],
+      &QueryOptions::default(),
+    )
+    .unwrap();
+
+    let pinned = df.column("is_pinned").unwrap().bool().unwrap();
+    assert_eq!(
+      pinned.into_iter().collect::<Vec<_>>(),
+      vec![Some(false), Some(true), Some(false)]
+    );
+    assert_eq!(df.column("is_trashed").unwrap().dtype(), &DataType::Boolean);
+    assert_eq!(df.column("not_a_flag").unwrap().dtype(), &DataType::Int64);
+  }
+
+  #[test]
+  fn test_known_column_that_is_not_a_date_keeps_its_type() {
+    let conn = create_test_db();
+    let queryable = create_test_queryable(&conn);
+
+    let df = query_to_dataframe(
+      &queryable,
+      "SELECT title AS modified, 2 AS is_pinned FROM notes",
+      [],
+      &QueryOptions::default(),
+    )
+    .unwrap();
+
+    assert_eq!(df.column("modified").unwrap().dtype(), &DataType::String);
+    assert_eq!(df.column("is_pinned").unwrap().dtype(), &DataType::Int64);
+  }
+
+  #[test]
+  fn test_parse_dates() {
+    let conn = create_test_db();
+    let queryable = create_test_queryable(&conn);
+
+    let options = QueryOptions::new().parse_dates(["latest", "day", "epoch"]);
+    let df = query_to_dataframe(
+      &queryable,
+      r"
+      SELECT
+        max(modified) AS latest,
+        date(max(modified)) AS day,
+        unixepoch(max(modified)) AS epoch,
+        max(modified) AS untouched
+      FROM notes
+    ",
+      [],
+      &options,
+    )
+    .unwrap();
+
+    let utc = DataType::Datetime(TimeUnit::Microseconds, Some(TimeZone::UTC));
+    for name in ["latest", "day", "epoch"] {
+      let column = df.column(name).unwrap();
+      assert_eq!(column.dtype(), &utc, "{}", name);
+      assert_eq!(
+        column
+          .as_materialized_series()
+          .to_physical_repr()
+          .i64()
+          .unwrap()
+          .get(0),
+        Some(1_009_843_200_000_000),
+        "{}",
+        name
+      );
+    }
+    assert_eq!(df.column("untouched").unwrap().dtype(), &DataType::String);
+
+    let error = query_to_dataframe(
+      &queryable,
+      "SELECT title FROM notes",
+      [],
+      &QueryOptions::new().parse_dates(["title"]),
+    )
+    .unwrap_err();
+    assert!(error.to_string().contains("cannot parse"), "{}", error);
+  }
+
+  #[test]
+  fn test_schema_override() {
+    let conn = create_test_db();
+    let queryable = create_test_queryable(&conn);
+
+    let schema = Schema::from_iter([
+      (
+        "modified".into(),
+        DataType::Datetime(TimeUnit::Milliseconds, None),
+      ),
+      ("is_pinned".into(), DataType::Int32),
+      ("count".into(), DataType::UInt32),
+      ("missing".into(), DataType::Boolean),
+    ]);
+    let df = query_to_dataframe(
+      &queryable,
+      "SELECT modified, is_pinned, COUNT(*) OVER () AS count FROM notes WHERE id = 2",
+      [],
+      &QueryOptions::new().schema(schema),
+    )
+    .unwrap();
+
+    let modified = df.column("modified").unwrap();
+    assert_eq!(
+      modified.dtype(),
+      &DataType::Datetime(TimeUnit::Milliseconds, None)
+    );
+    assert_eq!(
+      modified
+        .as_materialized_series()
+        .to_physical_repr()
+        .i64()
+        .unwrap()
+        .get(0),
+      Some(1_009_843_200_000)
+    );
+    assert_eq!(df.column("is_pinned").unwrap().dtype(), &DataType::Int32);
+    assert_eq!(df.column("count").unwrap().dtype(), &DataType::UInt32);
+    assert_eq!(df.width(), 3);
+  }
+
+  /// One row per SQLite type, plus a NULL
+  const MIXED_SQL: &str = r"
+    SELECT value FROM (
+      SELECT 1 AS n, 42 AS value
+      UNION ALL SELECT 2, 1.5
+      UNION ALL SELECT 3, 'text'
+      UNION ALL SELECT 4, x'beef'
+      UNION ALL SELECT 5, NULL
+    ) ORDER BY n
+  ";
+
+  #[test]
+  fn test_mixed_types_coerce_to_string_by_default() {
+    let conn = create_test_db();
+    let queryable = create_test_queryable(&conn);
+
+    let df = query_to_dataframe(&queryable, MIXED_SQL, [], &QueryOptions::default()).unwrap();
+
+    let values = df.column("value").unwrap().str().unwrap();
+    assert_eq!(
+      values.into_iter().collect::<Vec<_>>(),
+      vec![Some("42"), Some("1.5"), Some("text"), Some("beef"), None]
+    );
+  }
+
+  #[test]
+  fn test_mixed_types_error() {
+    let conn = create_test_db();
+    let queryable = create_test_queryable(&conn);
+
+    let error = query_to_dataframe(
+      &queryable,
+      MIXED_SQL,
+      [],
+      &QueryOptions::new().mixed_types(MixedTypes::Error),
+    )
+    .unwrap_err();
+
+    match error {
+      BearError::MixedColumnTypes { column, types } => {
+        assert_eq!(column, "value");
+        assert_eq!(types, vec!["INTEGER", "REAL", "TEXT", "BLOB"]);
       }
-      _ => panic!("Expected string value, got: {:?}", value),
+      error => panic!("Expected MixedColumnTypes, got: {:?}", error),
     }
   }
 
+  #[test]
+  fn test_mixed_types_struct() {
+    let conn = create_test_db();
+    let queryable = create_test_queryable(&conn);
+
+    let df = query_to_dataframe(
+      &queryable,
+      MIXED_SQL,
+      [],
+      &QueryOptions::new().mixed_types(MixedTypes::Struct),
+    )
+    .unwrap();
+
+    let fields = df
+      .column("value")
+      .unwrap()
+      .struct_()
+      .unwrap()
+      .fields_as_series();
+    let names: Vec<&str> = fields.iter().map(|field| field.name().as_str()).collect();
+    assert_eq!(names, vec!["integer", "real", "text", "blob"]);
+    assert_eq!(
+      fields[0].i64().unwrap().into_iter().collect::<Vec<_>>(),
+      vec![Some(42), None, None, None, None]
+    );
+    assert_eq!(fields[1].f64().unwrap().get(1), Some(1.5));
+    assert_eq!(fields[2].str().unwrap().get(2), Some("text"));
+    assert_eq!(
+      fields[3].binary().unwrap().get(3),
+      Some([0xbe, 0xef].as_slice())
+    );
+  }
+
+  #[test]
+  fn test_integers_and_reals_are_not_mixed() {
+    let conn = create_test_db();
+    let queryable = create_test_queryable(&conn);
+
+    let df = query_to_dataframe(
+      &queryable,
+      "SELECT 1 AS value UNION ALL SELECT 2.5",
+      [],
+      &QueryOptions::new().mixed_types(MixedTypes::Error),
+    )
+    .unwrap();
+
+    assert_eq!(df.column("value").unwrap().dtype(), &DataType::Float64);
+
+    // 2^53 + 1 has no f64 of its own, so it isn't promoted
+    let sql = "SELECT 9007199254740993 AS value UNION ALL SELECT 2.5";
+    let error = query_to_dataframe(
+      &queryable,
+      sql,
+      [],
+      &QueryOptions::new().mixed_types(MixedTypes::Error),
+    )
+    .unwrap_err();
+    assert!(
+      matches!(&error, BearError::MixedColumnTypes { types, .. } if types == &["INTEGER", "REAL"]),
+      "{:?}",
+      error
+    );
+
+    let df = query_to_dataframe(&queryable, sql, [], &QueryOptions::default()).unwrap();
+    assert_eq!(
+      df.column("value")
+        .unwrap()
+        .str()
+        .unwrap()
+        .into_iter()
+        .collect::<Vec<_>>(),
+      vec![Some("9007199254740993"), Some("2.5")]
+    );
+  }
+
+  #[test]
+  fn test_query_params() {
+    let conn = create_test_db();
+    let queryable = create_test_queryable(&conn);
+
+    let df = query_to_dataframe(
+      &queryable,
+      "SELECT title FROM notes WHERE is_pinned = ?1 AND title LIKE ?2",
+      rusqlite::params![0, "First%"],
+      &QueryOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(df.height(), 1);
+    assert_eq!(
+      df.column("title").unwrap().str().unwrap().get(0),
+      Some("First Note")
+    );
+
+    let df = query_to_dataframe(
+      &queryable,
+      "SELECT id FROM notes WHERE title = :title",
+      rusqlite::named_params! { ":title": "Second Note" },
+      &QueryOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(df.column("id").unwrap().i64().unwrap().get(0), Some(2));
+
+    // Parameter values are never interpreted as SQL
+    let df = query_to_dataframe(
+      &queryable,
+      "SELECT id FROM notes WHERE title = ?1",
+      ["x' OR '1'='1"],
+      &QueryOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(df.height(), 0);
+
+    let error = query_to_dataframe(
+      &queryable,
+      "SELECT id FROM notes WHERE title = ?1",
+      [],
+      &QueryOptions::default(),
+    )
+    .unwrap_err();
+    assert!(matches!(error, BearError::SqlError { .. }), "{:?}", error);
+  }
+
+  #[test]
+  fn test_batches() {
+    let conn = create_test_db();
+    let queryable = create_test_queryable(&conn);
+
+    let mut heights = Vec::new();
+    let mut titles = Vec::new();
+    query_to_dataframes(
+      &queryable,
+      "SELECT title, modified FROM notes ORDER BY id",
+      [],
+      2,
+      &QueryOptions::default(),
+      |df| {
+        heights.push(df.height());
+        assert_eq!(
+          df.column("modified").unwrap().dtype(),
+          &DataType::Datetime(TimeUnit::Microseconds, Some(TimeZone::UTC))
+        );
+        let column = df.column("title").unwrap().str().unwrap().clone();
+        titles.extend(column.into_iter().flatten().map(str::to_string));
+        Ok(())
+      },
+    )
+    .unwrap();
+    assert_eq!(heights, vec![2, 1]);
+    assert_eq!(titles, vec!["First Note", "Second Note", "Trashed Note"]);
+
+    // No rows, no batches
+    let mut batches = 0;
+    query_to_dataframes(
+      &queryable,
+      "SELECT title FROM notes WHERE id > 100",
+      [],
+      2,
+      &QueryOptions::default(),
+      |_| {
+        batches += 1;
+        Ok(())
+      },
+    )
+    .unwrap();
+    assert_eq!(batches, 0);
+
+    // An error from the callback stops the query
+    let result = query_to_dataframes(
+      &queryable,
+      "SELECT title FROM notes",
+      [],
+      1,
+      &QueryOptions::default(),
+      |_| Err(BearError::Cancelled),
+    );
+    assert!(matches!(result, Err(BearError::Cancelled)));
+  }
+
   #[test]
   fn test_null_values() {
     let conn = create_test_db();
@@ -402,7 +1292,13 @@ WITH
     ).unwrap();
 
     let queryable = create_test_queryable(&conn);
-    let df = query_to_dataframe(&queryable, "SELECT id, title FROM notes WHERE id = 4").unwrap();
+    let df = query_to_dataframe(
+      &queryable,
+      "SELECT id, title FROM notes WHERE id = 4",
+      [],
+      &QueryOptions::default(),
+    )
+    .unwrap();
 
     assert_eq!(df.height(), 1);
 
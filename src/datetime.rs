@@ -0,0 +1,272 @@
+//! Serde helpers for the timestamps of the normalized tables.
+//!
+//! The normalized views return timestamps as SQLite `datetime()` text in UTC
+//! (`2024-01-31 09:30:00`), which `OffsetDateTime`'s own `Deserialize` doesn't accept.
+//! Use this module on fields of types read with [`BearDb::query_as()`](crate::BearDb::query_as):
+//!
+//! ```no_run
+//! # use bear_query::BearDb;
+//! use serde::Deserialize;
+//! use time::OffsetDateTime;
+//!
+//! #[derive(Deserialize)]
+//! struct Edit {
+//!   title: String,
+//!   #[serde(with = "bear_query::datetime")]
+//!   modified: OffsetDateTime,
+//!   #[serde(with = "bear_query::datetime::option")]
+//!   tag_modified: Option<OffsetDateTime>,
+//! }
+//! # fn main() -> Result<(), bear_query::BearError> {
+//! # let db = BearDb::new()?;
+//! # let edits: Vec<Edit> = db.query_as("SELECT title, modified, NULL AS tag_modified FROM notes", [])?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Deserializing accepts SQLite `datetime()` and `date()` text, RFC 3339 text and
+//! numbers. Numbers are Core Data timestamps (seconds since 2001-01-01 UTC), like
+//! the `core_data_modified` and `core_data_created` columns, not Unix seconds; select
+//! Unix seconds as `datetime(seconds, 'unixepoch')` instead. Serializing writes
+//! RFC 3339, like [`Note`](crate::Note).
+//!
+//! The normalized timestamps have millisecond precision; [`from_core_data()`] converts
+//! the raw Core Data values with their full precision, and so does deserializing them.
+
+use serde::de::{self, Visitor};
+use serde::{Deserializer, Serializer};
+use std::fmt;
+use std::sync::LazyLock;
+use time::format_description::{OwnedFormatItem, well_known::Rfc3339};
+use time::{Date, OffsetDateTime, PrimitiveDateTime};
+
+/// The format of SQLite's `datetime()`, with the optional fraction `strftime('%f')` adds
+static SQLITE_DATETIME: LazyLock<OwnedFormatItem> = LazyLock::new(|| {
+  time::format_description::parse_owned::<2>(
+    "[year]-[month]-[day] [hour]:[minute]:[second][optional [.[subsecond]]]",
+  )
+  .expect("valid format description")
+});
+
+/// The format of SQLite's `date()`
+static SQLITE_DATE: LazyLock<OwnedFormatItem> = LazyLock::new(|| {
+  time::format_description::parse_owned::<2>("[year]-[month]-[day]")
+    .expect("valid format description")
+});
+
+/// Seconds from the Unix epoch to Core Data's reference date, 2001-01-01 00:00:00 UTC
+pub const CORE_DATA_EPOCH: i64 = 978_307_200;
+
+/// Converts a raw Core Data timestamp (seconds since 2001-01-01 UTC, with a fraction)
+/// into an `OffsetDateTime`, to the microsecond.
+///
+/// Returns `None` for values that aren't finite or are out of range.
+///
+/// ```
+/// use bear_query::datetime::from_core_data;
+///
+/// let modified = from_core_data(31_536_000.25).unwrap();
+/// assert_eq!(modified.year(), 2002);
+/// assert_eq!(modified.millisecond(), 250);
+/// ```
+pub fn from_core_data(seconds: f64) -> Option<OffsetDateTime> {
+  if !seconds.is_finite() {
+    return None;
+  }
+  // Split off the whole seconds first, so the fraction isn't rounded away by the
+  // magnitude of the Unix timestamp. A double this size only holds about seven
+  // fractional digits, so anything past microseconds is noise.
+  let whole = seconds.floor();
+  let nanos = ((seconds - whole) * 1e6).round() as i128 * 1_000;
+  let unix_seconds = (whole as i64).checked_add(CORE_DATA_EPOCH)?;
+  OffsetDateTime::from_unix_timestamp_nanos(i128::from(unix_seconds) * 1_000_000_000 + nanos).ok()
+}
+
+/// Parses SQLite `datetime()` or `date()` text (as UTC) or RFC 3339 text.
+pub(crate) fn parse_text(text: &str) -> Option<OffsetDateTime> {
+  PrimitiveDateTime::parse(text, &SQLITE_DATETIME)
+    .map(PrimitiveDateTime::assume_utc)
+    .or_else(|_| OffsetDateTime::parse(text, &Rfc3339))
+    .or_else(|_| Date::parse(text, &SQLITE_DATE).map(|date| date.midnight().assume_utc()))
+    .ok()
+}
+
+/// Serializes a timestamp as RFC 3339.
+pub fn serialize<S: Serializer>(
+  datetime: &OffsetDateTime,
+  serializer: S,
+) -> Result<S::Ok, S::Error> {
+  time::serde::rfc3339::serialize(datetime, serializer)
+}
+
+/// Deserializes a timestamp from SQLite datetime text, RFC 3339 text or Core Data seconds.
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<OffsetDateTime, D::Error> {
+  deserializer.deserialize_any(DateTimeVisitor)
+}
+
+/// The same helpers for `Option<OffsetDateTime>` fields, mapping NULL to `None`.
+pub mod option {
+  use super::*;
+
+  /// Serializes an optional timestamp as RFC 3339 or null.
+  pub fn serialize<S: Serializer>(
+    datetime: &Option<OffsetDateTime>,
+    serializer: S,
+  ) -> Result<S::Ok, S::Error> {
+    time::serde::rfc3339::option::serialize(datetime, serializer)
+  }
+
+  /// Deserializes an optional timestamp, mapping null to `None`.
+  pub fn deserialize<'de, D: Deserializer<'de>>(
+    deserializer: D
+  ) -> Result<Option<OffsetDateTime>, D::Error> {
+    deserializer.deserialize_option(OptionVisitor)
+  }
+
+  struct OptionVisitor;
+
+  impl<'de> Visitor<'de> for OptionVisitor {
+    type Value = Option<OffsetDateTime>;
+
+    fn expecting(
+      &self,
+      formatter: &mut fmt::Formatter,
+    ) -> fmt::Result {
+      formatter.write_str("an optional datetime")
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+      Ok(None)
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+      Ok(None)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(
+      self,
+      deserializer: D,
+    ) -> Result<Self::Value, D::Error> {
+      super::deserialize(deserializer).map(Some)
+    }
+  }
+}
+
+struct DateTimeVisitor;
+
+impl Visitor<'_> for DateTimeVisitor {
+  type Value = OffsetDateTime;
+
+  fn expecting(
+    &self,
+    formatter: &mut fmt::Formatter,
+  ) -> fmt::Result {
+    formatter.write_str("a SQLite datetime, an RFC 3339 datetime or Core Data seconds")
+  }
+
+  fn visit_str<E: de::Error>(
+    self,
+    value: &str,
+  ) -> Result<Self::Value, E> {
+    parse_text(value).ok_or_else(|| E::invalid_value(de::Unexpected::Str(value), &self))
+  }
+
+  fn visit_i64<E: de::Error>(
+    self,
+    value: i64,
+  ) -> Result<Self::Value, E> {
+    value
+      .checked_add(CORE_DATA_EPOCH)
+      .and_then(|unix_seconds| OffsetDateTime::from_unix_timestamp(unix_seconds).ok())
+      .ok_or_else(|| E::invalid_value(de::Unexpected::Signed(value), &self))
+  }
+
+  fn visit_u64<E: de::Error>(
+    self,
+    value: u64,
+  ) -> Result<Self::Value, E> {
+    i64::try_from(value)
+      .map_err(|_| E::invalid_value(de::Unexpected::Unsigned(value), &self))
+      .and_then(|value| self.visit_i64(value))
+  }
+
+  fn visit_f64<E: de::Error>(
+    self,
+    value: f64,
+  ) -> Result<Self::Value, E> {
+    from_core_data(value).ok_or_else(|| E::invalid_value(de::Unexpected::Float(value), &self))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde::Deserialize;
+  use serde::de::IntoDeserializer;
+  use serde::de::value::Error;
+  use time::Duration;
+
+  /// 2002-01-01 00:00:00 UTC
+  fn new_year() -> OffsetDateTime {
+    OffsetDateTime::from_unix_timestamp(1_009_843_200).unwrap()
+  }
+
+  #[test]
+  fn test_parse_text() {
+    assert_eq!(parse_text("2002-01-01 00:00:00"), Some(new_year()));
+    assert_eq!(
+      parse_text("2002-01-01 00:00:00.250"),
+      Some(new_year() + Duration::milliseconds(250))
+    );
+    assert_eq!(parse_text("2002-01-01T02:00:00+02:00"), Some(new_year()));
+    assert_eq!(parse_text("2002-01-01"), Some(new_year()));
+    assert_eq!(parse_text("yesterday"), None);
+  }
+
+  #[test]
+  fn test_from_core_data() {
+    assert_eq!(from_core_data(31_536_000.0), Some(new_year()));
+    assert_eq!(
+      from_core_data(31_536_000.123_456),
+      Some(new_year() + Duration::nanoseconds(123_456_000))
+    );
+    // Before the Core Data epoch
+    assert_eq!(
+      from_core_data(-0.5),
+      OffsetDateTime::from_unix_timestamp_nanos(978_307_199_500_000_000).ok()
+    );
+    assert_eq!(from_core_data(f64::NAN), None);
+    assert_eq!(from_core_data(1e300), None);
+  }
+
+  #[test]
+  fn test_deserialize() {
+    let from_text: OffsetDateTime = deserialize(IntoDeserializer::<Error>::into_deserializer(
+      "2002-01-01 00:00:00",
+    ))
+    .unwrap();
+    assert_eq!(from_text, new_year());
+
+    // Numbers are Core Data seconds, as in the core_data_modified column
+    let from_seconds: OffsetDateTime =
+      deserialize(IntoDeserializer::<Error>::into_deserializer(31_536_000_i64)).unwrap();
+    assert_eq!(from_seconds, new_year());
+    let from_fraction: OffsetDateTime = deserialize(IntoDeserializer::<Error>::into_deserializer(
+      31_536_000.25_f64,
+    ))
+    .unwrap();
+    assert_eq!(from_fraction, new_year() + Duration::milliseconds(250));
+    assert!(deserialize(IntoDeserializer::<Error>::into_deserializer(i64::MAX)).is_err());
+
+    #[derive(Deserialize)]
+    struct Row {
+      #[serde(with = "super::option")]
+      modified: Option<OffsetDateTime>,
+    }
+    let row: Row = serde_json::from_str(r#"{"modified": null}"#).unwrap();
+    assert_eq!(row.modified, None);
+    let row: Row = serde_json::from_str(r#"{"modified": "2002-01-01 00:00:00"}"#).unwrap();
+    assert_eq!(row.modified, Some(new_year()));
+    assert!(serde_json::from_str::<Row>(r#"{"modified": "soon"}"#).is_err());
+  }
+}
@@ -0,0 +1,189 @@
+//! JSON Lines export.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{BufWriter, Write};
+
+use crate::models::note_from_row;
+use crate::{BearDb, BearError, Note, NoteId, NotesQuery, read_note_link_ids, read_note_tag_names};
+
+/// Version of the record format written by `to_jsonl()`.
+///
+/// Every record carries it as `schema_version`. It is bumped whenever a field is
+/// removed, renamed or changes type; a new `JSONL_SCHEMA` is published with each version.
+pub const JSONL_SCHEMA_VERSION: u32 = 1;
+
+/// JSON Schema (draft 2020-12) describing one record written by `to_jsonl()`.
+///
+/// The same file is published in the repository as
+/// `schemas/note-record.v1.schema.json`.
+pub const JSONL_SCHEMA: &str = include_str!("../../schemas/note-record.v1.schema.json");
+
+/// One line of the JSON Lines export.
+#[derive(Serialize)]
+struct NoteRecord<'a> {
+  schema_version: u32,
+  #[serde(flatten)]
+  note: &'a Note,
+  tags: &'a BTreeSet<String>,
+  links: &'a BTreeSet<NoteId>,
+  backlinks: &'a BTreeSet<NoteId>,
+}
+
+/// Writes notes as JSON Lines: one JSON object per line, one line per note.
+///
+/// Each record holds the note's fields as serialized by `Note` (`id`, `title`,
+/// `content`, `modified`, `created`, `is_pinned`; timestamps in RFC 3339) plus
+/// `schema_version`, the note's `tags` names, and the ids of the notes it `links`
+/// to and of its `backlinks`. Records follow `JSONL_SCHEMA`.
+///
+/// Notes are written as they are read from the database, through a buffer that is
+/// flushed before returning, so memory use doesn't grow with the number of notes.
+/// Notes, tags and links are all read from one connection, in one read
+/// transaction, so every record agrees with the others. Returns the number of
+/// records written.
+///
+/// Unlike other operations, the export isn't retried under
+/// `BearDb::with_retry_policy()`, since part of it may already have been written.
+///
+/// # Example
+///
+/// ```no_run
+/// # use bear_query::{BearDb, NotesQuery};
+/// # use bear_query::export;
+/// # fn main() -> Result<(), bear_query::BearError> {
+/// let db = BearDb::new()?;
+/// let file = std::fs::File::create("notes.jsonl")?;
+/// let written = export::to_jsonl(&db, file, NotesQuery::new().no_limit())?;
+/// println!("Wrote {} records", written);
+/// # Ok(())
+/// # }
+/// ```
+pub fn to_jsonl(
+  db: &BearDb,
+  writer: impl Write,
+  query: NotesQuery,
+) -> Result<usize, BearError> {
+  let mut writer = BufWriter::new(writer);
+
+  let written = db.with_single_attempt(|queryable| {
+    let (sql, params) = query.to_sql();
+    let mut statement = queryable.prepare(&sql)?;
+    let mut rows = statement.query_map(rusqlite::params_from_iter(params), note_from_row)?;
+
+    // The notes query holds its read transaction until all its rows are read, so
+    // reading tags and links after its first row sees the same state of the database
+    let first = rows.next().transpose()?;
+    let tags = read_note_tag_names(queryable)?;
+
+    let mut links: BTreeMap<NoteId, BTreeSet<NoteId>> = BTreeMap::new();
+    let mut backlinks: BTreeMap<NoteId, BTreeSet<NoteId>> = BTreeMap::new();
+    for (from, to) in read_note_link_ids(queryable)? {
+      backlinks
+        .entry(to.clone())
+        .or_default()
+        .insert(from.clone());
+      links.entry(from).or_default().insert(to);
+    }
+
+    let no_tags = BTreeSet::new();
+    let no_links = BTreeSet::new();
+    let mut written = 0;
+
+    for note in first.map(Ok).into_iter().chain(rows) {
+      let note: Note = note?;
+      let record = NoteRecord {
+        schema_version: JSONL_SCHEMA_VERSION,
+        note: &note,
+        tags: tags.get(note.id()).unwrap_or(&no_tags),
+        links: links.get(note.id()).unwrap_or(&no_links),
+        backlinks: backlinks.get(note.id()).unwrap_or(&no_links),
+      };
+      serde_json::to_writer(&mut writer, &record)?;
+      writer.write_all(b"\n")?;
+      written += 1;
+    }
+    Ok(written)
+  })?;
+
+  writer.flush()?;
+  Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::DatabasePath;
+  use serde_json::Value;
+
+  fn records() -> Vec<Value> {
+    let db = BearDb::new_with_path(DatabasePath::InMemory).unwrap();
+    let mut output = Vec::new();
+    let written = to_jsonl(&db, &mut output, NotesQuery::new().no_limit()).unwrap();
+
+    let text = String::from_utf8(output).unwrap();
+    assert_eq!(text.lines().count(), written);
+    text
+      .lines()
+      .map(|line| serde_json::from_str(line).unwrap())
+      .collect()
+  }
+
+  #[test]
+  fn test_to_jsonl_records() {
+    let records = records();
+    assert_eq!(records.len(), 4);
+
+    let first = records
+      .iter()
+      .find(|record| record["id"] == "note-uuid-1")
+      .unwrap();
+    assert_eq!(first["schema_version"], JSONL_SCHEMA_VERSION);
+    assert_eq!(first["title"], "First Note");
+    assert_eq!(first["created"], "2001-01-01T00:00:00Z");
+    assert_eq!(first["is_pinned"], false);
+    assert_eq!(first["tags"], serde_json::json!(["work"]));
+    assert_eq!(first["links"], serde_json::json!(["note-uuid-2"]));
+    assert_eq!(first["backlinks"], serde_json::json!([]));
+
+    let second = records
+      .iter()
+      .find(|record| record["id"] == "note-uuid-2")
+      .unwrap();
+    assert_eq!(second["backlinks"], serde_json::json!(["note-uuid-1"]));
+
+    let empty = records
+      .iter()
+      .find(|record| record["id"] == "note-uuid-5")
+      .unwrap();
+    assert_eq!(empty["content"], Value::Null);
+  }
+
+  #[test]
+  fn test_records_match_schema() {
+    let schema: Value = serde_json::from_str(JSONL_SCHEMA).unwrap();
+    assert_eq!(
+      schema["properties"]["schema_version"]["const"],
+      JSONL_SCHEMA_VERSION
+    );
+
+    let mut required: Vec<&str> = schema["required"]
+      .as_array()
+      .unwrap()
+      .iter()
+      .map(|name| name.as_str().unwrap())
+      .collect();
+    required.sort();
+
+    for record in records() {
+      let mut keys: Vec<&str> = record
+        .as_object()
+        .unwrap()
+        .keys()
+        .map(String::as_str)
+        .collect();
+      keys.sort();
+      assert_eq!(keys, required);
+    }
+  }
+}
@@ -0,0 +1,232 @@
+//! Export state kept between runs, so repeated exports only touch what changed.
+//!
+//! The manifest is a JSON file at the root of the export directory recording, for
+//! each exported note, where it was written and a hash of what was written.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use super::RefusalReason;
+use crate::{BearError, NoteId};
+
+/// Name of the manifest file written at the root of an export.
+pub(crate) const MANIFEST_FILE: &str = ".bear-export.json";
+
+/// Manifest format version, bumped on incompatible changes.
+const MANIFEST_VERSION: u32 = 1;
+
+/// What was written for one note on a previous export.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct ManifestEntry {
+  pub(crate) id: String,
+  /// SHA-256 of the written file, hex encoded
+  pub(crate) hash: String,
+  /// The note's modification timestamp (RFC 3339)
+  pub(crate) modified: String,
+  /// Output path relative to the export root, `/`-separated
+  pub(crate) path: PathBuf,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ManifestFile {
+  version: u32,
+  notes: Vec<ManifestEntry>,
+}
+
+/// Previous export state, keyed by note id.
+#[derive(Debug, Default)]
+pub(crate) struct Manifest {
+  entries: HashMap<String, ManifestEntry>,
+}
+
+impl Manifest {
+  /// Loads the manifest from an export directory.
+  ///
+  /// A missing manifest yields an empty one, for a first export. A corrupt manifest
+  /// fails with `RefusalReason::UnreadableManifest` and one from a newer version with
+  /// `RefusalReason::NewerVersion`, rather than forgetting which files earlier
+  /// exports wrote. Entries with paths escaping the export directory are ignored.
+  pub(crate) fn load(dir: &Path) -> Result<Self, BearError> {
+    let path = dir.join(MANIFEST_FILE);
+    if !path.is_file() {
+      return Ok(Self::default());
+    }
+
+    let refuse = |reason| BearError::ExportTargetRefused {
+      path: dir.to_path_buf(),
+      reason,
+    };
+    let file = match serde_json::from_slice::<ManifestFile>(&fs::read(path)?) {
+      Ok(file) if file.version == MANIFEST_VERSION => file,
+      Ok(file) if file.version > MANIFEST_VERSION => {
+        return Err(refuse(RefusalReason::NewerVersion));
+      }
+      _ => return Err(refuse(RefusalReason::UnreadableManifest)),
+    };
+
+    let entries = file
+      .notes
+      .into_iter()
+      .filter(|entry| is_contained(&entry.path))
+      .map(|entry| (entry.id.clone(), entry))
+      .collect();
+    Ok(Self { entries })
+  }
+
+  /// Returns the previous entry for a note.
+  pub(crate) fn get(
+    &self,
+    id: &NoteId,
+  ) -> Option<&ManifestEntry> {
+    self.entries.get(id.as_str())
+  }
+
+  /// Returns all previous entries, ordered by path.
+  pub(crate) fn entries(&self) -> Vec<&ManifestEntry> {
+    let mut entries: Vec<&ManifestEntry> = self.entries.values().collect();
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    entries
+  }
+
+  /// Writes a manifest with the given entries to an export directory.
+  ///
+  /// The file is written next to its final location and renamed into place, so an
+  /// interrupted export never leaves a truncated manifest behind.
+  pub(crate) fn save(
+    dir: &Path,
+    mut notes: Vec<ManifestEntry>,
+  ) -> Result<(), BearError> {
+    notes.sort_by(|a, b| a.path.cmp(&b.path));
+    let file = ManifestFile {
+      version: MANIFEST_VERSION,
+      notes,
+    };
+
+    let staging = dir.join(format!("{MANIFEST_FILE}.tmp"));
+    fs::write(&staging, serde_json::to_vec_pretty(&file)?)?;
+    fs::rename(staging, dir.join(MANIFEST_FILE))?;
+    Ok(())
+  }
+}
+
+/// Returns true for relative paths that stay inside the directory they are joined to.
+fn is_contained(path: &Path) -> bool {
+  path.components().next().is_some()
+    && path
+      .components()
+      .all(|component| matches!(component, Component::Normal(_)))
+}
+
+/// Hashes exported file contents.
+pub(crate) fn content_hash(contents: &[u8]) -> String {
+  Sha256::digest(contents)
+    .iter()
+    .map(|byte| format!("{byte:02x}"))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn entry(
+    id: &str,
+    path: &str,
+  ) -> ManifestEntry {
+    ManifestEntry {
+      id: id.to_string(),
+      hash: content_hash(id.as_bytes()),
+      modified: "2024-01-01T00:00:00Z".to_string(),
+      path: PathBuf::from(path),
+    }
+  }
+
+  #[test]
+  fn test_round_trip_and_missing_manifest() {
+    let dir = tempfile::tempdir().unwrap();
+    assert!(Manifest::load(dir.path()).unwrap().entries().is_empty());
+
+    Manifest::save(
+      dir.path(),
+      vec![entry("b", "z.md"), entry("a", "work/a.md")],
+    )
+    .unwrap();
+
+    let manifest = Manifest::load(dir.path()).unwrap();
+    let paths: Vec<&Path> = manifest
+      .entries()
+      .iter()
+      .map(|entry| entry.path.as_path())
+      .collect();
+    assert_eq!(paths, vec![Path::new("work/a.md"), Path::new("z.md")]);
+    assert_eq!(
+      manifest.get(&NoteId::new("a".to_string())),
+      Some(&entry("a", "work/a.md"))
+    );
+  }
+
+  #[test]
+  fn test_unusable_manifest() {
+    let dir = tempfile::tempdir().unwrap();
+    Manifest::save(dir.path(), vec![entry("a", "a.md")]).unwrap();
+    let manifest = fs::read(dir.path().join(MANIFEST_FILE)).unwrap();
+
+    // Truncated, garbage and from an unknown older version
+    for contents in [
+      &manifest[..manifest.len() / 2],
+      b"\x00not json{",
+      br#"{"version": 0, "notes": []}"#,
+    ] {
+      fs::write(dir.path().join(MANIFEST_FILE), contents).unwrap();
+      assert!(matches!(
+        Manifest::load(dir.path()),
+        Err(BearError::ExportTargetRefused {
+          path,
+          reason: RefusalReason::UnreadableManifest,
+        }) if path == dir.path()
+      ));
+    }
+
+    fs::write(
+      dir.path().join(MANIFEST_FILE),
+      br#"{"version": 99, "notes": []}"#,
+    )
+    .unwrap();
+    assert!(matches!(
+      Manifest::load(dir.path()),
+      Err(BearError::ExportTargetRefused {
+        reason: RefusalReason::NewerVersion,
+        ..
+      })
+    ));
+  }
+
+  #[test]
+  fn test_ignores_paths_outside_export() {
+    let dir = tempfile::tempdir().unwrap();
+    Manifest::save(
+      dir.path(),
+      vec![
+        entry("a", "../outside.md"),
+        entry("b", "/etc/passwd"),
+        entry("c", "ok.md"),
+      ],
+    )
+    .unwrap();
+
+    let manifest = Manifest::load(dir.path()).unwrap();
+    assert_eq!(manifest.entries().len(), 1);
+    assert_eq!(manifest.entries()[0].id, "c");
+  }
+
+  #[test]
+  fn test_content_hash() {
+    assert_eq!(
+      content_hash(b"abc"),
+      "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+  }
+}
@@ -0,0 +1,615 @@
+//! Markdown vault export.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::manifest::{Manifest, ManifestEntry, content_hash};
+use super::rewrite::{Reference, rewrite_references};
+use super::{
+  ExportSummary, ExportedNote, RemovedNotes, UniquePaths, copy_attachment, encode_link_path,
+  is_claimed_variant, remove_exported, rfc3339, sanitize_filename, set_modified, slash_path,
+};
+use crate::{BearDb, BearError, Note, NotesQuery};
+
+/// Options for `to_markdown_dir()`.
+///
+/// By default every non-trashed, non-archived note is exported with YAML front
+/// matter, filed into folders by its primary tag, and its attachments are copied
+/// into an `attachments` folder at the root of the export.
+///
+/// # Example
+///
+/// ```no_run
+/// # use bear_query::{BearDb, NotesQuery};
+/// # use bear_query::export::ExportOptions;
+/// # fn main() -> Result<(), bear_query::BearError> {
+/// let options = ExportOptions::new()
+///     .notes(NotesQuery::new().no_limit().include_archived())
+///     .attachments_folder("_files");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+  query: NotesQuery,
+  tag_folders: bool,
+  front_matter: bool,
+  copy_attachments: bool,
+  attachments_folder: PathBuf,
+  removed_notes: RemovedNotes,
+}
+
+impl Default for ExportOptions {
+  fn default() -> Self {
+    Self {
+      query: NotesQuery::new().no_limit(),
+      tag_folders: true,
+      front_matter: true,
+      copy_attachments: true,
+      attachments_folder: PathBuf::from("attachments"),
+      removed_notes: RemovedNotes::default(),
+    }
+  }
+}
+
+impl ExportOptions {
+  /// Creates export options with the defaults.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Selects which notes to export (default: all non-trashed, non-archived notes).
+  pub fn notes(
+    mut self,
+    query: NotesQuery,
+  ) -> Self {
+    self.query = query;
+    self
+  }
+
+  /// Writes every note into the export root instead of folders named after tags.
+  pub fn flat(mut self) -> Self {
+    self.tag_folders = false;
+    self
+  }
+
+  /// Omits the YAML front matter block.
+  pub fn no_front_matter(mut self) -> Self {
+    self.front_matter = false;
+    self
+  }
+
+  /// Leaves attachment references as written instead of copying the files.
+  pub fn skip_attachments(mut self) -> Self {
+    self.copy_attachments = false;
+    self
+  }
+
+  /// Sets the folder, relative to the export root, that attachments are copied into.
+  pub fn attachments_folder(
+    mut self,
+    folder: impl Into<PathBuf>,
+  ) -> Self {
+    self.attachments_folder = folder.into();
+    self
+  }
+
+  /// Sets what happens to files of previously exported notes that are no longer
+  /// exported (default: `RemovedNotes::MoveToTrash`).
+  pub fn removed_notes(
+    mut self,
+    mode: RemovedNotes,
+  ) -> Self {
+    self.removed_notes = mode;
+    self
+  }
+}
+
+/// Exports notes as an Obsidian-compatible Markdown vault.
+///
+/// Each note becomes one `.md` file:
+///
+/// - **Front matter**: a YAML block with the note's `id`, `created` and `modified`
+///   timestamps (RFC 3339, UTC), `tags` and `pinned` flag.
+/// - **File names**: the note title with characters that are unsafe in file names
+///   removed. Clashing names (compared case-insensitively) get ` 2`, ` 3`, …
+///   appended, oldest note first, so repeated exports produce the same names.
+/// - **Folders**: the note's primary tag hierarchy, e.g. a note tagged
+///   `#work/projects` is written to `work/projects/`. The primary tag is the first
+///   tag written in the note, or its most deeply nested indexed tag.
+/// - **Links**: `[[Title]]` links to exported notes are rewritten to the target's
+///   vault path (`[[work/projects/Title|Title]]`); `[[Title/Heading]]` becomes
+///   `[[path#Heading|Title/Heading]]`. Links to notes outside the export are kept.
+/// - **Attachments**: files referenced from the note are copied to
+///   `<attachments folder>/<attachment id>/<file name>` and the references updated.
+///
+/// File modification times are set to the notes' modification timestamps.
+///
+/// Exports are incremental: exporting into the same directory again only rewrites
+/// notes whose output changed, moves files whose title or primary tag changed (other
+/// notes keep their names), and removes files of notes no longer exported. See the
+/// `export` module documentation.
+///
+/// # Example
+///
+/// ```no_run
+/// # use bear_query::BearDb;
+/// # use bear_query::export::{self, ExportOptions};
+/// # fn main() -> Result<(), bear_query::BearError> {
+/// let db = BearDb::new()?;
+/// let summary = export::to_markdown_dir(&db, "vault", ExportOptions::new())?;
+///
+/// println!(
+///     "Exported {} notes ({} unchanged, {} removed)",
+///     summary.notes().len(),
+///     summary.notes_unchanged(),
+///     summary.removed().len()
+/// );
+/// if summary.attachments_missing() > 0 {
+///     println!("{} attachments were not found", summary.attachments_missing());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn to_markdown_dir(
+  db: &BearDb,
+  dir: impl AsRef<Path>,
+  options: ExportOptions,
+) -> Result<ExportSummary, BearError> {
+  let dir = dir.as_ref();
+
+  // Oldest first, so name collisions resolve the same way on every export
+  let mut notes = db.notes(options.query.clone())?;
+  notes.sort_by(|a, b| (a.created(), a.id()).cmp(&(b.created(), b.id())));
+
+  let tags = db.note_tag_names()?;
+  let attachments = if options.copy_attachments {
+    db.note_attachments()?
+  } else {
+    HashMap::new()
+  };
+  let no_tags = BTreeSet::new();
+
+  let previous = Manifest::load(dir)?;
+  let targets: Vec<(PathBuf, String)> = notes
+    .iter()
+    .map(|note| {
+      let folder = if options.tag_folders {
+        primary_tag_folder(note, tags.get(note.id()).unwrap_or(&no_tags))
+      } else {
+        PathBuf::new()
+      };
+      (folder, sanitize_filename(note.title()))
+    })
+    .collect();
+
+  // Notes keep the path they were exported to last time while their title and
+  // folder still match it, so new clashing notes never push existing files around
+  let mut unique = UniquePaths::default();
+  let kept: Vec<Option<PathBuf>> = notes
+    .iter()
+    .zip(&targets)
+    .map(|(note, (folder, stem))| {
+      previous
+        .get(note.id())
+        .map(|entry| entry.path.clone())
+        .filter(|path| is_claimed_variant(path, folder, stem, "md") && unique.reserve(path))
+    })
+    .collect();
+  let paths: Vec<PathBuf> = kept
+    .into_iter()
+    .zip(&targets)
+    .map(|(kept, (folder, stem))| kept.unwrap_or_else(|| unique.claim(folder, stem, "md")))
+    .collect();
+
+  // Wiki links resolve by title; the oldest note wins when titles repeat
+  let mut by_title: HashMap<String, &Path> = HashMap::new();
+  for (note, path) in notes.iter().zip(&paths) {
+    by_title
+      .entry(note.title().to_lowercase())
+      .or_insert(path.as_path());
+  }
+
+  fs::create_dir_all(dir)?;
+  let mut summary = ExportSummary::default();
+
+  // Clear out files whose notes were removed or moved before writing, so a renamed
+  // note taking over a removed note's path doesn't clobber it before it is trashed
+  let new_paths: HashMap<&str, &Path> = notes
+    .iter()
+    .zip(&paths)
+    .map(|(note, path)| (note.id().as_str(), path.as_path()))
+    .collect();
+  for entry in previous.entries() {
+    let still_exported = new_paths.get(entry.id.as_str());
+    if still_exported == Some(&entry.path.as_path()) || !dir.join(&entry.path).is_file() {
+      continue;
+    }
+    if still_exported.is_some() {
+      remove_exported(dir, &entry.path, RemovedNotes::Delete)?;
+    } else {
+      remove_exported(dir, &entry.path, options.removed_notes)?;
+      summary.removed.push(entry.path.clone());
+    }
+  }
+
+  let mut manifest = Vec::with_capacity(notes.len());
+
+  for (note, path) in notes.iter().zip(&paths) {
+    let mut copied: HashMap<&str, String> = HashMap::new();
+    for attachment in attachments.get(note.id()).into_iter().flatten() {
+      match copy_attachment(db, attachment, dir, &options.attachments_folder)? {
+        Some(target) => {
+          summary.attachments_copied += 1;
+          copied.insert(attachment.filename(), target);
+        }
+        None => summary.attachments_missing += 1,
+      }
+    }
+
+    // Links are relative to the folder the note is written to
+    let depth = path.components().count() - 1;
+    let content = rewrite_references(
+      note.content().unwrap_or_default(),
+      |reference| match reference {
+        Reference::WikiLink { target, display } => rewrite_wiki_link(target, *display, &by_title),
+        Reference::Attachment(filename) => copied
+          .get(filename.as_str())
+          .map(|target| format!("{}{}", "../".repeat(depth), encode_link_path(target))),
+      },
+    );
+
+    let mut document = String::new();
+    if options.front_matter {
+      document.push_str(&front_matter(note, tags.get(note.id()).unwrap_or(&no_tags)));
+    }
+    document.push_str(&content);
+
+    let entry = ManifestEntry {
+      id: note.id().as_str().to_string(),
+      hash: content_hash(document.as_bytes()),
+      modified: rfc3339(note.modified()),
+      path: path.clone(),
+    };
+
+    let file = dir.join(path);
+    match previous.get(note.id()) {
+      Some(last) if last.path == entry.path && last.hash == entry.hash && file.is_file() => {
+        summary.unchanged += 1;
+        if last.modified != entry.modified {
+          set_modified(&file, note.modified())?;
+        }
+      }
+      _ => {
+        if let Some(parent) = file.parent() {
+          fs::create_dir_all(parent)?;
+        }
+        fs::write(&file, document)?;
+        set_modified(&file, note.modified())?;
+      }
+    }
+
+    manifest.push(entry);
+    summary.notes.push(ExportedNote {
+      id: note.id().clone(),
+      path: path.clone(),
+    });
+  }
+
+  Manifest::save(dir, manifest)?;
+
+  Ok(summary)
+}
+
+/// Chooses the folder for a note from its primary tag.
+fn primary_tag_folder(
+  note: &Note,
+  indexed: &BTreeSet<String>,
+) -> PathBuf {
+  let primary = note
+    .inline_tags()
+    .first()
+    .map(|tag| tag.name().to_string())
+    .or_else(|| {
+      indexed
+        .iter()
+        .max_by_key(|name| name.matches('/').count())
+        .cloned()
+    });
+
+  primary
+    .iter()
+    .flat_map(|tag| tag.split('/'))
+    .filter(|segment| !segment.trim().is_empty())
+    .map(sanitize_filename)
+    .collect()
+}
+
+/// Rewrites a wiki link to point at the target note's path in the export.
+fn rewrite_wiki_link(
+  target: &str,
+  display: Option<&str>,
+  by_title: &HashMap<String, &Path>,
+) -> Option<String> {
+  let display = display.unwrap_or(target);
+  let (path, heading) = match by_title.get(&target.to_lowercase()) {
+    Some(path) => (*path, None),
+    // Bear links to a heading with `[[Note/Heading]]`
+    None => {
+      let (title, heading) = target.rsplit_once('/')?;
+      (*by_title.get(&title.to_lowercase())?, Some(heading))
+    }
+  };
+
+  let link = slash_path(&path.with_extension(""));
+  Some(match heading {
+    Some(heading) => format!("[[{link}#{heading}|{display}]]"),
+    None => format!("[[{link}|{display}]]"),
+  })
+}
+
+/// Builds the YAML front matter block for a note.
+fn front_matter(
+  note: &Note,
+  tags: &BTreeSet<String>,
+) -> String {
+  let mut yaml = String::from("---\n");
+  yaml.push_str(&format!("id: {}\n", yaml_string(note.id().as_str())));
+  yaml.push_str(&format!("title: {}\n", yaml_string(note.title())));
+  yaml.push_str(&format!("created: {}\n", rfc3339(note.created())));
+  yaml.push_str(&format!("modified: {}\n", rfc3339(note.modified())));
+  if tags.is_empty() {
+    yaml.push_str("tags: []\n");
+  } else {
+    yaml.push_str("tags:\n");
+    for tag in tags {
+      yaml.push_str(&format!("  - {}\n", yaml_string(tag)));
+    }
+  }
+  yaml.push_str(&format!("pinned: {}\n", note.is_pinned()));
+  yaml.push_str("---\n\n");
+  yaml
+}
+
+/// Quotes a value as a double-quoted YAML string.
+fn yaml_string(value: &str) -> String {
+  let mut quoted = String::with_capacity(value.len() + 2);
+  quoted.push('"');
+  for c in value.chars() {
+    match c {
+      '"' => quoted.push_str("\\\""),
+      '\\' => quoted.push_str("\\\\"),
+      '\n' => quoted.push_str("\\n"),
+      '\t' => quoted.push_str("\\t"),
+      c if c.is_control() => quoted.push_str(&format!("\\u{:04X}", c as u32)),
+      c => quoted.push(c),
+    }
+  }
+  quoted.push('"');
+  quoted
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::export::fixture_db;
+  use std::time::SystemTime;
+  use time::OffsetDateTime;
+
+  /// Notes that link to each other, carry tags, and reference attachments
+  const EXPORT_FIXTURE: &str = r"
+    INSERT INTO ZSFNOTE (Z_PK, ZUNIQUEIDENTIFIER, ZTITLE, ZTEXT, ZMODIFICATIONDATE, ZCREATIONDATE, ZPINNED, ZTRASHED, ZARCHIVED)
+    VALUES
+      (30, 'note-uuid-30', 'Roadmap: 2024', '# Roadmap: 2024
+#work/projects
+
+See [[Second Note]] and [[Meeting/Agenda|the agenda]]. ![chart](chart%201.png)
+Missing: ![gone](gone.png) [[Nowhere]]', 100, 100, 1, 0, 0),
+      (31, 'note-uuid-31', 'Meeting', '# Meeting
+## Agenda
+- plan', 200, 200, 0, 0, 0),
+      (32, 'note-uuid-32', 'meeting', 'Same title, different case', 300, 300, 0, 0, 0);
+
+    INSERT INTO ZSFNOTETAG (Z_PK, ZTITLE, ZMODIFICATIONDATE)
+    VALUES
+      (30, 'work/projects', 0);
+
+    INSERT INTO Z_5TAGS (Z_5NOTES, Z_13TAGS)
+    VALUES
+      (30, 1),
+      (30, 30);
+
+    INSERT INTO ZSFNOTEFILE (Z_PK, ZUNIQUEIDENTIFIER, ZNOTE, ZFILENAME, ZFILESIZE, ZCREATIONDATE, ZMODIFICATIONDATE)
+    VALUES
+      (1, 'file-uuid-1', 30, 'chart 1.png', 3, 100, 100),
+      (2, 'file-uuid-2', 30, 'gone.png', 3, 100, 100);
+  ";
+
+  /// The export fixture after a round of edits: one note retitled, one deleted, one added
+  const EDITED_FIXTURE: &str = r"
+    INSERT INTO ZSFNOTE (Z_PK, ZUNIQUEIDENTIFIER, ZTITLE, ZTEXT, ZMODIFICATIONDATE, ZCREATIONDATE, ZPINNED, ZTRASHED, ZARCHIVED)
+    VALUES
+      (30, 'note-uuid-30', 'Roadmap 2025', '# Roadmap 2025
+#work/projects', 400, 100, 1, 0, 0),
+      (31, 'note-uuid-31', 'Meeting', '# Meeting
+## Agenda
+- plan', 200, 200, 0, 0, 0),
+      (33, 'note-uuid-33', 'Meeting', 'Another meeting', 500, 50, 0, 0, 0);
+
+    INSERT INTO ZSFNOTETAG (Z_PK, ZTITLE, ZMODIFICATIONDATE)
+    VALUES
+      (30, 'work/projects', 0);
+
+    INSERT INTO Z_5TAGS (Z_5NOTES, Z_13TAGS)
+    VALUES
+      (30, 1),
+      (30, 30);
+  ";
+
+  fn exported_db(files_root: &Path) -> BearDb {
+    fixture_db(EXPORT_FIXTURE, files_root, "chart 1.png")
+  }
+
+  #[test]
+  fn test_to_markdown_dir() {
+    let files = tempfile::tempdir().unwrap();
+    let out = tempfile::tempdir().unwrap();
+    let db = exported_db(files.path());
+
+    let summary = to_markdown_dir(&db, out.path(), ExportOptions::new()).unwrap();
+
+    let paths: Vec<String> = summary
+      .notes()
+      .iter()
+      .map(|note| slash_path(note.path()))
+      .collect();
+    assert_eq!(
+      paths,
+      vec![
+        "work/First Note.md",
+        "Untitled.md",
+        "Empty Note.md",
+        "work/projects/Roadmap 2024.md",
+        "Meeting.md",
+        "meeting 2.md",
+        "personal/Second Note.md",
+      ]
+    );
+    assert_eq!(summary.attachments_copied(), 1);
+    assert_eq!(summary.attachments_missing(), 1);
+
+    let roadmap = fs::read_to_string(out.path().join("work/projects/Roadmap 2024.md")).unwrap();
+    assert!(roadmap.starts_with(
+      "---\nid: \"note-uuid-30\"\ntitle: \"Roadmap: 2024\"\ncreated: 2001-01-01T00:01:40Z\n"
+    ));
+    assert!(roadmap.contains("tags:\n  - \"work\"\n  - \"work/projects\"\npinned: true\n---\n\n"));
+    assert!(
+      roadmap
+        .contains("See [[personal/Second Note|Second Note]] and [[Meeting#Agenda|the agenda]].")
+    );
+    assert!(roadmap.contains("![chart](../../attachments/file-uuid-1/chart%201.png)"));
+    assert!(roadmap.contains("![gone](gone.png) [[Nowhere]]"));
+
+    let copied = out.path().join("attachments/file-uuid-1/chart 1.png");
+    assert_eq!(fs::read(copied).unwrap(), b"png");
+
+    let modified = fs::metadata(out.path().join("Meeting.md"))
+      .unwrap()
+      .modified()
+      .unwrap();
+    let expected: SystemTime = OffsetDateTime::from_unix_timestamp(978_307_200 + 200)
+      .unwrap()
+      .into();
+    assert_eq!(modified, expected);
+  }
+
+  /// Attachments whose stored names climb out of their folders
+  const TRAVERSAL_FIXTURE: &str = r"
+    INSERT INTO ZSFNOTE (Z_PK, ZUNIQUEIDENTIFIER, ZTITLE, ZTEXT, ZMODIFICATIONDATE, ZCREATIONDATE, ZPINNED, ZTRASHED, ZARCHIVED)
+    VALUES
+      (40, 'note-uuid-40', 'Escape', '![a](../../../outside.png) ![b](../inside.png)', 100, 100, 0, 0, 0);
+
+    INSERT INTO ZSFNOTEFILE (Z_PK, ZUNIQUEIDENTIFIER, ZNOTE, ZFILENAME, ZFILESIZE, ZCREATIONDATE, ZMODIFICATIONDATE)
+    VALUES
+      (3, 'file-uuid-3', 40, '../../../outside.png', 3, 100, 100),
+      (4, 'file-uuid-4', 40, '../inside.png', 3, 100, 100),
+      (5, 'file-uuid-5', 40, '..', 3, 100, 100);
+  ";
+
+  #[test]
+  fn test_attachment_path_traversal() {
+    let files = tempfile::tempdir().unwrap();
+    let root = files.path().join("Local Files");
+    let db = fixture_db(TRAVERSAL_FIXTURE, &root, "chart 1.png");
+    // Where the unchecked names would resolve to
+    fs::write(files.path().join("outside.png"), b"secret").unwrap();
+    let inside = root.join("Note Images").join("file-uuid-4");
+    fs::create_dir_all(&inside).unwrap();
+    fs::write(inside.join("inside.png"), b"png").unwrap();
+
+    let out = tempfile::tempdir().unwrap();
+    let dir = out.path().join("export");
+    let summary = to_markdown_dir(&db, &dir, ExportOptions::new()).unwrap();
+
+    // Only the last path component is used, for reading and for writing
+    assert_eq!(
+      fs::read(dir.join("attachments/file-uuid-4/inside.png")).unwrap(),
+      b"png"
+    );
+    assert!(!out.path().join("outside.png").exists());
+    assert!(!dir.join("attachments/file-uuid-3").exists());
+    assert!(!dir.join("attachments/file-uuid-5").exists());
+    assert_eq!(summary.attachments_copied(), 1);
+    assert_eq!(summary.attachments_missing(), 2);
+  }
+
+  #[test]
+  fn test_flat_export_without_front_matter() {
+    let files = tempfile::tempdir().unwrap();
+    let out = tempfile::tempdir().unwrap();
+    let db = exported_db(files.path());
+
+    let options = ExportOptions::new()
+      .flat()
+      .no_front_matter()
+      .skip_attachments();
+    let summary = to_markdown_dir(&db, out.path(), options).unwrap();
+
+    assert!(
+      summary
+        .notes()
+        .iter()
+        .all(|note| note.path().parent() == Some(Path::new("")))
+    );
+    assert_eq!(summary.attachments_copied(), 0);
+
+    let roadmap = fs::read_to_string(out.path().join("Roadmap 2024.md")).unwrap();
+    assert!(roadmap.starts_with("# Roadmap: 2024\n"));
+    assert!(roadmap.contains("![chart](chart%201.png)"));
+    assert!(!out.path().join("attachments").exists());
+  }
+
+  #[test]
+  fn test_incremental_export() {
+    let files = tempfile::tempdir().unwrap();
+    let out = tempfile::tempdir().unwrap();
+    let db = exported_db(files.path());
+
+    let first = to_markdown_dir(&db, out.path(), ExportOptions::new()).unwrap();
+    assert_eq!(first.notes_written(), 7);
+    assert!(out.path().join(".bear-export.json").is_file());
+
+    // Nothing changed: nothing is rewritten
+    let second = to_markdown_dir(&db, out.path(), ExportOptions::new()).unwrap();
+    assert_eq!(second.notes_unchanged(), 7);
+    assert_eq!(second.notes_written(), 0);
+    assert!(second.removed().is_empty());
+
+    let edited = fixture_db(EDITED_FIXTURE, files.path(), "chart 1.png");
+    let third = to_markdown_dir(&edited, out.path(), ExportOptions::new()).unwrap();
+    assert_eq!(third.notes_written(), 2);
+    assert_eq!(third.notes_unchanged(), 5);
+
+    // The retitled note moves
+    assert!(!out.path().join("work/projects/Roadmap 2024.md").exists());
+    assert!(out.path().join("work/projects/Roadmap 2025.md").is_file());
+
+    // The deleted note goes to the trash folder
+    assert_eq!(third.removed(), [PathBuf::from("meeting 2.md")]);
+    let trashed = fs::read_to_string(out.path().join("_trash/meeting 2.md")).unwrap();
+    assert!(trashed.contains("Same title, different case"));
+
+    // The existing "Meeting" keeps its file; the older, newly added one is numbered
+    let meeting = fs::read_to_string(out.path().join("Meeting.md")).unwrap();
+    assert!(meeting.contains("note-uuid-31"));
+    let added = fs::read_to_string(out.path().join("Meeting 2.md")).unwrap();
+    assert!(added.contains("note-uuid-33"));
+  }
+
+  #[test]
+  fn test_yaml_string_escapes() {
+    assert_eq!(yaml_string(r#"a "b" \c"#), r#""a \"b\" \\c""#);
+    assert_eq!(yaml_string("line\nbreak"), r#""line\nbreak""#);
+  }
+}
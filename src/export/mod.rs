@@ -0,0 +1,536 @@
+//! Exporting notes out of Bear into files other tools can read.
+//!
+//! Exports only read from Bear's database; all output goes to the directory you
+//! pass in.
+//!
+//! - `to_markdown_dir()` writes an Obsidian-compatible Markdown vault.
+//! - `to_textbundles()` writes one TextBundle (or, with the `textpack` feature,
+//!   zipped TextPack) per note, which Bear can import again.
+//! - `to_site()` renders notes into a static HTML site with tag indexes, backlinks
+//!   and a search index.
+//! - `to_jsonl()` streams one JSON record per note, following the versioned
+//!   `JSONL_SCHEMA`.
+//! - `to_normalized_sqlite()` materializes the normalized views into a standalone
+//!   SQLite database with indexes, foreign keys and optional full-text search.
+//! - `dataframe_to_parquet()`, `dataframe_to_ipc()` and `dataframe_to_csv()` write
+//!   query results, and `BearDb::export_tables()` dumps the normalized tables, in
+//!   the formats enabled by the `parquet`, `ipc` and `csv` cargo features.
+//!
+//! # Incremental exports
+//!
+//! Each export records what it wrote in a `.bear-export.json` manifest at the root
+//! of the export directory. Exporting again into the same directory only rewrites
+//! notes whose output changed, renames files whose note titles (or primary tags)
+//! changed, and removes the files of notes that are no longer exported, such as
+//! notes trashed or deleted in Bear. See `RemovedNotes` for what "removes" means.
+//! A corrupt manifest fails the export with `RefusalReason::UnreadableManifest`
+//! instead of losing track of earlier output.
+//! TextBundle exports work the same way, one bundle (or TextPack) per note.
+
+mod jsonl;
+mod manifest;
+mod markdown;
+mod rewrite;
+mod site;
+mod sqlite;
+mod tables;
+mod textbundle;
+
+pub use jsonl::{JSONL_SCHEMA, JSONL_SCHEMA_VERSION, to_jsonl};
+pub use markdown::{ExportOptions, to_markdown_dir};
+pub use site::{SiteOptions, to_site};
+pub use sqlite::{SqliteExportOptions, SqliteExportSummary, to_normalized_sqlite};
+pub use tables::TableFormat;
+#[cfg(feature = "csv")]
+pub use tables::dataframe_to_csv;
+#[cfg(feature = "ipc")]
+pub use tables::dataframe_to_ipc;
+#[cfg(feature = "parquet")]
+pub use tables::dataframe_to_parquet;
+pub use textbundle::{TextBundleOptions, to_textbundles};
+
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+
+use crate::models::Attachment;
+use crate::{BearDb, BearError, NoteId};
+
+/// Why an export refused to write to its target, in
+/// `BearError::ExportTargetRefused`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefusalReason {
+  /// The target is the database the notes are read from
+  BearDatabase,
+  /// The target holds tables that weren't written by this exporter
+  ForeignDatabase,
+  /// The target was written by a newer version of this exporter
+  NewerVersion,
+  /// The target directory holds files that weren't written by this exporter
+  ForeignFolder,
+  /// The target directory's `.bear-export.json` manifest is corrupt, so the files
+  /// earlier exports wrote are unknown. Delete the manifest (and any files left
+  /// from earlier exports) to export from scratch
+  UnreadableManifest,
+}
+
+impl fmt::Display for RefusalReason {
+  fn fmt(
+    &self,
+    f: &mut fmt::Formatter<'_>,
+  ) -> fmt::Result {
+    f.write_str(match self {
+      RefusalReason::BearDatabase => "it is Bear's own database",
+      RefusalReason::ForeignDatabase => "it is not a database exported by bear-query",
+      RefusalReason::NewerVersion => "it was exported by a newer version of bear-query",
+      RefusalReason::ForeignFolder => "it holds files that were not exported by bear-query",
+      RefusalReason::UnreadableManifest => {
+        "its .bear-export.json manifest can't be read; delete it to export from scratch"
+      }
+    })
+  }
+}
+
+/// A note written by an export, and where it was written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportedNote {
+  id: NoteId,
+  path: PathBuf,
+}
+
+impl ExportedNote {
+  /// Returns the exported note's identifier.
+  pub fn id(&self) -> &NoteId {
+    &self.id
+  }
+
+  /// Returns the path of the written file, relative to the export directory.
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+}
+
+/// What happens to the files of notes that a previous export wrote but that are no
+/// longer exported (trashed, archived or deleted in Bear, or excluded by the query).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RemovedNotes {
+  /// Move the files into a `_trash` folder at the root of the export, keeping their paths.
+  #[default]
+  MoveToTrash,
+  /// Delete the files.
+  Delete,
+}
+
+/// Folder that removed notes are moved into with `RemovedNotes::MoveToTrash`.
+pub(crate) const TRASH_FOLDER: &str = "_trash";
+
+/// What an export wrote.
+#[derive(Debug, Clone, Default)]
+pub struct ExportSummary {
+  notes: Vec<ExportedNote>,
+  unchanged: usize,
+  removed: Vec<PathBuf>,
+  attachments_copied: usize,
+  attachments_missing: usize,
+}
+
+impl ExportSummary {
+  /// Returns every exported note, including ones whose files were already up to date.
+  pub fn notes(&self) -> &[ExportedNote] {
+    &self.notes
+  }
+
+  /// Returns the number of notes whose files were already up to date and not rewritten.
+  pub fn notes_unchanged(&self) -> usize {
+    self.unchanged
+  }
+
+  /// Returns the number of notes whose files were written or rewritten.
+  pub fn notes_written(&self) -> usize {
+    self.notes.len() - self.unchanged
+  }
+
+  /// Returns the paths, relative to the export directory, of files removed because
+  /// their notes are no longer exported.
+  pub fn removed(&self) -> &[PathBuf] {
+    &self.removed
+  }
+
+  /// Returns the number of attachment files copied into the export.
+  pub fn attachments_copied(&self) -> usize {
+    self.attachments_copied
+  }
+
+  /// Returns the number of attachments whose files could not be found on disk.
+  ///
+  /// References to missing attachments are left unchanged in the exported notes.
+  pub fn attachments_missing(&self) -> usize {
+    self.attachments_missing
+  }
+}
+
+/// Characters that are unsafe in file names on at least one common platform or
+/// have special meaning in wiki links.
+const UNSAFE_FILENAME_CHARS: &str = "/\\:*?\"<>|#^[]";
+
+/// Longest file name stem produced, in characters.
+const MAX_FILENAME_CHARS: usize = 120;
+
+/// Longest file name stem produced, in UTF-8 bytes. File names are limited to 255
+/// bytes on APFS and ext4, which leaves room for a ` 4294967295` collision suffix
+/// from `UniquePaths::claim()` and an extension like `.textbundle`.
+const MAX_FILENAME_BYTES: usize = 200;
+
+/// Turns a note title or tag into a name that is safe to use as a file or folder name.
+///
+/// Unsafe characters become spaces, whitespace is collapsed, leading and trailing
+/// dots are removed, and empty results fall back to `"Untitled"`. Long names are
+/// cut at a character boundary to [`MAX_FILENAME_CHARS`] characters and
+/// [`MAX_FILENAME_BYTES`] bytes.
+pub(crate) fn sanitize_filename(name: &str) -> String {
+  let cleaned: String = name
+    .chars()
+    .map(|c| {
+      if c.is_control() || UNSAFE_FILENAME_CHARS.contains(c) {
+        ' '
+      } else {
+        c
+      }
+    })
+    .collect();
+  let collapsed = cleaned.split_whitespace().collect::<Vec<_>>().join(" ");
+  let mut bytes = 0;
+  let truncated: String = collapsed
+    .chars()
+    .take(MAX_FILENAME_CHARS)
+    .take_while(|c| {
+      bytes += c.len_utf8();
+      bytes <= MAX_FILENAME_BYTES
+    })
+    .collect();
+  let trimmed = truncated.trim_matches(|c: char| c == '.' || c.is_whitespace());
+
+  if trimmed.is_empty() {
+    "Untitled".to_string()
+  } else {
+    trimmed.to_string()
+  }
+}
+
+/// Hands out file paths that do not collide, ignoring case.
+///
+/// macOS file systems are case-insensitive by default, so `Notes.md` and
+/// `notes.md` are treated as the same file.
+#[derive(Debug, Default)]
+pub(crate) struct UniquePaths {
+  used: HashSet<String>,
+}
+
+impl UniquePaths {
+  /// Claims an exact path, returning false if it is already taken.
+  pub(crate) fn reserve(
+    &mut self,
+    path: &Path,
+  ) -> bool {
+    self.used.insert(path.to_string_lossy().to_lowercase())
+  }
+
+  /// Claims `folder/stem.extension`, appending ` 2`, ` 3`, … to the stem if it is taken.
+  pub(crate) fn claim(
+    &mut self,
+    folder: &Path,
+    stem: &str,
+    extension: &str,
+  ) -> PathBuf {
+    (1..)
+      .map(|n| match n {
+        1 => folder.join(format!("{stem}.{extension}")),
+        n => folder.join(format!("{stem} {n}.{extension}")),
+      })
+      .find(|path| self.reserve(path))
+      .expect("an unused path suffix always exists")
+  }
+}
+
+/// Formats a timestamp as RFC 3339, e.g. `2024-01-15T09:30:00Z`.
+pub(crate) fn rfc3339(timestamp: OffsetDateTime) -> String {
+  timestamp
+    .format(&Rfc3339)
+    .unwrap_or_else(|_| timestamp.to_string())
+}
+
+/// Sets a file's modification time.
+pub(crate) fn set_modified(
+  path: &Path,
+  modified: OffsetDateTime,
+) -> io::Result<()> {
+  fs::File::options()
+    .write(true)
+    .open(path)?
+    .set_modified(modified.into())
+}
+
+/// Returns true if `path` is `folder/stem.extension` or a numbered variant such as
+/// `folder/stem 2.extension` that `UniquePaths::claim()` could have produced.
+pub(crate) fn is_claimed_variant(
+  path: &Path,
+  folder: &Path,
+  stem: &str,
+  extension: &str,
+) -> bool {
+  let Some(file_stem) = path.file_stem().and_then(|stem| stem.to_str()) else {
+    return false;
+  };
+  let suffix_ok = match file_stem.strip_prefix(stem) {
+    Some("") => true,
+    Some(suffix) => suffix
+      .strip_prefix(' ')
+      .and_then(|n| n.parse::<u32>().ok())
+      .is_some_and(|n| n >= 2),
+    None => false,
+  };
+
+  suffix_ok
+    && path.parent() == Some(folder)
+    && path.extension().and_then(|ext| ext.to_str()) == Some(extension)
+}
+
+/// Removes a previously exported file or bundle directory, either deleting it or
+/// moving it under `_trash`.
+///
+/// Folders left empty are removed, up to (but not including) the export directory.
+pub(crate) fn remove_exported(
+  dir: &Path,
+  relative: &Path,
+  mode: RemovedNotes,
+) -> io::Result<()> {
+  let file = dir.join(relative);
+  match mode {
+    RemovedNotes::Delete if file.is_dir() => fs::remove_dir_all(&file)?,
+    RemovedNotes::Delete => fs::remove_file(&file)?,
+    RemovedNotes::MoveToTrash => {
+      let target = dir.join(TRASH_FOLDER).join(relative);
+      if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+      }
+      // Directories can't be renamed over, so an older bundle in the trash makes way
+      if target.is_dir() {
+        fs::remove_dir_all(&target)?;
+      }
+      fs::rename(&file, target)?;
+    }
+  }
+
+  for folder in file.ancestors().skip(1) {
+    if folder == dir || !folder.starts_with(dir) || fs::remove_dir(folder).is_err() {
+      break;
+    }
+  }
+  Ok(())
+}
+
+/// Copies an attachment file into the export, returning its path relative to the export root.
+///
+/// Returns `None` when the file cannot be found or its name is empty. Only the last
+/// component of the stored file name is kept, so the copy stays inside `dir`.
+pub(crate) fn copy_attachment(
+  db: &BearDb,
+  attachment: &Attachment,
+  dir: &Path,
+  folder: &Path,
+) -> Result<Option<String>, BearError> {
+  let Some(filename) = Path::new(attachment.filename()).file_name() else {
+    return Ok(None);
+  };
+  let Some(source) = db
+    .files_root()
+    .and_then(|root| attachment.source_path(root))
+  else {
+    return Ok(None);
+  };
+
+  let relative = folder
+    .join(sanitize_filename(attachment.id()))
+    .join(sanitize_filename(&filename.to_string_lossy()));
+  let target = dir.join(&relative);
+
+  // Attachments never change in place in Bear, so an existing copy of the same size is current
+  let up_to_date = match (fs::metadata(&source), fs::metadata(&target)) {
+    (Ok(source), Ok(target)) => source.len() == target.len(),
+    _ => false,
+  };
+  if !up_to_date {
+    if let Some(parent) = target.parent() {
+      fs::create_dir_all(parent)?;
+    }
+    fs::copy(&source, &target)?;
+  }
+
+  Ok(Some(slash_path(&relative)))
+}
+
+/// Percent-encodes a relative path for use as a Markdown link destination.
+///
+/// Path separators and unreserved characters are kept as-is.
+pub(crate) fn encode_link_path(path: &str) -> String {
+  let mut encoded = String::with_capacity(path.len());
+  for byte in path.bytes() {
+    match byte {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+        encoded.push(byte as char)
+      }
+      _ => encoded.push_str(&format!("%{byte:02X}")),
+    }
+  }
+  encoded
+}
+
+/// Joins path components with `/`, regardless of platform.
+pub(crate) fn slash_path(path: &Path) -> String {
+  path
+    .components()
+    .map(|component| component.as_os_str().to_string_lossy())
+    .collect::<Vec<_>>()
+    .join("/")
+}
+
+/// Opens an in-memory test database with `fixture` applied, whose attachments live
+/// under `files_root`. Only the attachment `file-uuid-1` exists there, as a file
+/// named `image`; fixtures give it that name in `ZSFNOTEFILE`.
+#[cfg(test)]
+pub(crate) fn fixture_db(
+  fixture: &'static str,
+  files_root: &Path,
+  image: &str,
+) -> BearDb {
+  let mut db = BearDb::new_with_path(crate::DatabasePath::InMemoryWith(fixture)).unwrap();
+  let images = files_root.join("Note Images").join("file-uuid-1");
+  fs::create_dir_all(&images).unwrap();
+  fs::write(images.join(image), b"png").unwrap();
+  db.files_root = Some(files_root.to_path_buf());
+  db
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_sanitize_filename() {
+    assert_eq!(sanitize_filename("Plans: 2024/25?"), "Plans 2024 25");
+    assert_eq!(sanitize_filename("  ..hidden.. "), "hidden");
+    assert_eq!(sanitize_filename("#[[]]"), "Untitled");
+    assert_eq!(sanitize_filename(""), "Untitled");
+    assert_eq!(
+      sanitize_filename(&"x".repeat(500)).len(),
+      MAX_FILENAME_CHARS
+    );
+
+    // Multibyte titles are cut on a byte budget, at a character boundary
+    for title in [
+      "日本語のノート".repeat(30),
+      "🎉".repeat(120),
+      "aé".repeat(100),
+    ] {
+      let name = sanitize_filename(&title);
+      assert!(name.len() <= MAX_FILENAME_BYTES, "{} bytes", name.len());
+      assert!(title.starts_with(&name));
+      let claimed = UniquePaths::default().claim(Path::new(""), &name, "textbundle");
+      assert!(format!("{name} 4294967295.textbundle").len() <= 255);
+      assert!(claimed.as_os_str().len() <= 255);
+    }
+    assert_eq!(sanitize_filename(&"🎉".repeat(120)), "🎉".repeat(50));
+  }
+
+  #[test]
+  fn test_unique_paths_ignore_case() {
+    let mut paths = UniquePaths::default();
+    let folder = Path::new("work");
+    assert_eq!(paths.claim(folder, "Notes", "md"), folder.join("Notes.md"));
+    assert_eq!(
+      paths.claim(folder, "notes", "md"),
+      folder.join("notes 2.md")
+    );
+    assert_eq!(
+      paths.claim(folder, "NOTES", "md"),
+      folder.join("NOTES 3.md")
+    );
+    assert_eq!(
+      paths.claim(Path::new(""), "Notes", "md"),
+      PathBuf::from("Notes.md")
+    );
+  }
+
+  #[test]
+  fn test_is_claimed_variant() {
+    let folder = Path::new("work");
+    assert!(is_claimed_variant(
+      Path::new("work/Notes.md"),
+      folder,
+      "Notes",
+      "md"
+    ));
+    assert!(is_claimed_variant(
+      Path::new("work/Notes 3.md"),
+      folder,
+      "Notes",
+      "md"
+    ));
+    assert!(!is_claimed_variant(
+      Path::new("work/Notes 1.md"),
+      folder,
+      "Notes",
+      "md"
+    ));
+    assert!(!is_claimed_variant(
+      Path::new("work/Notes old.md"),
+      folder,
+      "Notes",
+      "md"
+    ));
+    assert!(!is_claimed_variant(
+      Path::new("Notes.md"),
+      folder,
+      "Notes",
+      "md"
+    ));
+  }
+
+  #[test]
+  fn test_remove_exported() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in ["a/b/one.md", "a/two.md"] {
+      let file = dir.path().join(name);
+      fs::create_dir_all(file.parent().unwrap()).unwrap();
+      fs::write(file, name).unwrap();
+    }
+
+    remove_exported(
+      dir.path(),
+      Path::new("a/b/one.md"),
+      RemovedNotes::MoveToTrash,
+    )
+    .unwrap();
+    assert!(!dir.path().join("a/b").exists());
+    assert_eq!(
+      fs::read_to_string(dir.path().join("_trash/a/b/one.md")).unwrap(),
+      "a/b/one.md"
+    );
+
+    remove_exported(dir.path(), Path::new("a/two.md"), RemovedNotes::Delete).unwrap();
+    assert!(!dir.path().join("a").exists());
+    assert!(dir.path().exists());
+  }
+
+  #[test]
+  fn test_encode_link_path() {
+    assert_eq!(
+      encode_link_path("attachments/a b/(1).png"),
+      "attachments/a%20b/%281%29.png"
+    );
+  }
+}
@@ -0,0 +1,163 @@
+//! Rewriting link targets inside Markdown source without re-rendering it.
+//!
+//! Exports keep the note's Markdown as written and only splice in new targets for
+//! `[[wiki links]]` and relative attachment references, so formatting, tags and
+//! anything the parser doesn't understand survive unchanged.
+
+use pulldown_cmark::{Event, LinkType, Parser, Tag};
+use std::ops::Range;
+
+use crate::html;
+
+/// A link found in note content that an exporter may want to retarget.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Reference<'a> {
+  /// A `[[target]]` or `[[target|display]]` link to another note.
+  WikiLink {
+    target: &'a str,
+    display: Option<&'a str>,
+  },
+  /// A relative link or image destination, percent-decoded (e.g. an attachment file name).
+  Attachment(String),
+}
+
+/// Rewrites references in `content`.
+///
+/// `rewrite` is called for each reference in order of appearance. Returning `Some`
+/// replaces the whole `[[...]]` span of a wiki link, or only the destination of a
+/// Markdown link or image; returning `None` leaves the reference as written.
+pub(crate) fn rewrite_references<'a>(
+  content: &'a str,
+  mut rewrite: impl FnMut(&Reference<'a>) -> Option<String>,
+) -> String {
+  let mut edits: Vec<(Range<usize>, String)> = Vec::new();
+
+  for (event, range) in Parser::new_ext(content, html::parser_options()).into_offset_iter() {
+    match event {
+      Event::Start(Tag::Link {
+        link_type: LinkType::WikiLink { .. },
+        ..
+      }) => {
+        let Some(inner) = content[range.clone()]
+          .strip_prefix("[[")
+          .and_then(|source| source.strip_suffix("]]"))
+        else {
+          continue;
+        };
+        let (target, display) = match inner.split_once('|') {
+          Some((target, display)) => (target, Some(display)),
+          None => (inner, None),
+        };
+        if let Some(replacement) = rewrite(&Reference::WikiLink { target, display }) {
+          edits.push((range, replacement));
+        }
+      }
+      Event::Start(Tag::Link { dest_url, .. } | Tag::Image { dest_url, .. })
+        if html::is_attachment_reference(&dest_url) =>
+      {
+        let Some(dest) = destination_range(content, range, &dest_url) else {
+          continue;
+        };
+        let reference = Reference::Attachment(html::percent_decode(&dest_url));
+        if let Some(replacement) = rewrite(&reference) {
+          edits.push((dest, replacement));
+        }
+      }
+      _ => {}
+    }
+  }
+
+  apply_edits(content, edits)
+}
+
+/// Finds the byte range of an inline link's destination within its source span.
+///
+/// Reference-style links have no inline destination and yield `None`.
+fn destination_range(
+  content: &str,
+  link: Range<usize>,
+  dest_url: &str,
+) -> Option<Range<usize>> {
+  let source = &content[link.clone()];
+  let open = source.rfind("](")? + 2;
+  let start = link.start + open + source[open..].find(dest_url)?;
+  Some(start..start + dest_url.len())
+}
+
+/// Applies non-overlapping edits; an edit overlapping an earlier one is dropped.
+fn apply_edits(
+  content: &str,
+  mut edits: Vec<(Range<usize>, String)>,
+) -> String {
+  edits.sort_by_key(|(range, _)| range.start);
+
+  let mut output = String::with_capacity(content.len());
+  let mut pos = 0;
+  for (range, replacement) in edits {
+    if range.start < pos {
+      continue;
+    }
+    output.push_str(&content[pos..range.start]);
+    output.push_str(&replacement);
+    pos = range.end;
+  }
+  output.push_str(&content[pos..]);
+  output
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_rewrites_wiki_links() {
+    let content = "See [[Other Note]] and [[Plans|the plan]], not `[[Code]]`.";
+    let rewritten = rewrite_references(content, |reference| match reference {
+      Reference::WikiLink { target, display } => {
+        Some(format!("[[x/{target}|{}]]", display.unwrap_or(target)))
+      }
+      Reference::Attachment(_) => None,
+    });
+    assert_eq!(
+      rewritten,
+      "See [[x/Other Note|Other Note]] and [[x/Plans|the plan]], not `[[Code]]`."
+    );
+  }
+
+  #[test]
+  fn test_rewrites_attachment_destinations_only() {
+    let content = "![diagram](my%20image.png \"Title\") [site](https://example.com) [[Note]]";
+    let mut seen = Vec::new();
+    let rewritten = rewrite_references(content, |reference| {
+      seen.push(reference.clone());
+      match reference {
+        Reference::Attachment(name) => Some(format!("assets/{}", name.replace(' ', "_"))),
+        Reference::WikiLink { .. } => None,
+      }
+    });
+    assert_eq!(
+      rewritten,
+      "![diagram](assets/my_image.png \"Title\") [site](https://example.com) [[Note]]"
+    );
+    assert_eq!(
+      seen,
+      vec![
+        Reference::Attachment("my image.png".to_string()),
+        Reference::WikiLink {
+          target: "Note",
+          display: None
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn test_image_inside_link() {
+    let content = "[![](a.png)](b.pdf)";
+    let rewritten = rewrite_references(content, |reference| match reference {
+      Reference::Attachment(name) => Some(format!("files/{name}")),
+      Reference::WikiLink { .. } => None,
+    });
+    assert_eq!(rewritten, "[![](files/a.png)](files/b.pdf)");
+  }
+}
@@ -0,0 +1,690 @@
+//! Static HTML site export.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::{ExportSummary, ExportedNote, RefusalReason, copy_attachment, encode_link_path};
+use crate::html::{escape_html, tag_path};
+use crate::{BearDb, BearError, HtmlOptions, Note, NoteId, NotesQuery, SearchQuery};
+
+/// Folders the site export owns and replaces on every run.
+const GENERATED_FOLDERS: [&str; 3] = ["notes", "tags", "attachments"];
+
+/// File marking a directory as a site written by `to_site()`.
+const SITE_MARKER: &str = ".bear-site";
+
+/// Stylesheet shared by every page.
+const STYLESHEET: &str = r"body {
+  font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', sans-serif;
+  line-height: 1.6;
+  max-width: 46rem;
+  margin: 2rem auto;
+  padding: 0 1rem;
+  color: #222;
+}
+nav { font-size: 0.9rem; margin-bottom: 2rem; }
+a { color: #d6453d; }
+.meta, .tags { color: #777; font-size: 0.9rem; }
+.tag { margin-right: 0.5rem; }
+.wiki-link.missing { color: #999; }
+mark { background: #fff3a8; }
+img { max-width: 100%; }
+pre { background: #f6f6f6; padding: 0.75rem; overflow-x: auto; }
+.backlinks { border-top: 1px solid #ddd; margin-top: 3rem; }
+";
+
+/// Which notes a site is built from.
+#[derive(Debug, Clone)]
+enum Selection {
+  All,
+  Tag(String),
+  Search(SearchQuery),
+}
+
+/// Options for `to_site()`.
+///
+/// By default the site contains every non-trashed, non-archived note.
+///
+/// # Example
+///
+/// ```no_run
+/// # use bear_query::SearchQuery;
+/// # use bear_query::export::SiteOptions;
+/// // Publish a tag and everything nested under it
+/// let options = SiteOptions::new().tag("work/handbook").title("Handbook");
+///
+/// // Or the results of a search
+/// let options = SiteOptions::new().search(SearchQuery::new("recipe").no_limit());
+/// ```
+#[derive(Debug, Clone)]
+pub struct SiteOptions {
+  selection: Selection,
+  title: String,
+}
+
+impl Default for SiteOptions {
+  fn default() -> Self {
+    Self {
+      selection: Selection::All,
+      title: "Notes".to_string(),
+    }
+  }
+}
+
+impl SiteOptions {
+  /// Creates site options with the defaults.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Builds the site from notes tagged with `tag` or any tag nested under it.
+  ///
+  /// Tags are matched case-insensitively, without the leading `#`.
+  pub fn tag(
+    mut self,
+    tag: impl Into<String>,
+  ) -> Self {
+    self.selection = Selection::Tag(tag.into());
+    self
+  }
+
+  /// Builds the site from the results of a search.
+  pub fn search(
+    mut self,
+    query: SearchQuery,
+  ) -> Self {
+    self.selection = Selection::Search(query);
+    self
+  }
+
+  /// Sets the site title shown on the index page and in navigation (default: "Notes").
+  pub fn title(
+    mut self,
+    title: impl Into<String>,
+  ) -> Self {
+    self.title = title.into();
+    self
+  }
+}
+
+/// One entry of `search-index.json`.
+#[derive(Serialize)]
+struct SearchEntry<'a> {
+  id: &'a NoteId,
+  title: &'a str,
+  url: String,
+  tags: Vec<&'a str>,
+  text: String,
+}
+
+/// A tag page: the tag's full name and the notes filed under it.
+#[derive(Default)]
+struct TagPage {
+  name: String,
+  notes: Vec<usize>,
+}
+
+/// Renders notes into a browsable static HTML site.
+///
+/// The site is written to `dir`:
+///
+/// - `index.html`: top-level tags and every note, sorted by title
+/// - `tags/<tag>/<subtag>/index.html`: one page per tag in the hierarchy, listing
+///   its nested tags and notes
+/// - `notes/<note id>.html`: one page per note, rendered with `Note::to_html()`,
+///   with its tags and a "Linked from" section built from `note_links`
+/// - `search-index.json`: `id`, `title`, `url`, `tags` and plain `text` of every
+///   note, for client-side search
+/// - `attachments/<attachment id>/<file name>`: attachments the notes reference
+/// - `style.css`
+///
+/// All links are relative, so the site works from any URL prefix or straight from
+/// disk. `[[Wiki links]]` to notes outside the site are rendered as plain text.
+///
+/// Output is deterministic: pages are keyed by note UUID rather than title, lists
+/// are sorted, and nothing depends on the time of the export, so re-exporting an
+/// unchanged library produces identical files. The `notes`, `tags` and
+/// `attachments` folders are replaced on every export, so pages of notes that left
+/// the selection disappear.
+///
+/// `dir` must be empty, missing or a previous site export, which is marked by a
+/// `.bear-site` file. Otherwise this fails with `BearError::ExportTargetRefused`
+/// rather than replace folders it didn't write.
+///
+/// # Example
+///
+/// ```no_run
+/// # use bear_query::BearDb;
+/// # use bear_query::export::{self, SiteOptions};
+/// # fn main() -> Result<(), bear_query::BearError> {
+/// let db = BearDb::new()?;
+/// let summary = export::to_site(&db, "site", SiteOptions::new().tag("blog").title("Blog"))?;
+/// println!("Published {} notes", summary.notes().len());
+/// # Ok(())
+/// # }
+/// ```
+pub fn to_site(
+  db: &BearDb,
+  dir: impl AsRef<Path>,
+  options: SiteOptions,
+) -> Result<ExportSummary, BearError> {
+  let dir = dir.as_ref();
+  let has_files = fs::read_dir(dir).is_ok_and(|mut entries| entries.next().is_some());
+  if has_files && !dir.join(SITE_MARKER).is_file() {
+    return Err(BearError::ExportTargetRefused {
+      path: dir.to_path_buf(),
+      reason: RefusalReason::ForeignFolder,
+    });
+  }
+
+  let indexed = db.note_tag_names()?;
+  let no_tags = BTreeSet::new();
+
+  let mut notes = match &options.selection {
+    Selection::Search(query) => db.search(query.clone())?,
+    Selection::All | Selection::Tag(_) => db.notes(NotesQuery::new().no_limit())?,
+  };
+
+  // Each note's tags, indexed or written inline, keyed by slugified path
+  let note_tags = |note: &Note| -> BTreeMap<String, String> {
+    let inline = note.inline_tags();
+    let names = indexed
+      .get(note.id())
+      .unwrap_or(&no_tags)
+      .iter()
+      .map(String::as_str)
+      .chain(inline.iter().flat_map(|tag| tag.with_ancestors()));
+
+    let mut tags = BTreeMap::new();
+    for name in names {
+      tags
+        .entry(tag_path(name))
+        .or_insert_with(|| name.to_string());
+    }
+    tags
+  };
+
+  if let Selection::Tag(tag) = &options.selection {
+    let wanted = tag_path(tag.trim_start_matches('#'));
+    notes.retain(|note| note_tags(note).contains_key(&wanted));
+  }
+  notes.sort_by(|a, b| (a.title().to_lowercase(), a.id()).cmp(&(b.title().to_lowercase(), b.id())));
+  let tags: Vec<BTreeMap<String, String>> = notes.iter().map(note_tags).collect();
+
+  let mut tag_pages: BTreeMap<String, TagPage> = BTreeMap::new();
+  for (index, note_tags) in tags.iter().enumerate() {
+    for (path, name) in note_tags {
+      let page = tag_pages.entry(path.clone()).or_default();
+      if page.name.is_empty() {
+        page.name = name.clone();
+      }
+      page.notes.push(index);
+    }
+  }
+
+  // Backlinks between notes in the site, sorted like the notes themselves
+  let positions: HashMap<&NoteId, usize> = notes
+    .iter()
+    .enumerate()
+    .map(|(index, note)| (note.id(), index))
+    .collect();
+  let mut backlinks: Vec<BTreeSet<usize>> = vec![BTreeSet::new(); notes.len()];
+  for (from, to) in db.note_link_ids()? {
+    if let (Some(&from), Some(&to)) = (positions.get(&from), positions.get(&to))
+      && from != to
+    {
+      backlinks[to].insert(from);
+    }
+  }
+
+  for folder in GENERATED_FOLDERS {
+    let path = dir.join(folder);
+    if path.is_dir() {
+      fs::remove_dir_all(path)?;
+    }
+  }
+  fs::create_dir_all(dir.join("notes"))?;
+  fs::write(
+    dir.join(SITE_MARKER),
+    "Written by bear-query; the notes, tags and attachments folders are replaced on every export\n",
+  )?;
+  fs::write(dir.join("style.css"), STYLESHEET)?;
+
+  let base_options = HtmlOptions::new()
+    .wiki_links_by_id(
+      "{}.html",
+      notes
+        .iter()
+        .map(|note| (note.title().to_string(), note.id().clone())),
+    )
+    .tag_links("../tags/{}/index.html");
+  let attachments = db.note_attachments()?;
+  let attachments_folder = Path::new("attachments");
+  let mut summary = ExportSummary::default();
+  let mut search_index = Vec::with_capacity(notes.len());
+
+  for (index, note) in notes.iter().enumerate() {
+    let mut urls: HashMap<String, String> = HashMap::new();
+    for attachment in attachments.get(note.id()).into_iter().flatten() {
+      match copy_attachment(db, attachment, dir, attachments_folder)? {
+        Some(target) => {
+          summary.attachments_copied += 1;
+          urls.insert(
+            attachment.filename().to_string(),
+            format!("../{}", encode_link_path(&target)),
+          );
+        }
+        None => summary.attachments_missing += 1,
+      }
+    }
+    let html_options = base_options
+      .clone()
+      .attachment_resolver(move |name| urls.get(name).cloned());
+
+    let path = PathBuf::from("notes").join(format!("{}.html", note.id().as_str()));
+    let page = note_page(
+      &options.title,
+      note,
+      &note.to_html(&html_options),
+      &tags[index],
+      backlinks[index].iter().map(|&from| &notes[from]),
+    );
+    fs::write(dir.join(&path), page)?;
+
+    search_index.push(SearchEntry {
+      id: note.id(),
+      title: note.title(),
+      url: format!("notes/{}.html", note.id().as_str()),
+      tags: tags[index].values().map(String::as_str).collect(),
+      text: note.plain_text(),
+    });
+    summary.notes.push(ExportedNote {
+      id: note.id().clone(),
+      path,
+    });
+  }
+
+  for (path, page) in &tag_pages {
+    let file = dir.join("tags").join(path).join("index.html");
+    fs::create_dir_all(file.parent().unwrap_or(dir))?;
+    fs::write(
+      file,
+      tag_page(&options.title, path, page, &tag_pages, &notes),
+    )?;
+  }
+
+  fs::write(
+    dir.join("index.html"),
+    index_page(&options.title, &tag_pages, &notes),
+  )?;
+
+  search_index.sort_by(|a, b| a.id.cmp(b.id));
+  let mut json = serde_json::to_vec_pretty(&search_index)?;
+  json.push(b'\n');
+  fs::write(dir.join("search-index.json"), json)?;
+
+  Ok(summary)
+}
+
+/// Wraps page content in the shared HTML skeleton.
+///
+/// `root` is the relative path from the page back to the site root.
+fn page(
+  title: &str,
+  root: &str,
+  body: &str,
+) -> String {
+  format!(
+    "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+     <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+     <title>{}</title>\n<link rel=\"stylesheet\" href=\"{root}style.css\">\n\
+     </head>\n<body>\n{body}</body>\n</html>\n",
+    escape_html(title)
+  )
+}
+
+/// Renders a list item linking to a note page.
+fn note_item(
+  root: &str,
+  note: &Note,
+) -> String {
+  let title = if note.title().is_empty() {
+    "Untitled"
+  } else {
+    note.title()
+  };
+  format!(
+    "<li><a href=\"{root}notes/{}.html\">{}</a></li>\n",
+    escape_html(note.id().as_str()),
+    escape_html(title)
+  )
+}
+
+/// Renders a list item linking to a tag page, labelled with the tag's last level.
+fn tag_item(
+  root: &str,
+  path: &str,
+  page: &TagPage,
+) -> String {
+  let label = page.name.rsplit('/').next().unwrap_or(&page.name);
+  format!(
+    "<li><a href=\"{root}tags/{path}/index.html\">#{}</a> ({})</li>\n",
+    escape_html(label),
+    page.notes.len()
+  )
+}
+
+fn index_page(
+  title: &str,
+  tag_pages: &BTreeMap<String, TagPage>,
+  notes: &[Note],
+) -> String {
+  let mut body = format!("<h1>{}</h1>\n", escape_html(title));
+
+  let top_level: Vec<(&String, &TagPage)> = tag_pages
+    .iter()
+    .filter(|(path, _)| !path.contains('/'))
+    .collect();
+  if !top_level.is_empty() {
+    body.push_str("<h2>Tags</h2>\n<ul>\n");
+    for (path, page) in top_level {
+      body.push_str(&tag_item("", path, page));
+    }
+    body.push_str("</ul>\n");
+  }
+
+  body.push_str("<h2>Notes</h2>\n<ul>\n");
+  for note in notes {
+    body.push_str(&note_item("", note));
+  }
+  body.push_str("</ul>\n");
+
+  page(title, "", &body)
+}
+
+fn tag_page(
+  site_title: &str,
+  path: &str,
+  tag: &TagPage,
+  tag_pages: &BTreeMap<String, TagPage>,
+  notes: &[Note],
+) -> String {
+  let root = "../".repeat(path.split('/').count() + 1);
+
+  // Breadcrumb through each ancestor tag
+  let mut nav = format!(
+    "<nav><a href=\"{root}index.html\">{}</a>",
+    escape_html(site_title)
+  );
+  let levels: Vec<&str> = path.split('/').collect();
+  for depth in 1..levels.len() {
+    let ancestor = levels[..depth].join("/");
+    if let Some(page) = tag_pages.get(&ancestor) {
+      let label = page.name.rsplit('/').next().unwrap_or(&page.name);
+      nav.push_str(&format!(
+        " / <a href=\"{root}tags/{ancestor}/index.html\">#{}</a>",
+        escape_html(label)
+      ));
+    }
+  }
+  nav.push_str("</nav>\n");
+
+  let mut body = nav;
+  body.push_str(&format!("<h1>#{}</h1>\n", escape_html(&tag.name)));
+
+  let prefix = format!("{path}/");
+  let children: Vec<(&String, &TagPage)> = tag_pages
+    .iter()
+    .filter(|(child, _)| {
+      child
+        .strip_prefix(&prefix)
+        .is_some_and(|rest| !rest.contains('/'))
+    })
+    .collect();
+  if !children.is_empty() {
+    body.push_str("<h2>Tags</h2>\n<ul>\n");
+    for (child, page) in children {
+      body.push_str(&tag_item(&root, child, page));
+    }
+    body.push_str("</ul>\n");
+  }
+
+  body.push_str("<h2>Notes</h2>\n<ul>\n");
+  for &index in &tag.notes {
+    body.push_str(&note_item(&root, &notes[index]));
+  }
+  body.push_str("</ul>\n");
+
+  page(&format!("#{} · {}", tag.name, site_title), &root, &body)
+}
+
+fn note_page<'a>(
+  site_title: &str,
+  note: &Note,
+  html: &str,
+  tags: &BTreeMap<String, String>,
+  backlinks: impl Iterator<Item = &'a Note>,
+) -> String {
+  let mut body = format!(
+    "<nav><a href=\"../index.html\">{}</a></nav>\n<article>\n",
+    escape_html(site_title)
+  );
+  body.push_str(&format!(
+    "<p class=\"meta\">Created {} · Modified {}</p>\n",
+    note.created().date(),
+    note.modified().date()
+  ));
+  if !tags.is_empty() {
+    body.push_str("<p class=\"tags\">");
+    for (path, name) in tags {
+      body.push_str(&format!(
+        "<a class=\"tag\" href=\"../tags/{path}/index.html\">#{}</a>",
+        escape_html(name)
+      ));
+    }
+    body.push_str("</p>\n");
+  }
+  body.push_str(html);
+  body.push_str("</article>\n");
+
+  let backlinks: String = backlinks.map(|from| note_item("../", from)).collect();
+  if !backlinks.is_empty() {
+    body.push_str("<section class=\"backlinks\">\n<h2>Linked from</h2>\n<ul>\n");
+    body.push_str(&backlinks);
+    body.push_str("</ul>\n</section>\n");
+  }
+
+  let title = if note.title().is_empty() {
+    site_title.to_string()
+  } else {
+    format!("{} · {}", note.title(), site_title)
+  };
+  page(&title, "../", &body)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::DatabasePath;
+  use crate::export::fixture_db;
+  use crate::html::tag_slug;
+
+  /// A small tagged, linked library with an attachment
+  const SITE_FIXTURE: &str = r"
+    INSERT INTO ZSFNOTE (Z_PK, ZUNIQUEIDENTIFIER, ZTITLE, ZTEXT, ZMODIFICATIONDATE, ZCREATIONDATE, ZPINNED, ZTRASHED, ZARCHIVED)
+    VALUES
+      (60, 'note-uuid-60', 'Handbook', '# Handbook
+#work/handbook
+Read [[Onboarding]] first, not [[Secret Plans]]. ![logo](logo.png)', 100, 100, 0, 0, 0),
+      (61, 'note-uuid-61', 'Onboarding', '# Onboarding
+#work/handbook/people
+Welcome <script>alert(1)</script>', 200, 200, 0, 0, 0),
+      (62, 'note-uuid-62', 'Secret Plans', '# Secret Plans
+#private', 300, 300, 0, 0, 0);
+
+    INSERT INTO ZSFNOTEBACKLINK (ZLINKEDBY, ZLINKINGTO)
+    VALUES
+      (60, 61),
+      (60, 62);
+
+    INSERT INTO ZSFNOTEFILE (Z_PK, ZUNIQUEIDENTIFIER, ZNOTE, ZFILENAME, ZFILESIZE, ZCREATIONDATE, ZMODIFICATIONDATE)
+    VALUES
+      (1, 'file-uuid-1', 60, 'logo.png', 3, 100, 100);
+  ";
+
+  fn site_db(files_root: &Path) -> BearDb {
+    fixture_db(SITE_FIXTURE, files_root, "logo.png")
+  }
+
+  fn read(
+    dir: &Path,
+    path: &str,
+  ) -> String {
+    fs::read_to_string(dir.join(path)).unwrap()
+  }
+
+  #[test]
+  fn test_tag_site() {
+    let files = tempfile::tempdir().unwrap();
+    let out = tempfile::tempdir().unwrap();
+    let db = site_db(files.path());
+
+    let summary = to_site(
+      &db,
+      out.path(),
+      SiteOptions::new().tag("#Work/Handbook").title("Handbook"),
+    )
+    .unwrap();
+    let ids: Vec<&str> = summary
+      .notes()
+      .iter()
+      .map(|note| note.id().as_str())
+      .collect();
+    assert_eq!(ids, vec!["note-uuid-60", "note-uuid-61"]);
+    assert_eq!(summary.attachments_copied(), 1);
+
+    let handbook = read(out.path(), "notes/note-uuid-60.html");
+    assert!(handbook.contains("<a class=\"wiki-link\" href=\"note-uuid-61.html\">Onboarding</a>"));
+    // Notes outside the site are not linked
+    assert!(handbook.contains("<span class=\"wiki-link missing\">Secret Plans</span>"));
+    assert!(handbook.contains("src=\"../attachments/file-uuid-1/logo.png\""));
+    assert!(handbook.contains("href=\"../tags/work/handbook/index.html\""));
+
+    let onboarding = read(out.path(), "notes/note-uuid-61.html");
+    assert!(onboarding.contains("<h2>Linked from</h2>"));
+    assert!(onboarding.contains("<a href=\"../notes/note-uuid-60.html\">Handbook</a>"));
+    assert!(!onboarding.contains("<script>"));
+
+    let tag = read(out.path(), "tags/work/handbook/index.html");
+    assert!(
+      tag.contains("<a href=\"../../../tags/work/handbook/people/index.html\">#people</a> (1)")
+    );
+    assert!(tag.contains("<a href=\"../../../notes/note-uuid-61.html\">Onboarding</a>"));
+    assert!(tag.contains(" / <a href=\"../../../tags/work/index.html\">#work</a>"));
+
+    let index = read(out.path(), "index.html");
+    assert!(index.contains("<a href=\"tags/work/index.html\">#work</a> (2)"));
+    assert!(!out.path().join("tags/private").exists());
+
+    let search: serde_json::Value =
+      serde_json::from_str(&read(out.path(), "search-index.json")).unwrap();
+    assert_eq!(search.as_array().unwrap().len(), 2);
+    assert_eq!(search[0]["url"], "notes/note-uuid-60.html");
+    assert_eq!(search[1]["text"], "Onboarding\n\nWelcome alert(1)");
+  }
+
+  #[test]
+  fn test_tags_with_matching_slugs_get_their_own_pages() {
+    const FIXTURE: &str = r"
+      INSERT INTO ZSFNOTE (Z_PK, ZUNIQUEIDENTIFIER, ZTITLE, ZTEXT, ZMODIFICATIONDATE, ZCREATIONDATE, ZPINNED, ZTRASHED, ZARCHIVED)
+      VALUES
+        (70, 'note-uuid-70', 'Party', '# Party
+#🎉', 100, 100, 0, 0, 0),
+        (71, 'note-uuid-71', 'Launch', '# Launch
+#🚀', 200, 200, 0, 0, 0),
+        (72, 'note-uuid-72', 'Templates', '# Templates
+#c++#', 300, 300, 0, 0, 0),
+        (73, 'note-uuid-73', 'Pointers', '# Pointers
+#c', 400, 400, 0, 0, 0);
+    ";
+    let db = BearDb::new_with_path(DatabasePath::InMemoryWith(FIXTURE)).unwrap();
+    let out = tempfile::tempdir().unwrap();
+    to_site(&db, out.path(), SiteOptions::new()).unwrap();
+
+    let slugs: BTreeSet<String> = ["🎉", "🚀", "c++", "c"].into_iter().map(tag_slug).collect();
+    assert_eq!(slugs.len(), 4, "{:?}", slugs);
+    for (tag, note) in [
+      ("🎉", "Party"),
+      ("🚀", "Launch"),
+      ("c++", "Templates"),
+      ("c", "Pointers"),
+    ] {
+      let page = read(out.path(), &format!("tags/{}/index.html", tag_slug(tag)));
+      assert!(page.contains(&format!(">{}</a>", note)), "{}", page);
+      assert_eq!(page.matches("notes/note-uuid-").count(), 1, "{}", page);
+    }
+  }
+
+  #[test]
+  fn test_site_output_is_deterministic() {
+    let files = tempfile::tempdir().unwrap();
+    let db = site_db(files.path());
+
+    let snapshot = |dir: &Path| -> BTreeMap<PathBuf, Vec<u8>> {
+      let mut files = BTreeMap::new();
+      let mut pending = vec![dir.to_path_buf()];
+      while let Some(folder) = pending.pop() {
+        for entry in fs::read_dir(folder).unwrap() {
+          let path = entry.unwrap().path();
+          if path.is_dir() {
+            pending.push(path);
+          } else {
+            let contents = fs::read(&path).unwrap();
+            files.insert(path.strip_prefix(dir).unwrap().to_path_buf(), contents);
+          }
+        }
+      }
+      files
+    };
+
+    let first = tempfile::tempdir().unwrap();
+    let second = tempfile::tempdir().unwrap();
+    to_site(&db, first.path(), SiteOptions::new()).unwrap();
+    to_site(&db, second.path(), SiteOptions::new()).unwrap();
+    assert_eq!(snapshot(first.path()), snapshot(second.path()));
+
+    // Re-exporting over an existing site gives the same result as a fresh one
+    to_site(&db, first.path(), SiteOptions::new().tag("private")).unwrap();
+    to_site(&db, first.path(), SiteOptions::new()).unwrap();
+    assert_eq!(snapshot(first.path()), snapshot(second.path()));
+  }
+
+  #[test]
+  fn test_refuses_folders_it_did_not_write() {
+    let files = tempfile::tempdir().unwrap();
+    let db = site_db(files.path());
+
+    let documents = tempfile::tempdir().unwrap();
+    let mine = documents.path().join("notes").join("mine.txt");
+    fs::create_dir_all(mine.parent().unwrap()).unwrap();
+    fs::write(&mine, b"keep me").unwrap();
+
+    let error = to_site(&db, documents.path(), SiteOptions::new()).unwrap_err();
+    assert!(matches!(
+      error,
+      BearError::ExportTargetRefused {
+        reason: RefusalReason::ForeignFolder,
+        ..
+      }
+    ));
+    assert_eq!(fs::read(&mine).unwrap(), b"keep me");
+    assert!(!documents.path().join("index.html").exists());
+
+    // A missing directory is created
+    let site = documents.path().join("site");
+    to_site(&db, &site, SiteOptions::new()).unwrap();
+    assert!(site.join(SITE_MARKER).is_file());
+  }
+}
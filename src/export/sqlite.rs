@@ -0,0 +1,625 @@
+//! Standalone normalized SQLite export.
+
+use rusqlite::types::Value;
+use rusqlite::{Connection, Transaction, params_from_iter};
+use std::collections::HashSet;
+use std::path::Path;
+
+use super::RefusalReason;
+use crate::{BearDb, BearError};
+
+/// Schema version of exported databases, stored in `PRAGMA user_version`.
+///
+/// Version 2 gave `notes` an explicit `rowid` column, so the full-text index keeps
+/// pointing at the right notes after a `VACUUM`.
+const SQLITE_SCHEMA_VERSION: i32 = 2;
+
+/// Tables, indexes and foreign keys of an exported database.
+const SCHEMA: &str = r"
+  CREATE TABLE IF NOT EXISTS notes (
+    rowid INTEGER PRIMARY KEY,
+    id TEXT NOT NULL UNIQUE,
+    title TEXT NOT NULL,
+    content TEXT,
+    modified TEXT NOT NULL,
+    created TEXT NOT NULL,
+    is_pinned INTEGER NOT NULL,
+    is_trashed INTEGER NOT NULL,
+    is_archived INTEGER NOT NULL
+  );
+  CREATE INDEX IF NOT EXISTS notes_modified ON notes (modified);
+  CREATE INDEX IF NOT EXISTS notes_created ON notes (created);
+
+  CREATE TABLE IF NOT EXISTS tags (
+    id INTEGER PRIMARY KEY,
+    name TEXT,
+    modified TEXT
+  );
+  CREATE INDEX IF NOT EXISTS tags_name ON tags (name);
+
+  CREATE TABLE IF NOT EXISTS note_tags (
+    note_id TEXT NOT NULL REFERENCES notes (id) ON DELETE CASCADE,
+    tag_id INTEGER NOT NULL REFERENCES tags (id) ON DELETE CASCADE,
+    PRIMARY KEY (note_id, tag_id)
+  ) WITHOUT ROWID;
+  CREATE INDEX IF NOT EXISTS note_tags_tag_id ON note_tags (tag_id);
+
+  CREATE TABLE IF NOT EXISTS note_links (
+    from_note_id TEXT NOT NULL REFERENCES notes (id) ON DELETE CASCADE,
+    to_note_id TEXT NOT NULL REFERENCES notes (id) ON DELETE CASCADE,
+    PRIMARY KEY (from_note_id, to_note_id)
+  ) WITHOUT ROWID;
+  CREATE INDEX IF NOT EXISTS note_links_to_note_id ON note_links (to_note_id);
+
+  CREATE TABLE IF NOT EXISTS attachments (
+    id TEXT PRIMARY KEY,
+    note_id TEXT NOT NULL REFERENCES notes (id) ON DELETE CASCADE,
+    filename TEXT,
+    size INTEGER,
+    modified TEXT,
+    created TEXT
+  );
+  CREATE INDEX IF NOT EXISTS attachments_note_id ON attachments (note_id);
+";
+
+/// Full-text index over note titles and content, kept in sync with `notes` by triggers.
+const FTS_SCHEMA: &str = r"
+  CREATE VIRTUAL TABLE notes_fts USING fts5 (
+    title,
+    content,
+    content = 'notes',
+    content_rowid = 'rowid'
+  );
+
+  CREATE TRIGGER notes_fts_insert AFTER INSERT ON notes BEGIN
+    INSERT INTO notes_fts (rowid, title, content) VALUES (new.rowid, new.title, new.content);
+  END;
+  CREATE TRIGGER notes_fts_delete AFTER DELETE ON notes BEGIN
+    INSERT INTO notes_fts (notes_fts, rowid, title, content)
+    VALUES ('delete', old.rowid, old.title, old.content);
+  END;
+  CREATE TRIGGER notes_fts_update AFTER UPDATE ON notes BEGIN
+    INSERT INTO notes_fts (notes_fts, rowid, title, content)
+    VALUES ('delete', old.rowid, old.title, old.content);
+    INSERT INTO notes_fts (rowid, title, content) VALUES (new.rowid, new.title, new.content);
+  END;
+
+  INSERT INTO notes_fts (notes_fts) VALUES ('rebuild');
+";
+
+/// Drops the tables of an export with an older schema, which is then rebuilt.
+const DROP_SCHEMA: &str = r"
+  DROP TABLE IF EXISTS attachments;
+  DROP TABLE IF EXISTS note_links;
+  DROP TABLE IF EXISTS note_tags;
+  DROP TABLE IF EXISTS tags;
+  DROP TABLE IF EXISTS notes;
+";
+
+const DROP_FTS: &str = r"
+  DROP TRIGGER IF EXISTS notes_fts_insert;
+  DROP TRIGGER IF EXISTS notes_fts_delete;
+  DROP TRIGGER IF EXISTS notes_fts_update;
+  DROP TABLE IF EXISTS notes_fts;
+";
+
+/// Options for `to_normalized_sqlite()`.
+///
+/// # Example
+///
+/// ```no_run
+/// # use bear_query::export::SqliteExportOptions;
+/// let options = SqliteExportOptions::new().full_text_search();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SqliteExportOptions {
+  full_text_search: bool,
+}
+
+impl SqliteExportOptions {
+  /// Creates SQLite export options with the defaults (no full-text index).
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Adds a `notes_fts` FTS5 table indexing note titles and content.
+  pub fn full_text_search(mut self) -> Self {
+    self.full_text_search = true;
+    self
+  }
+}
+
+/// What `to_normalized_sqlite()` changed in the target database.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SqliteExportSummary {
+  notes_inserted: usize,
+  notes_updated: usize,
+  notes_deleted: usize,
+  notes_unchanged: usize,
+}
+
+impl SqliteExportSummary {
+  /// Returns the number of notes added to the database.
+  pub fn notes_inserted(&self) -> usize {
+    self.notes_inserted
+  }
+
+  /// Returns the number of existing notes that changed and were updated.
+  pub fn notes_updated(&self) -> usize {
+    self.notes_updated
+  }
+
+  /// Returns the number of notes removed because they no longer exist in Bear.
+  pub fn notes_deleted(&self) -> usize {
+    self.notes_deleted
+  }
+
+  /// Returns the number of existing notes that were already up to date.
+  pub fn notes_unchanged(&self) -> usize {
+    self.notes_unchanged
+  }
+}
+
+/// Materializes the normalized views into a standalone SQLite database.
+///
+/// The database gets real `notes`, `tags`, `note_tags`, `note_links` and
+/// `attachments` tables with the same columns as the normalized views (minus the
+/// internal `core_db_id`), primary keys, foreign keys (`ON DELETE CASCADE`) and
+/// indexes on the columns used for joins and sorting. Every note is exported,
+/// including trashed and archived ones; filter on `is_trashed` and `is_archived`.
+/// Rows referencing notes or tags that no longer exist are dropped.
+///
+/// If `path` already holds an export, it is updated in place: only notes that
+/// changed are rewritten, notes deleted from Bear are removed, and the smaller
+/// tables are replaced. Exports written by an older version of this function are
+/// rebuilt from scratch. The whole update runs in one transaction, so readers never
+/// see a half-updated database.
+///
+/// With `SqliteExportOptions::full_text_search()`, a `notes_fts` FTS5 table (an
+/// external-content index on `notes`, keyed by its `rowid INTEGER PRIMARY KEY`
+/// column) is created and kept in sync by triggers.
+///
+/// Fails with `BearError::ExportTargetRefused` if `path` is Bear's own database or
+/// a SQLite database that was not created by this function.
+///
+/// # Example
+///
+/// ```no_run
+/// # use bear_query::BearDb;
+/// # use bear_query::export::{self, SqliteExportOptions};
+/// # fn main() -> Result<(), bear_query::BearError> {
+/// let db = BearDb::new()?;
+/// let summary = export::to_normalized_sqlite(
+///     &db,
+///     "bear.sqlite",
+///     SqliteExportOptions::new().full_text_search(),
+/// )?;
+/// println!(
+///     "{} new, {} updated, {} deleted",
+///     summary.notes_inserted(),
+///     summary.notes_updated(),
+///     summary.notes_deleted()
+/// );
+///
+/// // Later, in any SQLite client:
+/// // SELECT n.title FROM notes_fts JOIN notes n ON n.rowid = notes_fts.rowid
+/// // WHERE notes_fts MATCH 'roadmap' ORDER BY rank;
+/// # Ok(())
+/// # }
+/// ```
+pub fn to_normalized_sqlite(
+  db: &BearDb,
+  path: impl AsRef<Path>,
+  options: SqliteExportOptions,
+) -> Result<SqliteExportSummary, BearError> {
+  let path = path.as_ref();
+  if let Some(source) = db.database_file()
+    && same_file(source, path)
+  {
+    return Err(refuse(path, RefusalReason::BearDatabase));
+  }
+
+  let mut conn = Connection::open(path)?;
+  conn.pragma_update(None, "foreign_keys", "ON")?;
+  let version = check_export_version(&conn, path)?;
+
+  let tx = conn.transaction()?;
+  if version < SQLITE_SCHEMA_VERSION {
+    tx.execute_batch(DROP_FTS)?;
+    tx.execute_batch(DROP_SCHEMA)?;
+  }
+  tx.execute_batch(SCHEMA)?;
+  tx.pragma_update(None, "user_version", SQLITE_SCHEMA_VERSION)?;
+  sync_fts(&tx, options.full_text_search)?;
+
+  let summary = sync_notes(db, &tx)?;
+  replace_table(
+    &tx,
+    db,
+    "tags",
+    "SELECT id, name, modified FROM tags WHERE id IS NOT NULL",
+    "",
+  )?;
+  replace_table(
+    &tx,
+    db,
+    "note_tags",
+    "SELECT DISTINCT note_id, tag_id FROM note_tags",
+    "WHERE note_id IN (SELECT id FROM notes) AND tag_id IN (SELECT id FROM tags)",
+  )?;
+  replace_table(
+    &tx,
+    db,
+    "note_links",
+    "SELECT DISTINCT from_note_id, to_note_id FROM note_links",
+    "WHERE from_note_id IN (SELECT id FROM notes) AND to_note_id IN (SELECT id FROM notes)",
+  )?;
+  replace_table(
+    &tx,
+    db,
+    "attachments",
+    "SELECT id, note_id, filename, size, modified, created FROM attachments WHERE id IS NOT NULL",
+    "WHERE note_id IN (SELECT id FROM notes)",
+  )?;
+
+  tx.commit()?;
+  Ok(summary)
+}
+
+fn refuse(
+  path: &Path,
+  reason: RefusalReason,
+) -> BearError {
+  BearError::ExportTargetRefused {
+    path: path.to_path_buf(),
+    reason,
+  }
+}
+
+/// Returns true if both paths name the same existing file.
+fn same_file(
+  a: &Path,
+  b: &Path,
+) -> bool {
+  match (a.canonicalize(), b.canonicalize()) {
+    (Ok(a), Ok(b)) => a == b,
+    _ => false,
+  }
+}
+
+/// Rejects databases that weren't written by this exporter, or by a newer version of it.
+/// Returns the schema version of the export, 0 for an empty database.
+fn check_export_version(
+  conn: &Connection,
+  path: &Path,
+) -> Result<i32, BearError> {
+  let version: i32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+  let has_tables: bool = conn.query_row(
+    "SELECT EXISTS (SELECT 1 FROM sqlite_schema WHERE type = 'table')",
+    [],
+    |row| row.get(0),
+  )?;
+
+  if version > SQLITE_SCHEMA_VERSION {
+    return Err(refuse(path, RefusalReason::NewerVersion));
+  }
+  if version == 0 && has_tables {
+    return Err(refuse(path, RefusalReason::ForeignDatabase));
+  }
+  Ok(version)
+}
+
+/// Creates or drops the full-text index to match the options.
+fn sync_fts(
+  tx: &Transaction,
+  enabled: bool,
+) -> Result<(), BearError> {
+  let exists: bool = tx.query_row(
+    "SELECT EXISTS (SELECT 1 FROM sqlite_schema WHERE name = 'notes_fts')",
+    [],
+    |row| row.get(0),
+  )?;
+
+  match (enabled, exists) {
+    (true, false) => tx.execute_batch(FTS_SCHEMA)?,
+    (false, true) => tx.execute_batch(DROP_FTS)?,
+    _ => {}
+  }
+  Ok(())
+}
+
+/// Upserts changed notes and deletes notes that are gone from Bear.
+fn sync_notes(
+  db: &BearDb,
+  tx: &Transaction,
+) -> Result<SqliteExportSummary, BearError> {
+  let rows = db.raw_rows(
+    r"
+      SELECT id, title, content, modified, created, is_pinned, is_trashed, is_archived
+      FROM notes
+      WHERE id IS NOT NULL",
+  )?;
+
+  let existing: HashSet<String> = tx
+    .prepare("SELECT id FROM notes")?
+    .query_map([], |row| row.get(0))?
+    .collect::<rusqlite::Result<_>>()?;
+
+  let mut summary = SqliteExportSummary::default();
+  let mut seen: HashSet<String> = HashSet::with_capacity(rows.len());
+
+  {
+    // Only rows whose values differ are updated, so unchanged notes (and their
+    // full-text index entries) are left alone
+    let mut upsert = tx.prepare(
+      r"
+      INSERT INTO notes (id, title, content, modified, created, is_pinned, is_trashed, is_archived)
+      VALUES (?1, coalesce(?2, ''), ?3, ?4, ?5, coalesce(?6, 0), coalesce(?7, 0), coalesce(?8, 0))
+      ON CONFLICT (id) DO UPDATE SET
+        title = excluded.title,
+        content = excluded.content,
+        modified = excluded.modified,
+        created = excluded.created,
+        is_pinned = excluded.is_pinned,
+        is_trashed = excluded.is_trashed,
+        is_archived = excluded.is_archived
+      WHERE (title, content, modified, created, is_pinned, is_trashed, is_archived)
+        IS NOT (excluded.title, excluded.content, excluded.modified, excluded.created,
+                excluded.is_pinned, excluded.is_trashed, excluded.is_archived)",
+    )?;
+
+    for row in &rows {
+      let Value::Text(id) = &row[0] else {
+        continue;
+      };
+      if !seen.insert(id.clone()) {
+        continue;
+      }
+
+      let changed = upsert.execute(params_from_iter(row))? > 0;
+      match (existing.contains(id), changed) {
+        (false, _) => summary.notes_inserted += 1,
+        (true, true) => summary.notes_updated += 1,
+        (true, false) => summary.notes_unchanged += 1,
+      }
+    }
+  }
+
+  let mut delete = tx.prepare("DELETE FROM notes WHERE id = ?")?;
+  for id in existing.difference(&seen) {
+    delete.execute([id])?;
+    summary.notes_deleted += 1;
+  }
+
+  Ok(summary)
+}
+
+/// Replaces the contents of a table with rows read from Bear.
+///
+/// `cleanup` is a WHERE clause selecting the rows to keep once inserted, used to
+/// drop rows whose foreign keys point at missing notes or tags.
+fn replace_table(
+  tx: &Transaction,
+  db: &BearDb,
+  table: &str,
+  source_sql: &str,
+  cleanup: &str,
+) -> Result<(), BearError> {
+  let rows = db.raw_rows(source_sql)?;
+
+  // Foreign keys are checked per statement, so orphaned rows can't be inserted and
+  // filtered afterwards; stage them in a temporary table first
+  let staging = format!("staging_{table}");
+  tx.execute_batch(&format!(
+    "DROP TABLE IF EXISTS temp.{staging};
+     CREATE TEMP TABLE {staging} AS SELECT * FROM main.{table} WHERE 0;
+     DELETE FROM main.{table};"
+  ))?;
+
+  if let Some(first) = rows.first() {
+    let placeholders = vec!["?"; first.len()].join(", ");
+    let mut insert = tx.prepare(&format!(
+      "INSERT OR IGNORE INTO temp.{staging} VALUES ({placeholders})"
+    ))?;
+    for row in &rows {
+      insert.execute(params_from_iter(row))?;
+    }
+  }
+
+  tx.execute_batch(&format!(
+    "INSERT OR IGNORE INTO main.{table} SELECT * FROM temp.{staging} {cleanup};
+     DROP TABLE temp.{staging};"
+  ))?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::DatabasePath;
+
+  /// Rows the export must cope with: a link to a missing note and an attachment
+  const SQLITE_FIXTURE: &str = r"
+    INSERT INTO ZSFNOTEBACKLINK (ZLINKEDBY, ZLINKINGTO)
+    VALUES
+      (1, 99);
+
+    INSERT INTO ZSFNOTEFILE (Z_PK, ZUNIQUEIDENTIFIER, ZNOTE, ZFILENAME, ZFILESIZE, ZCREATIONDATE, ZMODIFICATIONDATE)
+    VALUES
+      (1, 'file-uuid-1', 2, 'photo.jpg', 2048, 0, 0);
+  ";
+
+  /// The same database after a round of edits
+  const EDITED_FIXTURE: &str = r"
+    UPDATE ZSFNOTE SET ZTEXT = 'Rewritten roadmap', ZMODIFICATIONDATE = 100 WHERE Z_PK = 1;
+    DELETE FROM ZSFNOTE WHERE Z_PK = 4;
+    INSERT INTO ZSFNOTE (Z_PK, ZUNIQUEIDENTIFIER, ZTITLE, ZTEXT, ZMODIFICATIONDATE, ZCREATIONDATE, ZPINNED, ZTRASHED, ZARCHIVED)
+    VALUES
+      (50, 'note-uuid-50', 'Fresh', 'A brand new roadmap', 200, 200, 0, 0, 0);
+  ";
+
+  fn count(
+    conn: &Connection,
+    sql: &str,
+  ) -> i64 {
+    conn.query_row(sql, [], |row| row.get(0)).unwrap()
+  }
+
+  #[test]
+  fn test_to_normalized_sqlite() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("bear.sqlite");
+    let db = BearDb::new_with_path(DatabasePath::InMemoryWith(SQLITE_FIXTURE)).unwrap();
+
+    let summary = to_normalized_sqlite(&db, &path, SqliteExportOptions::new()).unwrap();
+    assert_eq!(summary.notes_inserted(), 5);
+
+    let conn = Connection::open(&path).unwrap();
+    assert_eq!(count(&conn, "SELECT count(*) FROM notes"), 5);
+    assert_eq!(
+      count(&conn, "SELECT count(*) FROM notes WHERE is_trashed = 1"),
+      1
+    );
+    assert_eq!(count(&conn, "SELECT count(*) FROM tags"), 3);
+    assert_eq!(count(&conn, "SELECT count(*) FROM note_tags"), 2);
+    // The link to the missing note is dropped
+    assert_eq!(count(&conn, "SELECT count(*) FROM note_links"), 1);
+    assert_eq!(
+      count(
+        &conn,
+        "SELECT size FROM attachments WHERE note_id = 'note-uuid-2'"
+      ),
+      2048
+    );
+    assert_eq!(
+      count(&conn, "SELECT count(*) FROM pragma_foreign_key_check"),
+      0
+    );
+    assert!(count(&conn, "SELECT count(*) FROM pragma_index_list('note_tags')") >= 1);
+  }
+
+  #[test]
+  fn test_incremental_update_with_fts() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("bear.sqlite");
+    let options = SqliteExportOptions::new().full_text_search();
+
+    let db = BearDb::new_with_path(DatabasePath::InMemoryWith(SQLITE_FIXTURE)).unwrap();
+    to_normalized_sqlite(&db, &path, options.clone()).unwrap();
+
+    let again = to_normalized_sqlite(&db, &path, options.clone()).unwrap();
+    assert_eq!(again.notes_unchanged(), 5);
+    assert_eq!(again.notes_inserted() + again.notes_updated(), 0);
+
+    let edited = BearDb::new_with_path(DatabasePath::InMemoryWith(EDITED_FIXTURE)).unwrap();
+    let summary = to_normalized_sqlite(&edited, &path, options).unwrap();
+    assert_eq!(summary.notes_inserted(), 1);
+    assert_eq!(summary.notes_updated(), 1);
+    assert_eq!(summary.notes_deleted(), 1);
+    assert_eq!(summary.notes_unchanged(), 3);
+
+    let conn = Connection::open(&path).unwrap();
+    let matches: Vec<String> = conn
+      .prepare(
+        "SELECT n.id FROM notes_fts JOIN notes n ON n.rowid = notes_fts.rowid
+         WHERE notes_fts MATCH 'roadmap' ORDER BY n.id",
+      )
+      .unwrap()
+      .query_map([], |row| row.get(0))
+      .unwrap()
+      .collect::<rusqlite::Result<_>>()
+      .unwrap();
+    assert_eq!(matches, vec!["note-uuid-1", "note-uuid-50"]);
+
+    // The smaller tables mirror Bear's current state
+    assert_eq!(count(&conn, "SELECT count(*) FROM attachments"), 0);
+    assert_eq!(count(&conn, "SELECT count(*) FROM note_tags"), 2);
+
+    // The index still finds the right notes once VACUUM has closed the gap left by
+    // the deleted note
+    conn.execute_batch("VACUUM").unwrap();
+    let matched: String = conn
+      .query_row(
+        "SELECT n.id FROM notes_fts JOIN notes n ON n.rowid = notes_fts.rowid
+         WHERE notes_fts MATCH 'brand'",
+        [],
+        |row| row.get(0),
+      )
+      .unwrap();
+    assert_eq!(matched, "note-uuid-50");
+  }
+
+  #[test]
+  fn test_rebuilds_older_exports() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("bear.sqlite");
+    let old = Connection::open(&path).unwrap();
+    old
+      .execute_batch("CREATE TABLE notes (id TEXT PRIMARY KEY, title TEXT NOT NULL)")
+      .unwrap();
+    old.pragma_update(None, "user_version", 1).unwrap();
+    drop(old);
+
+    let db = BearDb::new_with_path(DatabasePath::InMemoryWith(SQLITE_FIXTURE)).unwrap();
+    let summary = to_normalized_sqlite(&db, &path, SqliteExportOptions::new()).unwrap();
+    assert_eq!(summary.notes_inserted(), 5);
+
+    let conn = Connection::open(&path).unwrap();
+    assert_eq!(
+      count(
+        &conn,
+        "SELECT count(*) FROM pragma_table_info('notes') WHERE pk = 1 AND name = 'rowid'"
+      ),
+      1
+    );
+  }
+
+  #[test]
+  fn test_refuses_foreign_databases() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("other.sqlite");
+    Connection::open(&path)
+      .unwrap()
+      .execute_batch("CREATE TABLE unrelated (x)")
+      .unwrap();
+
+    let db = BearDb::new_with_path(DatabasePath::InMemory).unwrap();
+    let err = to_normalized_sqlite(&db, &path, SqliteExportOptions::new()).unwrap_err();
+    assert!(matches!(
+      err,
+      BearError::ExportTargetRefused { path: refused, reason: RefusalReason::ForeignDatabase }
+        if refused == path
+    ));
+
+    let newer = dir.path().join("newer.sqlite");
+    Connection::open(&newer)
+      .unwrap()
+      .pragma_update(None, "user_version", SQLITE_SCHEMA_VERSION + 1)
+      .unwrap();
+    assert!(matches!(
+      to_normalized_sqlite(&db, &newer, SqliteExportOptions::new()),
+      Err(BearError::ExportTargetRefused {
+        reason: RefusalReason::NewerVersion,
+        ..
+      })
+    ));
+
+    // Bear's own database, whatever path it's given by
+    let bear = dir.path().join("database.sqlite");
+    crate::schema::setup_test_schema(&Connection::open(&bear).unwrap()).unwrap();
+    let db = BearDb::new_with_path(DatabasePath::RealPath(bear)).unwrap();
+    let err = to_normalized_sqlite(
+      &db,
+      dir.path().join(".").join("database.sqlite"),
+      SqliteExportOptions::new(),
+    )
+    .unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      format!(
+        "Refusing to export to {}: it is Bear's own database",
+        dir.path().join(".").join("database.sqlite").display()
+      )
+    );
+  }
+}
@@ -0,0 +1,167 @@
+//! Writing DataFrames to columnar and tabular file formats.
+//!
+//! Each format is behind its own cargo feature (`parquet`, `ipc`, `csv`), which
+//! enables the matching Polars writer.
+
+#[cfg(any(feature = "parquet", feature = "ipc", feature = "csv"))]
+use polars::prelude::*;
+#[cfg(any(feature = "parquet", feature = "ipc", feature = "csv"))]
+use std::io::Write;
+
+#[cfg(any(feature = "parquet", feature = "ipc", feature = "csv"))]
+use crate::BearError;
+
+/// File formats `BearDb::export_tables()` can write.
+///
+/// Each variant is only available with the cargo feature of the same name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableFormat {
+  /// Apache Parquet (feature `parquet`)
+  #[cfg(feature = "parquet")]
+  Parquet,
+  /// Arrow IPC file format, also known as Feather v2 (feature `ipc`)
+  #[cfg(feature = "ipc")]
+  Ipc,
+  /// Comma-separated values with a header row (feature `csv`)
+  #[cfg(feature = "csv")]
+  Csv,
+}
+
+impl TableFormat {
+  /// Returns the file extension used for this format, without the dot.
+  pub fn extension(self) -> &'static str {
+    match self {
+      #[cfg(feature = "parquet")]
+      TableFormat::Parquet => "parquet",
+      #[cfg(feature = "ipc")]
+      TableFormat::Ipc => "arrow",
+      #[cfg(feature = "csv")]
+      TableFormat::Csv => "csv",
+    }
+  }
+
+  /// Writes a DataFrame in this format.
+  #[cfg(any(feature = "parquet", feature = "ipc", feature = "csv"))]
+  pub(crate) fn write(
+    self,
+    df: &mut DataFrame,
+    writer: impl Write,
+  ) -> Result<(), BearError> {
+    match self {
+      #[cfg(feature = "parquet")]
+      TableFormat::Parquet => dataframe_to_parquet(df, writer),
+      #[cfg(feature = "ipc")]
+      TableFormat::Ipc => dataframe_to_ipc(df, writer),
+      #[cfg(feature = "csv")]
+      TableFormat::Csv => dataframe_to_csv(df, writer),
+    }
+  }
+}
+
+/// Writes a DataFrame as Parquet.
+///
+/// # Example
+///
+/// ```no_run
+/// # use bear_query::BearDb;
+/// # use bear_query::export;
+/// # fn main() -> Result<(), bear_query::BearError> {
+/// let db = BearDb::new()?;
+/// let mut df = db.query("SELECT title, word_count(content) AS words FROM notes")?;
+/// export::dataframe_to_parquet(&mut df, std::fs::File::create("words.parquet")?)?;
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(feature = "parquet")]
+pub fn dataframe_to_parquet(
+  df: &mut DataFrame,
+  writer: impl Write,
+) -> Result<(), BearError> {
+  ParquetWriter::new(writer).finish(df)?;
+  Ok(())
+}
+
+/// Writes a DataFrame in the Arrow IPC file format.
+#[cfg(feature = "ipc")]
+pub fn dataframe_to_ipc(
+  df: &mut DataFrame,
+  writer: impl Write,
+) -> Result<(), BearError> {
+  IpcWriter::new(writer).finish(df)?;
+  Ok(())
+}
+
+/// Writes a DataFrame as CSV with a header row.
+#[cfg(feature = "csv")]
+pub fn dataframe_to_csv(
+  df: &mut DataFrame,
+  writer: impl Write,
+) -> Result<(), BearError> {
+  CsvWriter::new(writer).include_header(true).finish(df)?;
+  Ok(())
+}
+
+#[cfg(all(test, feature = "parquet", feature = "ipc", feature = "csv"))]
+mod tests {
+  use super::*;
+  use crate::{BearDb, DatabasePath};
+  use std::fs;
+  use std::io::Cursor;
+
+  #[test]
+  fn test_dataframe_writers_round_trip() {
+    let db = BearDb::new_with_path(DatabasePath::InMemory).unwrap();
+    let mut df = db
+      .query("SELECT id, title, is_pinned FROM notes ORDER BY id")
+      .unwrap();
+
+    let mut parquet = Vec::new();
+    dataframe_to_parquet(&mut df, &mut parquet).unwrap();
+    let read = ParquetReader::new(Cursor::new(parquet)).finish().unwrap();
+    assert!(read.equals_missing(&df));
+
+    let mut ipc = Vec::new();
+    dataframe_to_ipc(&mut df, &mut ipc).unwrap();
+    let read = IpcReader::new(Cursor::new(ipc)).finish().unwrap();
+    assert!(read.equals_missing(&df));
+
+    let mut csv = Vec::new();
+    dataframe_to_csv(&mut df, &mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    assert!(csv.starts_with("id,title,is_pinned\nnote-uuid-1,First Note,false\n"));
+  }
+
+  #[test]
+  fn test_export_tables() {
+    let db = BearDb::new_with_path(DatabasePath::InMemory).unwrap();
+    let out = tempfile::tempdir().unwrap();
+
+    let written = db.export_tables(out.path(), TableFormat::Parquet).unwrap();
+    let names: Vec<String> = written
+      .iter()
+      .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+      .collect();
+    assert_eq!(
+      names,
+      vec![
+        "notes.parquet",
+        "tags.parquet",
+        "note_tags.parquet",
+        "note_links.parquet",
+        "attachments.parquet",
+      ]
+    );
+
+    let notes = ParquetReader::new(fs::File::open(&written[0]).unwrap())
+      .finish()
+      .unwrap();
+    assert_eq!(notes.height(), 5);
+
+    // Empty views are still written, with their columns
+    let attachments = ParquetReader::new(fs::File::open(&written[4]).unwrap())
+      .finish()
+      .unwrap();
+    assert_eq!(attachments.height(), 0);
+    assert_eq!(attachments.width(), 6);
+  }
+}
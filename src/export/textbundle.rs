@@ -0,0 +1,569 @@
+//! TextBundle and TextPack export.
+//!
+//! A TextBundle (<http://textbundle.org>) is a directory holding a Markdown
+//! document (`text.markdown`), a metadata file (`info.json`) and an `assets/`
+//! folder with the files the document references. A TextPack is the same bundle
+//! zipped into a single file. Bear imports and exports both formats.
+
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::manifest::{Manifest, ManifestEntry, content_hash};
+use super::rewrite::{Reference, rewrite_references};
+use super::{
+  ExportSummary, ExportedNote, RemovedNotes, UniquePaths, encode_link_path, is_claimed_variant,
+  remove_exported, rfc3339, sanitize_filename, set_modified,
+};
+use crate::{BearDb, BearError, Note, NotesQuery};
+
+/// TextBundle format version written to `info.json`.
+const TEXTBUNDLE_VERSION: u32 = 2;
+
+/// Options for `to_textbundles()`.
+///
+/// By default every non-trashed, non-archived note is written as a `.textbundle`
+/// directory.
+///
+/// # Example
+///
+/// ```no_run
+/// # use bear_query::NotesQuery;
+/// # use bear_query::export::TextBundleOptions;
+/// let options = TextBundleOptions::new().notes(NotesQuery::new().no_limit().include_all());
+/// ```
+#[derive(Debug, Clone)]
+pub struct TextBundleOptions {
+  query: NotesQuery,
+  packed: bool,
+  removed_notes: RemovedNotes,
+}
+
+impl Default for TextBundleOptions {
+  fn default() -> Self {
+    Self {
+      query: NotesQuery::new().no_limit(),
+      packed: false,
+      removed_notes: RemovedNotes::default(),
+    }
+  }
+}
+
+impl TextBundleOptions {
+  /// Creates TextBundle export options with the defaults.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Selects which notes to export (default: all non-trashed, non-archived notes).
+  pub fn notes(
+    mut self,
+    query: NotesQuery,
+  ) -> Self {
+    self.query = query;
+    self
+  }
+
+  /// Writes zipped `.textpack` files instead of `.textbundle` directories.
+  #[cfg(feature = "textpack")]
+  pub fn textpack(mut self) -> Self {
+    self.packed = true;
+    self
+  }
+
+  /// Sets what happens to bundles of previously exported notes that are no longer
+  /// exported (default: `RemovedNotes::MoveToTrash`).
+  pub fn removed_notes(
+    mut self,
+    mode: RemovedNotes,
+  ) -> Self {
+    self.removed_notes = mode;
+    self
+  }
+}
+
+/// Contents of `info.json`.
+///
+/// `creatorIdentifier` is optional and left out: these bundles weren't written by
+/// Bear, and bear-query has no bundle identifier of its own.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BundleInfo<'a> {
+  version: u32,
+  #[serde(rename = "type")]
+  kind: &'static str,
+  transient: bool,
+  #[serde(rename = "net.shinyfrog.bear")]
+  bear: BearInfo<'a>,
+}
+
+/// Bear-specific metadata stored in `info.json`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BearInfo<'a> {
+  unique_identifier: &'a str,
+  creation_date: String,
+  modification_date: String,
+  pinned: bool,
+  tags: &'a BTreeSet<String>,
+}
+
+/// A bundle ready to be written, with assets still on disk in Bear's storage.
+struct Bundle {
+  text: String,
+  info: Vec<u8>,
+  /// (file name inside `assets/`, source file)
+  assets: Vec<(String, PathBuf)>,
+}
+
+impl Bundle {
+  /// Hashes the bundle's text, metadata and asset names, recorded in the manifest.
+  /// Assets never change in place in Bear, so their names stand in for their contents.
+  fn hash(&self) -> String {
+    let mut contents = Vec::new();
+    contents.extend_from_slice(self.text.as_bytes());
+    contents.push(0);
+    contents.extend_from_slice(&self.info);
+    for (name, _) in &self.assets {
+      contents.push(0);
+      contents.extend_from_slice(name.as_bytes());
+    }
+    content_hash(&contents)
+  }
+}
+
+/// Exports each note as a TextBundle (`.textbundle` directory) or, with
+/// `TextBundleOptions::textpack()`, a zipped TextPack (`.textpack` file).
+///
+/// Each bundle contains:
+///
+/// - `text.markdown`: the note content, with attachment references pointing into `assets/`
+/// - `info.json`: TextBundle metadata plus Bear's own, under the `net.shinyfrog.bear`
+///   key: the note's `uniqueIdentifier`, `creationDate` and `modificationDate`
+///   (RFC 3339), `pinned` flag and `tags`
+/// - `assets/`: the attachments referenced by the note
+///
+/// Bundles are named after the note titles, made safe for file names and unique.
+/// Wiki links are kept as written, since Bear resolves them by title on import.
+/// Modification times of the written files are set to the notes' timestamps.
+///
+/// Exports are incremental, like `to_markdown_dir()`: bundles are only rewritten
+/// when their contents change, and bundles of notes no longer exported are removed
+/// as set by `TextBundleOptions::removed_notes()`. Only bundles recorded in the
+/// export's manifest are ever replaced or removed; a bundle already in the
+/// directory that an earlier export didn't write keeps its name, and the note gets
+/// a numbered one instead.
+///
+/// # Example
+///
+/// ```no_run
+/// # use bear_query::BearDb;
+/// # use bear_query::export::{self, TextBundleOptions};
+/// # fn main() -> Result<(), bear_query::BearError> {
+/// let db = BearDb::new()?;
+/// let summary = export::to_textbundles(&db, "backup", TextBundleOptions::new())?;
+/// println!("Wrote {} bundles", summary.notes().len());
+/// # Ok(())
+/// # }
+/// ```
+pub fn to_textbundles(
+  db: &BearDb,
+  dir: impl AsRef<Path>,
+  options: TextBundleOptions,
+) -> Result<ExportSummary, BearError> {
+  let dir = dir.as_ref();
+
+  // Oldest first, so name collisions resolve the same way on every export
+  let mut notes = db.notes(options.query.clone())?;
+  notes.sort_by(|a, b| (a.created(), a.id()).cmp(&(b.created(), b.id())));
+
+  let tags = db.note_tag_names()?;
+  let attachments = db.note_attachments()?;
+  let no_tags = BTreeSet::new();
+  let extension = if options.packed {
+    "textpack"
+  } else {
+    "textbundle"
+  };
+
+  // Bundles keep their previous path while the title still matches it. New paths
+  // skip anything in the directory that an earlier export didn't write, so bundles
+  // made by hand are never replaced
+  let previous = Manifest::load(dir)?;
+  let owned: HashSet<&Path> = previous
+    .entries()
+    .into_iter()
+    .map(|entry| entry.path.as_path())
+    .collect();
+  let stems: Vec<String> = notes
+    .iter()
+    .map(|note| sanitize_filename(note.title()))
+    .collect();
+  let mut unique = UniquePaths::default();
+  let kept: Vec<Option<PathBuf>> = notes
+    .iter()
+    .zip(&stems)
+    .map(|(note, stem)| {
+      previous
+        .get(note.id())
+        .map(|entry| entry.path.clone())
+        .filter(|path| {
+          is_claimed_variant(path, Path::new(""), stem, extension) && unique.reserve(path)
+        })
+    })
+    .collect();
+  let paths: Vec<PathBuf> = kept
+    .into_iter()
+    .zip(&stems)
+    .map(|(kept, stem)| {
+      kept.unwrap_or_else(|| {
+        loop {
+          let path = unique.claim(Path::new(""), stem, extension);
+          if owned.contains(path.as_path()) || !dir.join(&path).exists() {
+            break path;
+          }
+        }
+      })
+    })
+    .collect();
+
+  fs::create_dir_all(dir)?;
+  let mut summary = ExportSummary::default();
+
+  // Clear out bundles whose notes were removed or renamed before writing, so a
+  // renamed note taking over a removed note's path doesn't clobber it first
+  let new_paths: HashMap<&str, &Path> = notes
+    .iter()
+    .zip(&paths)
+    .map(|(note, path)| (note.id().as_str(), path.as_path()))
+    .collect();
+  for entry in previous.entries() {
+    let still_exported = new_paths.get(entry.id.as_str());
+    if still_exported == Some(&entry.path.as_path()) || !dir.join(&entry.path).exists() {
+      continue;
+    }
+    if still_exported.is_some() {
+      remove_exported(dir, &entry.path, RemovedNotes::Delete)?;
+    } else {
+      remove_exported(dir, &entry.path, options.removed_notes)?;
+      summary.removed.push(entry.path.clone());
+    }
+  }
+
+  let mut manifest = Vec::with_capacity(notes.len());
+
+  for (note, path) in notes.iter().zip(&paths) {
+    // Attachments the note has, keyed by the file name its content references
+    let mut sources: HashMap<&str, PathBuf> = HashMap::new();
+    for attachment in attachments.get(note.id()).into_iter().flatten() {
+      match db
+        .files_root()
+        .and_then(|root| attachment.source_path(root))
+      {
+        Some(source) => {
+          sources.insert(attachment.filename(), source);
+        }
+        None => summary.attachments_missing += 1,
+      }
+    }
+
+    let bundle = build_bundle(note, tags.get(note.id()).unwrap_or(&no_tags), &sources)?;
+    summary.attachments_copied += bundle.assets.len();
+
+    let entry = ManifestEntry {
+      id: note.id().as_str().to_string(),
+      hash: bundle.hash(),
+      modified: rfc3339(note.modified()),
+      path: path.clone(),
+    };
+
+    let target = dir.join(path);
+    match previous.get(note.id()) {
+      Some(last) if last.path == entry.path && last.hash == entry.hash && target.exists() => {
+        summary.unchanged += 1;
+      }
+      _ => match options.packed {
+        #[cfg(feature = "textpack")]
+        true => write_textpack(&target, &bundle, note)?,
+        _ => write_textbundle(&target, &bundle, note)?,
+      },
+    }
+
+    manifest.push(entry);
+    summary.notes.push(ExportedNote {
+      id: note.id().clone(),
+      path: path.clone(),
+    });
+  }
+
+  Manifest::save(dir, manifest)?;
+
+  Ok(summary)
+}
+
+/// Rewrites a note's attachment references into `assets/` and builds its metadata.
+fn build_bundle(
+  note: &Note,
+  tags: &BTreeSet<String>,
+  sources: &HashMap<&str, PathBuf>,
+) -> Result<Bundle, BearError> {
+  let mut assets: Vec<(String, PathBuf)> = Vec::new();
+  let text = rewrite_references(note.content().unwrap_or_default(), |reference| {
+    let Reference::Attachment(filename) = reference else {
+      return None;
+    };
+    let source = sources.get(filename.as_str())?;
+    // Keep only the final path component so assets can't escape the bundle
+    let name = Path::new(filename)
+      .file_name()?
+      .to_string_lossy()
+      .into_owned();
+    if !assets.iter().any(|(existing, _)| *existing == name) {
+      assets.push((name.clone(), source.clone()));
+    }
+    Some(encode_link_path(&format!("assets/{name}")))
+  });
+
+  let info = BundleInfo {
+    version: TEXTBUNDLE_VERSION,
+    kind: "net.daringfireball.markdown",
+    transient: false,
+    bear: BearInfo {
+      unique_identifier: note.id().as_str(),
+      creation_date: rfc3339(note.created()),
+      modification_date: rfc3339(note.modified()),
+      pinned: note.is_pinned(),
+      tags,
+    },
+  };
+
+  Ok(Bundle {
+    text,
+    info: serde_json::to_vec_pretty(&info)?,
+    assets,
+  })
+}
+
+/// Writes a bundle as a `.textbundle` directory, replacing the one a previous export wrote.
+fn write_textbundle(
+  path: &Path,
+  bundle: &Bundle,
+  note: &Note,
+) -> Result<(), BearError> {
+  if path.is_dir() {
+    fs::remove_dir_all(path)?;
+  }
+  fs::create_dir_all(path)?;
+
+  fs::write(path.join("text.markdown"), &bundle.text)?;
+  fs::write(path.join("info.json"), &bundle.info)?;
+  set_modified(&path.join("text.markdown"), note.modified())?;
+  set_modified(&path.join("info.json"), note.modified())?;
+
+  if !bundle.assets.is_empty() {
+    let assets = path.join("assets");
+    fs::create_dir_all(&assets)?;
+    for (name, source) in &bundle.assets {
+      fs::copy(source, assets.join(name))?;
+    }
+  }
+
+  Ok(())
+}
+
+/// Writes a bundle as a zipped `.textpack` file, replacing any previous one.
+///
+/// The archive holds a single `.textbundle` directory named like the TextPack.
+#[cfg(feature = "textpack")]
+fn write_textpack(
+  path: &Path,
+  bundle: &Bundle,
+  note: &Note,
+) -> Result<(), BearError> {
+  use std::io::{self, Write};
+  use zip::write::SimpleFileOptions;
+
+  let root = path.with_extension("textbundle");
+  let root = root
+    .file_name()
+    .map(|name| name.to_string_lossy().into_owned())
+    .unwrap_or_default();
+
+  let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+  let mut zip = zip::ZipWriter::new(fs::File::create(path)?);
+
+  let files: [(&str, &[u8]); 2] = [
+    ("text.markdown", bundle.text.as_bytes()),
+    ("info.json", &bundle.info),
+  ];
+  for (name, contents) in files {
+    zip
+      .start_file(format!("{root}/{name}"), options)
+      .map_err(io::Error::from)?;
+    zip.write_all(contents)?;
+  }
+  for (name, source) in &bundle.assets {
+    zip
+      .start_file(format!("{root}/assets/{name}"), options)
+      .map_err(io::Error::from)?;
+    io::copy(&mut fs::File::open(source)?, &mut zip)?;
+  }
+
+  zip.finish().map_err(io::Error::from)?;
+  set_modified(path, note.modified())?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::DatabasePath;
+  use crate::export::fixture_db;
+
+  /// A tagged, pinned note referencing one attachment that exists and one that doesn't
+  const BUNDLE_FIXTURE: &str = r"
+    INSERT INTO ZSFNOTE (Z_PK, ZUNIQUEIDENTIFIER, ZTITLE, ZTEXT, ZMODIFICATIONDATE, ZCREATIONDATE, ZPINNED, ZTRASHED, ZARCHIVED)
+    VALUES
+      (40, 'note-uuid-40', 'Trip/Plans', '# Trip/Plans
+#travel
+![map](map%20v2.png) and [ticket](ticket.pdf) with [[First Note]]', 200, 100, 1, 0, 0);
+
+    INSERT INTO ZSFNOTETAG (Z_PK, ZTITLE, ZMODIFICATIONDATE)
+    VALUES
+      (40, 'travel', 0);
+
+    INSERT INTO Z_5TAGS (Z_5NOTES, Z_13TAGS)
+    VALUES
+      (40, 40);
+
+    INSERT INTO ZSFNOTEFILE (Z_PK, ZUNIQUEIDENTIFIER, ZNOTE, ZFILENAME, ZFILESIZE, ZCREATIONDATE, ZMODIFICATIONDATE)
+    VALUES
+      (1, 'file-uuid-1', 40, 'map v2.png', 3, 100, 100),
+      (2, 'file-uuid-2', 40, 'ticket.pdf', 3, 100, 100);
+  ";
+
+  fn bundle_db(files_root: &Path) -> BearDb {
+    fixture_db(BUNDLE_FIXTURE, files_root, "map v2.png")
+  }
+
+  #[test]
+  fn test_to_textbundles() {
+    let files = tempfile::tempdir().unwrap();
+    let out = tempfile::tempdir().unwrap();
+    let db = bundle_db(files.path());
+
+    let summary = to_textbundles(&db, out.path(), TextBundleOptions::new()).unwrap();
+    assert_eq!(summary.notes().len(), 5);
+    assert_eq!(summary.attachments_copied(), 1);
+    assert_eq!(summary.attachments_missing(), 1);
+
+    let bundle = out.path().join("Trip Plans.textbundle");
+    let text = fs::read_to_string(bundle.join("text.markdown")).unwrap();
+    assert_eq!(
+      text,
+      "# Trip/Plans\n#travel\n![map](assets/map%20v2.png) and [ticket](ticket.pdf) with [[First Note]]"
+    );
+    assert_eq!(fs::read(bundle.join("assets/map v2.png")).unwrap(), b"png");
+
+    let info: serde_json::Value =
+      serde_json::from_slice(&fs::read(bundle.join("info.json")).unwrap()).unwrap();
+    assert_eq!(info["version"], 2);
+    assert_eq!(info["type"], "net.daringfireball.markdown");
+    assert!(info.get("creatorIdentifier").is_none());
+    let bear = &info["net.shinyfrog.bear"];
+    assert_eq!(bear["uniqueIdentifier"], "note-uuid-40");
+    assert_eq!(bear["creationDate"], "2001-01-01T00:01:40Z");
+    assert_eq!(bear["modificationDate"], "2001-01-01T00:03:20Z");
+    assert_eq!(bear["pinned"], true);
+    assert_eq!(bear["tags"], serde_json::json!(["travel"]));
+
+    // Notes without attachments get no assets folder
+    assert!(!out.path().join("First Note.textbundle/assets").exists());
+  }
+
+  #[test]
+  fn test_incremental_bundles() {
+    const EDITED_FIXTURE: &str = r"
+      UPDATE ZSFNOTE SET ZTITLE = 'Renamed', ZTEXT = 'Renamed content' WHERE Z_PK = 2;
+      UPDATE ZSFNOTE SET ZTRASHED = 1 WHERE Z_PK = 5;
+    ";
+    let out = tempfile::tempdir().unwrap();
+
+    // A bundle made by hand, with the same name as a note
+    let handmade = out.path().join("First Note.textbundle");
+    fs::create_dir_all(&handmade).unwrap();
+    fs::write(handmade.join("text.markdown"), "mine").unwrap();
+
+    let db = BearDb::new_with_path(DatabasePath::InMemory).unwrap();
+    let first = to_textbundles(&db, out.path(), TextBundleOptions::new()).unwrap();
+    assert_eq!(first.notes_written(), 4);
+    assert!(
+      out
+        .path()
+        .join("First Note 2.textbundle/info.json")
+        .is_file()
+    );
+    assert_eq!(
+      fs::read_to_string(handmade.join("text.markdown")).unwrap(),
+      "mine"
+    );
+
+    let again = to_textbundles(&db, out.path(), TextBundleOptions::new()).unwrap();
+    assert_eq!(again.notes_unchanged(), 4);
+    assert!(again.removed().is_empty());
+
+    let edited = BearDb::new_with_path(DatabasePath::InMemoryWith(EDITED_FIXTURE)).unwrap();
+    let summary = to_textbundles(&edited, out.path(), TextBundleOptions::new()).unwrap();
+    assert_eq!(summary.notes_written(), 1);
+    assert_eq!(summary.notes_unchanged(), 2);
+    assert_eq!(summary.removed(), [PathBuf::from("Empty Note.textbundle")]);
+    assert!(!out.path().join("Second Note.textbundle").exists());
+    assert!(
+      out
+        .path()
+        .join("Renamed.textbundle/text.markdown")
+        .is_file()
+    );
+    assert!(
+      out
+        .path()
+        .join("_trash/Empty Note.textbundle/info.json")
+        .is_file()
+    );
+    assert!(handmade.join("text.markdown").is_file());
+  }
+
+  #[cfg(feature = "textpack")]
+  #[test]
+  fn test_to_textpacks() {
+    use std::io::Read;
+
+    let files = tempfile::tempdir().unwrap();
+    let out = tempfile::tempdir().unwrap();
+    let db = bundle_db(files.path());
+
+    to_textbundles(&db, out.path(), TextBundleOptions::new().textpack()).unwrap();
+
+    let pack = fs::File::open(out.path().join("Trip Plans.textpack")).unwrap();
+    let mut archive = zip::ZipArchive::new(pack).unwrap();
+    let mut names: Vec<String> = archive.file_names().map(str::to_string).collect();
+    names.sort();
+    assert_eq!(
+      names,
+      vec![
+        "Trip Plans.textbundle/assets/map v2.png",
+        "Trip Plans.textbundle/info.json",
+        "Trip Plans.textbundle/text.markdown",
+      ]
+    );
+
+    let mut text = String::new();
+    archive
+      .by_name("Trip Plans.textbundle/text.markdown")
+      .unwrap()
+      .read_to_string(&mut text)
+      .unwrap();
+    assert!(text.contains("![map](assets/map%20v2.png)"));
+  }
+}
@@ -0,0 +1,178 @@
+//! Custom SQL functions available to every query.
+//!
+//! These are registered on each short-lived connection so they can be used from the
+//! typed API and from generic `query()` SQL alike:
+//!
+//! | Function | Returns | Description |
+//! |----------|---------|-------------|
+//! | `plain_text(content)` | TEXT | Note content without Markdown syntax or tags |
+//! | `word_count(content)` | INTEGER | Unicode word count of `plain_text(content)` |
+//! | `local_date(timestamp[, zone])` | TEXT | `YYYY-MM-DD` date of the timestamp in a time zone |
+//! | `local_datetime(timestamp[, zone])` | TEXT | `YYYY-MM-DD HH:MM:SS.SSS` time of the timestamp in a time zone |
+//!
+//! Timestamps are SQLite datetime text (as in the normalized views), RFC 3339 text or
+//! Unix seconds. Without a `zone` argument (`UTC`, an offset like `-08:00` or an IANA
+//! name), local dates use the zone of
+//! [`BearDb::with_time_zone()`](crate::BearDb::with_time_zone).
+//!
+//! All functions return NULL for NULL input.
+
+use rusqlite::Connection;
+use rusqlite::functions::{Context, FunctionFlags};
+use rusqlite::types::ValueRef;
+use time::OffsetDateTime;
+use time::format_description::FormatItem;
+use time::macros::format_description;
+
+use crate::timezone::TimeZone;
+use crate::{datetime, plain_text};
+
+/// Format of `local_datetime()`, with milliseconds like the normalized views
+const LOCAL_DATETIME: &[FormatItem<'static>] =
+  format_description!("[year]-[month]-[day] [hour]:[minute]:[second].[subsecond digits:3]");
+
+/// Registers the custom SQL functions on a connection.
+pub(crate) fn register(conn: &Connection) -> rusqlite::Result<()> {
+  let flags = FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC;
+
+  conn.create_scalar_function("plain_text", 1, flags, |ctx| {
+    let content: Option<String> = ctx.get(0)?;
+    Ok(content.map(|content| plain_text::plain_text(&content)))
+  })?;
+
+  conn.create_scalar_function("word_count", 1, flags, |ctx| {
+    let content: Option<String> = ctx.get(0)?;
+    Ok(content.map(|content| plain_text::word_count(&plain_text::plain_text(&content)) as i64))
+  })?;
+
+  Ok(())
+}
+
+/// Registers the functions returning local dates, in `time_zone` unless a call names
+/// its zone.
+pub(crate) fn register_time_zone(
+  conn: &Connection,
+  time_zone: TimeZone,
+) -> rusqlite::Result<()> {
+  let flags = FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC;
+
+  for arguments in [1, 2] {
+    conn.create_scalar_function("local_date", arguments, flags, move |ctx| {
+      local_time(ctx, time_zone)?
+        .map(|local| local.date().to_string())
+        .map(Ok)
+        .transpose()
+    })?;
+
+    conn.create_scalar_function("local_datetime", arguments, flags, move |ctx| {
+      local_time(ctx, time_zone)?
+        .map(|local| local.format(LOCAL_DATETIME))
+        .transpose()
+        .map_err(|error| rusqlite::Error::UserFunctionError(error.into()))
+    })?;
+  }
+
+  Ok(())
+}
+
+/// Reads a call's timestamp and converts it to its zone, or to `time_zone` without a
+/// zone argument
+fn local_time(
+  ctx: &Context,
+  time_zone: TimeZone,
+) -> rusqlite::Result<Option<OffsetDateTime>> {
+  let instant = match ctx.get_raw(0) {
+    ValueRef::Null => return Ok(None),
+    ValueRef::Integer(seconds) => OffsetDateTime::from_unix_timestamp(seconds).ok(),
+    ValueRef::Real(seconds) if seconds.is_finite() => {
+      OffsetDateTime::from_unix_timestamp_nanos((seconds * 1e9) as i128).ok()
+    }
+    ValueRef::Text(text) => std::str::from_utf8(text)
+      .ok()
+      .and_then(datetime::parse_text),
+    _ => None,
+  }
+  .ok_or_else(|| {
+    rusqlite::Error::UserFunctionError(format!("not a timestamp: {:?}", ctx.get_raw(0)).into())
+  })?;
+
+  let time_zone = if ctx.len() > 1 {
+    let name: String = ctx.get(1)?;
+    name
+      .parse()
+      .map_err(|error: crate::BearError| rusqlite::Error::UserFunctionError(error.into()))?
+  } else {
+    time_zone
+  };
+
+  Ok(Some(time_zone.to_local(instant)))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_sql_functions() {
+    let conn = Connection::open_in_memory().unwrap();
+    register(&conn).unwrap();
+
+    let (text, words): (String, i64) = conn
+      .query_row(
+        "SELECT plain_text('# Hi **there** #tag'), word_count('# Hi **there** #tag')",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+      )
+      .unwrap();
+    assert_eq!(text, "Hi there");
+    assert_eq!(words, 2);
+
+    let words: Option<i64> = conn
+      .query_row("SELECT word_count(NULL)", [], |row| row.get(0))
+      .unwrap();
+    assert_eq!(words, None);
+  }
+
+  #[test]
+  fn test_local_dates() {
+    let conn = Connection::open_in_memory().unwrap();
+    register_time_zone(&conn, TimeZone::named("America/Los_Angeles").unwrap()).unwrap();
+
+    // 05:30 UTC on March 2nd is the evening of March 1st in California
+    let (date, time, utc, offset): (String, String, String, String) = conn
+      .query_row(
+        r"
+        SELECT
+          local_date('2024-03-02 05:30:00.250'),
+          local_datetime('2024-03-02 05:30:00.250'),
+          local_date('2024-03-02 05:30:00', 'UTC'),
+          local_datetime(1709357400, '+08:00')
+        ",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+      )
+      .unwrap();
+    assert_eq!(date, "2024-03-01");
+    assert_eq!(time, "2024-03-01 21:30:00.250");
+    assert_eq!(utc, "2024-03-02");
+    assert_eq!(offset, "2024-03-02 13:30:00.000");
+
+    let date: Option<String> = conn
+      .query_row("SELECT local_date(NULL)", [], |row| row.get(0))
+      .unwrap();
+    assert_eq!(date, None);
+
+    for sql in [
+      "SELECT local_date('yesterday')",
+      "SELECT local_date('2024-03-02', 'Mars/Olympus_Mons')",
+    ] {
+      assert!(
+        conn
+          .query_row(sql, [], |row| row.get::<_, String>(0))
+          .is_err(),
+        "{}",
+        sql
+      );
+    }
+  }
+}
@@ -0,0 +1,397 @@
+//! Inline hashtag extraction and reconciliation with Bear's tag index.
+//!
+//! Bear keeps tags in two places: inline in the note text (`#tag`, `#nested/tag`,
+//! `#tag with spaces#`) and in its tag tables (`ZSFNOTETAG` plus the numbered
+//! junction table, exposed here as `tags` and `note_tags`). The two should always
+//! agree; this module extracts the inline tags so callers can spot index drift.
+//!
+//! # Tag syntax
+//!
+//! - `#tag` starts at the beginning of a line or after whitespace and runs until
+//!   whitespace or punctuation. Trailing `.`, `/` and `'` are dropped.
+//! - `#nested/tag` uses `/` to separate levels of the hierarchy.
+//! - `#multi word tag#` is closed by a second `#` on the same line.
+//! - `\#escaped`, `# Headings`, `##`, `foo#bar`, URL fragments, and anything inside
+//!   code blocks, inline code, links or raw HTML are not tags.
+//! - Purely numeric tags such as `#123` are ignored, as Bear does.
+
+use pulldown_cmark::{Event, Options, Parser, Tag};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
+
+use crate::NoteId;
+
+/// A tag written inline in a note's content.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InlineTag {
+  name: String,
+  range: Range<usize>,
+}
+
+impl InlineTag {
+  /// Returns the tag name without the surrounding `#` markers.
+  ///
+  /// Nested tags keep their `/` separators, e.g. `"work/projects"`.
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  /// Returns the byte range of the tag, including its `#` markers, within the note content.
+  pub fn range(&self) -> Range<usize> {
+    self.range.clone()
+  }
+
+  /// Returns this tag's name followed by the names of all its ancestors.
+  ///
+  /// For `"work/projects/bear"` this yields `"work/projects/bear"`, `"work/projects"`
+  /// and `"work"`. Bear indexes a note under every ancestor of a nested tag.
+  pub fn with_ancestors(&self) -> impl Iterator<Item = &str> {
+    let name = self.name.as_str();
+    std::iter::once(name).chain(
+      name
+        .char_indices()
+        .rev()
+        .filter(|(_, c)| *c == '/')
+        .map(move |(i, _)| &name[..i]),
+    )
+  }
+}
+
+/// Punctuation that ends a tag.
+const TAG_TERMINATORS: &str = "#,;:!?()[]{}<>\"`*=|\\~";
+
+/// Characters that can never be part of a tag.
+fn is_tag_terminator(c: char) -> bool {
+  c.is_whitespace() || TAG_TERMINATORS.contains(c)
+}
+
+/// Returns true when a `#` at `pos` can start a tag (start of line or after whitespace).
+fn at_tag_boundary(
+  content: &str,
+  pos: usize,
+) -> bool {
+  content[..pos]
+    .chars()
+    .next_back()
+    .is_none_or(char::is_whitespace)
+}
+
+/// Ranges of content that never contain tags: code, links and raw HTML.
+fn excluded_ranges(content: &str) -> Vec<Range<usize>> {
+  let mut ranges = Vec::new();
+
+  for (event, range) in Parser::new_ext(content, Options::empty()).into_offset_iter() {
+    match event {
+      Event::Start(Tag::CodeBlock(_))
+      | Event::Start(Tag::Link { .. })
+      | Event::Start(Tag::Image { .. })
+      | Event::Start(Tag::HtmlBlock)
+      | Event::Code(_)
+      | Event::InlineHtml(_)
+      | Event::Html(_) => ranges.push(range),
+      _ => {}
+    }
+  }
+
+  ranges
+}
+
+/// Parses a tag whose `#` is at `start`, returning the name and the end offset.
+fn parse_tag(
+  content: &str,
+  start: usize,
+) -> Option<(String, usize)> {
+  let body_start = start + 1;
+  let rest = &content[body_start..];
+  let first = rest.chars().next()?;
+  if is_tag_terminator(first) {
+    return None;
+  }
+
+  let line = &rest[..rest.find('\n').unwrap_or(rest.len())];
+
+  // Multi-word tags are closed by a `#` on the same line, with no space before it and
+  // whitespace, punctuation or the end of the line after it
+  for (close, _) in line.match_indices('#') {
+    let candidate = &line[..close];
+    let closes = line[close + 1..]
+      .chars()
+      .next()
+      .is_none_or(|c| c.is_whitespace() || c.is_ascii_punctuation());
+    let valid = closes
+      && !candidate.ends_with(char::is_whitespace)
+      && candidate.chars().all(|c| c == ' ' || !is_tag_terminator(c));
+    if valid {
+      return finish_tag(candidate, body_start + close + 1);
+    }
+  }
+
+  let len = line.find(is_tag_terminator).unwrap_or(line.len());
+  let name = line[..len].trim_end_matches(['.', '/', '\'']);
+  finish_tag(name, body_start + name.len())
+}
+
+/// Validates a parsed tag name.
+fn finish_tag(
+  name: &str,
+  end: usize,
+) -> Option<(String, usize)> {
+  if name.is_empty() || name.chars().all(|c| c.is_ascii_digit()) {
+    return None;
+  }
+  Some((name.to_string(), end))
+}
+
+/// Extracts all inline tags from a Markdown document, in order of appearance.
+pub(crate) fn extract(content: &str) -> Vec<InlineTag> {
+  let excluded = excluded_ranges(content);
+  let mut tags = Vec::new();
+  let mut pos = 0;
+
+  while let Some(offset) = content[pos..].find('#') {
+    let start = pos + offset;
+
+    if let Some(skip) = excluded.iter().find(|r| r.contains(&start)) {
+      pos = skip.end;
+      continue;
+    }
+
+    match at_tag_boundary(content, start)
+      .then(|| parse_tag(content, start))
+      .flatten()
+    {
+      Some((name, end)) => {
+        tags.push(InlineTag {
+          name,
+          range: start..end,
+        });
+        pos = end;
+      }
+      None => {
+        // Skip runs of `#` so `## Heading` isn't re-examined as `# Heading`
+        pos = start + content[start..].len() - content[start..].trim_start_matches('#').len();
+      }
+    }
+  }
+
+  tags
+}
+
+/// Tag drift found for a single note.
+///
+/// Tag names are compared case-insensitively and reported in lowercase.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NoteTagDrift {
+  note_id: NoteId,
+  title: String,
+  missing_from_index: BTreeSet<String>,
+  missing_from_content: BTreeSet<String>,
+}
+
+impl NoteTagDrift {
+  /// Returns the affected note's identifier.
+  pub fn note_id(&self) -> &NoteId {
+    &self.note_id
+  }
+
+  /// Returns the affected note's title.
+  pub fn title(&self) -> &str {
+    &self.title
+  }
+
+  /// Tags written in the note (or ancestors of them) with no `note_tags` row.
+  pub fn missing_from_index(&self) -> &BTreeSet<String> {
+    &self.missing_from_index
+  }
+
+  /// Tags linked in `note_tags` that do not appear in the note's content.
+  pub fn missing_from_content(&self) -> &BTreeSet<String> {
+    &self.missing_from_content
+  }
+}
+
+/// Result of comparing inline tags against Bear's `note_tags` index.
+///
+/// Returned by `BearDb::tag_consistency()`.
+///
+/// # Example
+///
+/// ```no_run
+/// # use bear_query::{BearDb, NotesQuery};
+/// # fn main() -> Result<(), bear_query::BearError> {
+/// let db = BearDb::new()?;
+/// let report = db.tag_consistency(NotesQuery::new().no_limit())?;
+///
+/// println!("Checked {} notes", report.notes_checked());
+/// for drift in report.drift() {
+///     println!("{}", drift.title());
+///     for tag in drift.missing_from_index() {
+///         println!("  #{} is written but not indexed", tag);
+///     }
+///     for tag in drift.missing_from_content() {
+///         println!("  #{} is indexed but not written", tag);
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct TagConsistencyReport {
+  notes_checked: usize,
+  drift: Vec<NoteTagDrift>,
+}
+
+impl TagConsistencyReport {
+  /// Returns the number of notes that were compared.
+  pub fn notes_checked(&self) -> usize {
+    self.notes_checked
+  }
+
+  /// Returns the notes whose inline tags and index rows disagree.
+  pub fn drift(&self) -> &[NoteTagDrift] {
+    &self.drift
+  }
+
+  /// Returns true when every checked note's inline tags match its index rows.
+  pub fn is_consistent(&self) -> bool {
+    self.drift.is_empty()
+  }
+}
+
+/// Builds a consistency report from note contents and their indexed tag names.
+pub(crate) fn reconcile<'a>(
+  notes: impl IntoIterator<Item = (&'a NoteId, &'a str, Option<&'a str>)>,
+  indexed: &BTreeMap<NoteId, BTreeSet<String>>,
+) -> TagConsistencyReport {
+  let empty = BTreeSet::new();
+  let mut report = TagConsistencyReport::default();
+
+  for (note_id, title, content) in notes {
+    report.notes_checked += 1;
+
+    let inline: BTreeSet<String> = extract(content.unwrap_or_default())
+      .iter()
+      .flat_map(|tag| tag.with_ancestors().map(str::to_lowercase))
+      .collect();
+    let indexed: BTreeSet<String> = indexed
+      .get(note_id)
+      .unwrap_or(&empty)
+      .iter()
+      .map(|name| name.to_lowercase())
+      .collect();
+
+    let missing_from_index: BTreeSet<String> = inline.difference(&indexed).cloned().collect();
+    let missing_from_content: BTreeSet<String> = indexed.difference(&inline).cloned().collect();
+
+    if !missing_from_index.is_empty() || !missing_from_content.is_empty() {
+      report.drift.push(NoteTagDrift {
+        note_id: note_id.clone(),
+        title: title.to_string(),
+        missing_from_index,
+        missing_from_content,
+      });
+    }
+  }
+
+  report
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn names(content: &str) -> Vec<String> {
+    extract(content)
+      .into_iter()
+      .map(|tag| tag.name().to_string())
+      .collect()
+  }
+
+  #[test]
+  fn test_simple_and_nested_tags() {
+    assert_eq!(
+      names("#work notes about #work/projects/bear-query."),
+      vec!["work", "work/projects/bear-query"]
+    );
+  }
+
+  #[test]
+  fn test_multi_word_tags() {
+    assert_eq!(names("#tag with spaces# and more"), vec!["tag with spaces"]);
+    assert_eq!(names("#one two #three"), vec!["one", "three"]);
+    // A `#` inside a word doesn't close a tag
+    assert_eq!(names("#todo see issue#42"), vec!["todo"]);
+    assert_eq!(names("#two words#, then text"), vec!["two words"]);
+  }
+
+  #[test]
+  fn test_headings_are_not_tags() {
+    assert_eq!(
+      names("# Title\n## Section #inline\n#start"),
+      vec!["inline", "start"]
+    );
+  }
+
+  #[test]
+  fn test_escapes_urls_and_numbers_are_not_tags() {
+    assert!(names(r"\#escaped foo#bar issue #123").is_empty());
+    assert!(names("see https://example.com/page#section").is_empty());
+    assert!(names("see <https://example.com/#frag> and [link](#anchor)").is_empty());
+  }
+
+  #[test]
+  fn test_code_is_not_tagged() {
+    let content = "`#inline` code\n\n```\n#fenced\n```\n\n    #indented\n\n#real";
+    assert_eq!(names(content), vec!["real"]);
+  }
+
+  #[test]
+  fn test_tag_ranges() {
+    let content = "text #tag with spaces# and #nested/tag";
+    let tags = extract(content);
+    assert_eq!(&content[tags[0].range()], "#tag with spaces#");
+    assert_eq!(&content[tags[1].range()], "#nested/tag");
+  }
+
+  #[test]
+  fn test_with_ancestors() {
+    let tag = extract("#a/b/c").remove(0);
+    let all: Vec<&str> = tag.with_ancestors().collect();
+    assert_eq!(all, vec!["a/b/c", "a/b", "a"]);
+  }
+
+  #[test]
+  fn test_reconcile_reports_drift_both_ways() {
+    let consistent = NoteId::new("n1".to_string());
+    let drifted = NoteId::new("n2".to_string());
+
+    let mut indexed = BTreeMap::new();
+    indexed.insert(
+      consistent.clone(),
+      BTreeSet::from(["Work".to_string(), "work/projects".to_string()]),
+    );
+    indexed.insert(drifted.clone(), BTreeSet::from(["stale".to_string()]));
+
+    let report = reconcile(
+      [
+        (&consistent, "One", Some("#work/projects")),
+        (&drifted, "Two", Some("#fresh")),
+      ],
+      &indexed,
+    );
+
+    assert_eq!(report.notes_checked(), 2);
+    assert_eq!(report.drift().len(), 1);
+
+    let drift = &report.drift()[0];
+    assert_eq!(drift.note_id(), &drifted);
+    assert_eq!(
+      drift.missing_from_index(),
+      &BTreeSet::from(["fresh".to_string()])
+    );
+    assert_eq!(
+      drift.missing_from_content(),
+      &BTreeSet::from(["stale".to_string()])
+    );
+  }
+}
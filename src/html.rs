@@ -0,0 +1,666 @@
+//! Rendering of Bear's Markdown dialect to sanitized HTML.
+//!
+//! On top of CommonMark (with tables, strikethrough and task lists), Bear notes use
+//! `[[wiki links]]` to other notes, inline `#tags` and `==highlights==`. This module
+//! renders those to HTML suitable for publishing:
+//!
+//! - Wiki links become `<a class="wiki-link">` pointing at a slug or `NoteId` URL
+//! - Tags become `<a class="tag">` links
+//! - Highlights become `<mark>`
+//! - Todos (`- [ ]` / `- [x]`) become disabled checkbox inputs
+//! - Relative image and link targets are passed through an attachment resolver
+//!
+//! Raw HTML in notes is escaped rather than passed through, and link targets with
+//! schemes other than `http`, `https`, `mailto`, `tel` and `bear` are dropped.
+
+use pulldown_cmark::{CowStr, Event, LinkType, Options, Parser, Tag, TagEnd, html};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::{NoteId, hashtags};
+
+/// Resolves an attachment reference (e.g. `"Screenshot 2024.png"`) to a URL.
+type AttachmentResolver = Arc<dyn Fn(&str) -> Option<String> + Send + Sync>;
+
+/// How `[[wiki links]]` are turned into URLs.
+#[derive(Debug, Clone)]
+enum WikiLinks {
+  /// `{}` in the template is replaced by the slug of the linked title
+  Slug { template: String },
+  /// `{}` in the template is replaced by the linked note's UUID
+  NoteId {
+    template: String,
+    ids: Arc<HashMap<String, NoteId>>,
+  },
+}
+
+/// Options for rendering notes to HTML with `Note::to_html()`.
+///
+/// URL templates contain a `{}` placeholder that is replaced with the link target.
+/// By default:
+/// - Wiki links point at `{}.html`, where `{}` is the slug of the linked note's title
+/// - Tags point at `tags/{}.html`, where `{}` is the tag path with each level slugified
+///   (levels like `c++` or `🎉` that slugify lossily get a hash suffix, see [`tag_slug()`])
+/// - Attachment references are left unchanged
+///
+/// # Examples
+///
+/// ```no_run
+/// # use bear_query::{BearDb, HtmlOptions, NotesQuery};
+/// # fn main() -> Result<(), bear_query::BearError> {
+/// let db = BearDb::new()?;
+/// let notes = db.notes(NotesQuery::new().no_limit())?;
+///
+/// // Link notes by their Bear UUID rather than by title
+/// let titles = notes
+///     .iter()
+///     .map(|note| (note.title().to_string(), note.id().clone()));
+///
+/// let options = HtmlOptions::new()
+///     .wiki_links_by_id("/wiki/notes/{}", titles)
+///     .tag_links("/wiki/tags/{}")
+///     .attachment_resolver(|name| Some(format!("/wiki/files/{}", name)));
+///
+/// for note in &notes {
+///     println!("{}", note.to_html(&options));
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct HtmlOptions {
+  wiki_links: WikiLinks,
+  tag_template: String,
+  attachment_resolver: Option<AttachmentResolver>,
+}
+
+impl Default for HtmlOptions {
+  fn default() -> Self {
+    Self {
+      wiki_links: WikiLinks::Slug {
+        template: "{}.html".to_string(),
+      },
+      tag_template: "tags/{}.html".to_string(),
+      attachment_resolver: None,
+    }
+  }
+}
+
+impl fmt::Debug for HtmlOptions {
+  fn fmt(
+    &self,
+    f: &mut fmt::Formatter<'_>,
+  ) -> fmt::Result {
+    f.debug_struct("HtmlOptions")
+      .field("wiki_links", &self.wiki_links)
+      .field("tag_template", &self.tag_template)
+      .field("attachment_resolver", &self.attachment_resolver.is_some())
+      .finish()
+  }
+}
+
+impl HtmlOptions {
+  /// Create new HtmlOptions with default settings
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Link `[[wiki links]]` by the slug of the linked title, e.g. `"notes/{}.html"`
+  pub fn wiki_links_by_slug(
+    mut self,
+    template: impl Into<String>,
+  ) -> Self {
+    self.wiki_links = WikiLinks::Slug {
+      template: template.into(),
+    };
+    self
+  }
+
+  /// Link `[[wiki links]]` by the linked note's `NoteId`, e.g. `"bear://x-callback-url/open-note?id={}"`.
+  ///
+  /// `titles` maps note titles to their IDs; titles are matched case-insensitively.
+  /// Links to titles that are not in the map render as `<span class="wiki-link missing">`.
+  pub fn wiki_links_by_id(
+    mut self,
+    template: impl Into<String>,
+    titles: impl IntoIterator<Item = (String, NoteId)>,
+  ) -> Self {
+    self.wiki_links = WikiLinks::NoteId {
+      template: template.into(),
+      ids: Arc::new(
+        titles
+          .into_iter()
+          .map(|(title, id)| (title.to_lowercase(), id))
+          .collect(),
+      ),
+    };
+    self
+  }
+
+  /// Set the URL template for `#tag` links, e.g. `"/tags/{}"`
+  pub fn tag_links(
+    mut self,
+    template: impl Into<String>,
+  ) -> Self {
+    self.tag_template = template.into();
+    self
+  }
+
+  /// Rewrite relative image and link targets (attachments) through `resolver`.
+  ///
+  /// The resolver receives the percent-decoded reference as written in the note
+  /// (usually the attachment's file name). Returning `None` leaves it unchanged.
+  pub fn attachment_resolver(
+    mut self,
+    resolver: impl Fn(&str) -> Option<String> + Send + Sync + 'static,
+  ) -> Self {
+    self.attachment_resolver = Some(Arc::new(resolver));
+    self
+  }
+
+  /// Returns the URL for a wiki link target, or `None` if it cannot be resolved.
+  fn wiki_link_url(
+    &self,
+    target: &str,
+  ) -> Option<String> {
+    match &self.wiki_links {
+      WikiLinks::Slug { template } => Some(fill(template, &slugify(target))),
+      WikiLinks::NoteId { template, ids } => ids
+        .get(&target.trim().to_lowercase())
+        .map(|id| fill(template, id.as_str())),
+    }
+  }
+
+  /// Returns the URL for a tag, slugifying each level of the hierarchy.
+  fn tag_url(
+    &self,
+    tag: &str,
+  ) -> String {
+    fill(&self.tag_template, &tag_path(tag))
+  }
+}
+
+/// Replaces the `{}` placeholder in a URL template.
+fn fill(
+  template: &str,
+  value: &str,
+) -> String {
+  template.replace("{}", value)
+}
+
+/// Converts a title into a lowercase, URL- and filename-safe slug.
+///
+/// Letters and digits (including non-ASCII ones) are kept; every other run of
+/// characters becomes a single `-`. Titles without any letters or digits become
+/// `"untitled"`.
+///
+/// # Example
+///
+/// ```
+/// use bear_query::slugify;
+///
+/// assert_eq!(slugify("Meeting Notes: 2024/05"), "meeting-notes-2024-05");
+/// assert_eq!(slugify("Café crème"), "café-crème");
+/// assert_eq!(slugify("???"), "untitled");
+/// ```
+pub fn slugify(title: &str) -> String {
+  let mut slug = String::with_capacity(title.len());
+  for c in title.chars() {
+    if c.is_alphanumeric() {
+      slug.extend(c.to_lowercase());
+    } else if !slug.is_empty() && !slug.ends_with('-') {
+      slug.push('-');
+    }
+  }
+
+  let slug = slug.trim_end_matches('-');
+  if slug.is_empty() {
+    "untitled".to_string()
+  } else {
+    slug.to_string()
+  }
+}
+
+/// Converts one level of a tag into a slug that no other tag level shares.
+///
+/// Levels that only lose case and separators to `slugify()` keep its slug, so
+/// `Big Projects` becomes `big-projects`. Levels with other characters that
+/// `slugify()` drops, like `c++` or `🎉`, get the first 8 hex digits of the SHA-256
+/// of their lowercased name appended, so `#c++` and `#c` get different pages.
+///
+/// # Example
+///
+/// ```
+/// use bear_query::tag_slug;
+///
+/// assert_eq!(tag_slug("Big Projects"), "big-projects");
+/// assert_ne!(tag_slug("c++"), tag_slug("c"));
+/// assert!(tag_slug("c++").starts_with("c-"));
+/// assert_ne!(tag_slug("🎉"), tag_slug("🚀"));
+/// ```
+pub fn tag_slug(level: &str) -> String {
+  let slug = slugify(level);
+  let lowercase = level.to_lowercase();
+  let separated: Vec<&str> = lowercase
+    .split(|c: char| c.is_whitespace() || c == '-' || c == '_')
+    .filter(|word| !word.is_empty())
+    .collect();
+  if separated.join("-") == slug {
+    return slug;
+  }
+
+  let hash: String = Sha256::digest(lowercase.as_bytes())[..4]
+    .iter()
+    .map(|byte| format!("{byte:02x}"))
+    .collect();
+  format!("{slug}-{hash}")
+}
+
+/// Slugifies each level of a tag with `tag_slug()`, e.g. `"Work/Big Projects"` →
+/// `"work/big-projects"`.
+pub(crate) fn tag_path(tag: &str) -> String {
+  tag.split('/').map(tag_slug).collect::<Vec<_>>().join("/")
+}
+
+/// Escapes text for inclusion in HTML element content or attribute values.
+pub(crate) fn escape_html(text: &str) -> String {
+  let mut escaped = String::with_capacity(text.len());
+  for c in text.chars() {
+    match c {
+      '&' => escaped.push_str("&amp;"),
+      '<' => escaped.push_str("&lt;"),
+      '>' => escaped.push_str("&gt;"),
+      '"' => escaped.push_str("&quot;"),
+      '\'' => escaped.push_str("&#39;"),
+      _ => escaped.push(c),
+    }
+  }
+  escaped
+}
+
+/// Decodes `%XX` escapes, leaving malformed sequences as-is.
+pub(crate) fn percent_decode(text: &str) -> String {
+  let bytes = text.as_bytes();
+  let mut decoded = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+  while i < bytes.len() {
+    let hex = bytes
+      .get(i + 1..i + 3)
+      .and_then(|h| std::str::from_utf8(h).ok())
+      .and_then(|h| u8::from_str_radix(h, 16).ok());
+    match (bytes[i], hex) {
+      (b'%', Some(byte)) => {
+        decoded.push(byte);
+        i += 3;
+      }
+      (byte, _) => {
+        decoded.push(byte);
+        i += 1;
+      }
+    }
+  }
+  String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Returns the URL scheme (lowercased), if the URL has one.
+fn url_scheme(url: &str) -> Option<String> {
+  let (scheme, _) = url.split_once(':')?;
+  let is_scheme = !scheme.is_empty()
+    && scheme
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+  is_scheme.then(|| scheme.to_ascii_lowercase())
+}
+
+/// Returns true for link targets that are relative references to attachments.
+pub(crate) fn is_attachment_reference(url: &str) -> bool {
+  !url.is_empty() && !url.starts_with(['#', '/']) && url_scheme(url).is_none()
+}
+
+/// Returns the URL with unsafe schemes (e.g. `javascript:`) replaced by `#`.
+fn sanitize_url(url: &str) -> CowStr<'static> {
+  match url_scheme(url).as_deref() {
+    None | Some("http" | "https" | "mailto" | "tel" | "bear") => url.to_string().into(),
+    Some(_) => "#".into(),
+  }
+}
+
+/// Markdown extensions enabled for Bear notes.
+pub(crate) fn parser_options() -> Options {
+  Options::ENABLE_TABLES
+    | Options::ENABLE_STRIKETHROUGH
+    | Options::ENABLE_TASKLISTS
+    | Options::ENABLE_WIKILINKS
+}
+
+/// Renders a Markdown document to sanitized HTML.
+pub(crate) fn render(
+  content: &str,
+  options: &HtmlOptions,
+) -> String {
+  let tag_ranges: Vec<(Range<usize>, String)> = hashtags::extract(content)
+    .into_iter()
+    .map(|tag| (tag.range(), tag.name().to_string()))
+    .collect();
+
+  let mut events: Vec<Event> = Vec::new();
+  let mut open_links: Vec<OpenLink> = Vec::new();
+  let mut in_code_block = false;
+
+  for (event, range) in Parser::new_ext(content, parser_options()).into_offset_iter() {
+    match event {
+      Event::Start(Tag::Link {
+        link_type: LinkType::WikiLink { .. },
+        dest_url,
+        ..
+      }) => match options.wiki_link_url(&dest_url) {
+        Some(url) => {
+          open_links.push(OpenLink::Wiki);
+          events.push(Event::InlineHtml(
+            format!(
+              "<a class=\"wiki-link\" href=\"{}\">",
+              escape_html(&sanitize_url(&url))
+            )
+            .into(),
+          ));
+        }
+        None => {
+          open_links.push(OpenLink::MissingWiki);
+          events.push(Event::InlineHtml(
+            "<span class=\"wiki-link missing\">".into(),
+          ));
+        }
+      },
+      Event::Start(Tag::Link {
+        link_type,
+        dest_url,
+        title,
+        id,
+      }) => {
+        open_links.push(OpenLink::Markdown);
+        events.push(Event::Start(Tag::Link {
+          link_type,
+          dest_url: resolve_target(&dest_url, options),
+          title,
+          id,
+        }));
+      }
+      Event::End(TagEnd::Link) => events.push(match open_links.pop() {
+        Some(OpenLink::Wiki) => Event::InlineHtml("</a>".into()),
+        Some(OpenLink::MissingWiki) => Event::InlineHtml("</span>".into()),
+        _ => Event::End(TagEnd::Link),
+      }),
+      Event::Start(Tag::Image {
+        link_type,
+        dest_url,
+        title,
+        id,
+      }) => events.push(Event::Start(Tag::Image {
+        link_type,
+        dest_url: resolve_target(&dest_url, options),
+        title,
+        id,
+      })),
+      Event::Start(Tag::CodeBlock(kind)) => {
+        in_code_block = true;
+        events.push(Event::Start(Tag::CodeBlock(kind)));
+      }
+      Event::End(TagEnd::CodeBlock) => {
+        in_code_block = false;
+        events.push(Event::End(TagEnd::CodeBlock));
+      }
+      // Never pass raw HTML from notes through; render it as text instead
+      Event::Html(raw) | Event::InlineHtml(raw) => events.push(Event::Text(raw)),
+      Event::Text(text) if !in_code_block && open_links.is_empty() => {
+        // Tag offsets only line up when the text is a verbatim slice of the source
+        let tags: &[(Range<usize>, String)] = if content.get(range.clone()) == Some(&*text) {
+          &tag_ranges
+        } else {
+          &[]
+        };
+        push_text(&mut events, &text, range.start, tags, options);
+      }
+      other => events.push(other),
+    }
+  }
+
+  let mut output = String::new();
+  html::push_html(&mut output, events.into_iter());
+  output
+}
+
+/// How a currently open link was rendered, so it can be closed the same way.
+enum OpenLink {
+  /// A resolved wiki link, rendered as a raw `<a>`
+  Wiki,
+  /// An unresolved wiki link, rendered as a `<span>`
+  MissingWiki,
+  /// A regular Markdown link
+  Markdown,
+}
+
+/// Sanitizes a link or image target and rewrites attachment references.
+fn resolve_target(
+  dest_url: &str,
+  options: &HtmlOptions,
+) -> CowStr<'static> {
+  if is_attachment_reference(dest_url)
+    && let Some(resolver) = &options.attachment_resolver
+    && let Some(url) = resolver(&percent_decode(dest_url))
+  {
+    return sanitize_url(&url);
+  }
+  sanitize_url(dest_url)
+}
+
+/// Emits a text run, turning inline tags into links and `==text==` into `<mark>`.
+fn push_text<'a>(
+  events: &mut Vec<Event<'a>>,
+  text: &str,
+  offset: usize,
+  tags: &[(Range<usize>, String)],
+  options: &HtmlOptions,
+) {
+  let end = offset + text.len();
+  let mut pos = 0;
+
+  for (range, name) in tags
+    .iter()
+    .filter(|(range, _)| range.start >= offset && range.end <= end)
+  {
+    push_highlighted(events, &text[pos..range.start - offset]);
+    events.push(Event::InlineHtml(
+      format!(
+        "<a class=\"tag\" href=\"{}\">{}</a>",
+        escape_html(&sanitize_url(&options.tag_url(name))),
+        escape_html(&text[range.start - offset..range.end - offset])
+      )
+      .into(),
+    ));
+    pos = range.end - offset;
+  }
+
+  push_highlighted(events, &text[pos..]);
+}
+
+/// Emits a text run, wrapping `==highlighted==` spans in `<mark>`.
+fn push_highlighted<'a>(
+  events: &mut Vec<Event<'a>>,
+  text: &str,
+) {
+  let mut rest = text;
+
+  while let Some(open) = find_highlight_open(rest) {
+    let inner_start = open + 2;
+    let Some(close) = find_highlight_close(&rest[inner_start..]) else {
+      break;
+    };
+
+    if open > 0 {
+      events.push(Event::Text(rest[..open].to_string().into()));
+    }
+    events.push(Event::InlineHtml("<mark>".into()));
+    events.push(Event::Text(
+      rest[inner_start..inner_start + close].to_string().into(),
+    ));
+    events.push(Event::InlineHtml("</mark>".into()));
+    rest = &rest[inner_start + close + 2..];
+  }
+
+  if !rest.is_empty() {
+    events.push(Event::Text(rest.to_string().into()));
+  }
+}
+
+/// Finds an opening `==` that is followed by a non-space character.
+fn find_highlight_open(text: &str) -> Option<usize> {
+  text
+    .match_indices("==")
+    .map(|(i, _)| i)
+    .find(|&i| text[i + 2..].starts_with(|c: char| !c.is_whitespace() && c != '='))
+}
+
+/// Finds a closing `==` that is preceded by a non-space character.
+fn find_highlight_close(text: &str) -> Option<usize> {
+  text
+    .match_indices("==")
+    .map(|(i, _)| i)
+    .find(|&i| i > 0 && text[..i].ends_with(|c: char| !c.is_whitespace()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn render_default(content: &str) -> String {
+    render(content, &HtmlOptions::new())
+  }
+
+  #[test]
+  fn test_slugify() {
+    assert_eq!(slugify("Hello, World!"), "hello-world");
+    assert_eq!(slugify("  leading and trailing  "), "leading-and-trailing");
+    assert_eq!(slugify("日本語のノート"), "日本語のノート");
+    assert_eq!(slugify(""), "untitled");
+  }
+
+  #[test]
+  fn test_basic_markdown() {
+    let html = render_default("# Title\n\nSome *emphasis* and `code`.");
+    assert!(html.contains("<h1>Title</h1>"));
+    assert!(html.contains("<em>emphasis</em>"));
+    assert!(html.contains("<code>code</code>"));
+  }
+
+  #[test]
+  fn test_wiki_links_by_slug() {
+    let html = render(
+      "See [[Other Note]].",
+      &HtmlOptions::new().wiki_links_by_slug("/n/{}"),
+    );
+    assert!(html.contains(r#"<a class="wiki-link" href="/n/other-note">Other Note</a>"#));
+  }
+
+  #[test]
+  fn test_wiki_links_by_id() {
+    let options = HtmlOptions::new().wiki_links_by_id(
+      "bear://x-callback-url/open-note?id={}",
+      [("Other Note".to_string(), NoteId::new("UUID-1".to_string()))],
+    );
+
+    let html = render("[[other note]] and [[Missing]]", &options);
+    assert!(html.contains(
+      r#"<a class="wiki-link" href="bear://x-callback-url/open-note?id=UUID-1">other note</a>"#
+    ));
+    assert!(html.contains(r#"<span class="wiki-link missing">Missing</span>"#));
+  }
+
+  #[test]
+  fn test_regular_links_close_normally() {
+    let html = render_default("[[Wiki]] then [site](https://example.com)");
+    assert!(html.contains(r#"<a class="wiki-link" href="wiki.html">Wiki</a>"#));
+    assert!(html.contains(r#"<a href="https://example.com">site</a>"#));
+  }
+
+  #[test]
+  fn test_tags_become_links() {
+    let html = render_default("Filed under #work/Big Projects# and #home.");
+    assert!(
+      html.contains(r#"<a class="tag" href="tags/work/big-projects.html">#work/Big Projects#</a>"#)
+    );
+    assert!(html.contains(r#"<a class="tag" href="tags/home.html">#home</a>."#));
+
+    // Tags that slugify to the same text still link to different pages
+    let html = render_default("#c++# #c #🎉 #🚀");
+    let hrefs: Vec<&str> = html
+      .match_indices("href=\"")
+      .map(|(start, _)| {
+        let rest = &html[start + 6..];
+        &rest[..rest.find('"').unwrap()]
+      })
+      .collect();
+    assert_eq!(hrefs.len(), 4);
+    assert_eq!(hrefs[1], "tags/c.html");
+    assert!(hrefs[0].starts_with("tags/c-"));
+    assert!(hrefs[2].starts_with("tags/untitled-"));
+    assert_ne!(hrefs[2], hrefs[3]);
+  }
+
+  #[test]
+  fn test_tags_in_code_are_not_links() {
+    let html = render_default("```\n#not-a-tag\n```\n\n`#also-not`");
+    assert!(!html.contains("class=\"tag\""));
+  }
+
+  #[test]
+  fn test_highlights() {
+    let html = render_default("This is ==very important== but a == b is not.");
+    assert!(html.contains("<mark>very important</mark>"));
+    assert!(html.contains("a == b is not"));
+  }
+
+  #[test]
+  fn test_todos_become_checkboxes() {
+    let html = render_default("- [ ] open\n- [x] done\n");
+    assert!(html.contains(r#"<input disabled="" type="checkbox"/>"#));
+    assert!(html.contains(r#"<input disabled="" type="checkbox" checked=""/>"#));
+  }
+
+  #[test]
+  fn test_raw_html_is_escaped() {
+    let html = render_default("<script>alert(1)</script>\n\nInline <b>bold</b>");
+    assert!(!html.contains("<script>"));
+    assert!(html.contains("&lt;script&gt;"));
+    assert!(!html.contains("<b>"));
+  }
+
+  #[test]
+  fn test_unsafe_urls_are_dropped() {
+    let html = render_default("[click](javascript:alert(1))");
+    assert!(html.contains(r##"<a href="#">click</a>"##));
+  }
+
+  #[test]
+  fn test_attachment_resolver() {
+    let options = HtmlOptions::new()
+      .attachment_resolver(|name| Some(format!("/files/{}", name.replace(' ', "_"))));
+
+    let html = render(
+      "![](My%20Image.png) [pdf](doc.pdf) [web](https://example.com/a.png)",
+      &options,
+    );
+    assert!(html.contains(r#"<img src="/files/My_Image.png""#));
+    assert!(html.contains(r#"<a href="/files/doc.pdf">pdf</a>"#));
+    assert!(html.contains(r#"<a href="https://example.com/a.png">web</a>"#));
+  }
+
+  #[test]
+  fn test_percent_decode() {
+    assert_eq!(percent_decode("My%20Image.png"), "My Image.png");
+    assert_eq!(percent_decode("100%"), "100%");
+    assert_eq!(percent_decode("%E2%9C%93"), "✓");
+  }
+}
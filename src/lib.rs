@@ -9,8 +9,14 @@
 //! 1. **Read-Only File Access**: Opens with `SQLITE_OPEN_READ_ONLY`
 //! 2. **No Internal Locks**: Uses `SQLITE_OPEN_NO_MUTEX` to minimize lock contention
 //! 3. **Query-Only Mode**: Enforces `PRAGMA query_only = ON` at SQLite level
-//! 4. **Short-Lived Connections**: Connections are only open during each query
-//! 5. **Busy Timeout**: 5000ms timeout handles database contention gracefully
+//! 4. **Statement Authorizer**: Rejects anything but reads of Bear's tables with
+//!    `BearError::ForbiddenStatement`
+//! 5. **Short-Lived Connections**: Connections are only open during each query
+//! 6. **Busy Timeout**: 5000ms timeout handles database contention gracefully
+//! 7. **Query Timeout**: Queries running longer than [`DEFAULT_QUERY_TIMEOUT`] are
+//!    interrupted with `BearError::Timeout` (see [`QueryLimits`])
+//! 8. **Optional Retries**: A [`RetryPolicy`] backs off and tries again when Bear holds
+//!    the database past the busy timeout
 //!
 //! ## How It Works
 //!
@@ -42,6 +48,8 @@
 //! | `content` | TEXT | Full note content (Markdown) |
 //! | `modified` | DATETIME | Last modification timestamp (converted from Core Data epoch) |
 //! | `created` | DATETIME | Creation timestamp (converted from Core Data epoch) |
+//! | `core_data_modified` | REAL | Raw Core Data modification timestamp |
+//! | `core_data_created` | REAL | Raw Core Data creation timestamp |
 //! | `is_pinned` | INTEGER | 1 if pinned, 0 otherwise |
 //! | `is_trashed` | INTEGER | 1 if in trash, 0 otherwise |
 //! | `is_archived` | INTEGER | 1 if archived, 0 otherwise |
@@ -55,6 +63,7 @@
 //! | `id` | INTEGER | Tag's primary key |
 //! | `name` | TEXT | Tag name (e.g., "work/projects") |
 //! | `modified` | DATETIME | Last modification timestamp |
+//! | `core_data_modified` | REAL | Raw Core Data modification timestamp |
 //!
 //! ### `note_tags` Table
 //!
@@ -74,19 +83,47 @@
 //! | `from_note_id` | TEXT | Source note UUID (references notes.id) |
 //! | `to_note_id` | TEXT | Target note UUID (references notes.id) |
 //!
+//! ### `attachments` Table
+//!
+//! Files and images attached to notes (empty on Bear versions without `ZSFNOTEFILE`).
+//!
+//! | Column | Type | Description |
+//! |--------|------|-------------|
+//! | `id` | TEXT | Attachment UUID |
+//! | `note_id` | TEXT | Owning note UUID (references notes.id) |
+//! | `filename` | TEXT | File name, as referenced from the note content |
+//! | `size` | INTEGER | File size in bytes |
+//! | `modified` | DATETIME | Last modification timestamp |
+//! | `created` | DATETIME | Creation timestamp |
+//!
 //! ### Core Data Epoch Conversion
 //!
 //! Bear uses Apple's Core Data timestamp format (seconds since 2001-01-01 00:00:00 UTC).
-//! This library automatically converts all timestamps to standard SQLite datetime format.
+//! This library automatically converts all timestamps to SQLite datetime text with
+//! millisecond precision (`2024-11-19 23:50:00.125`). The `core_data_*` columns keep the
+//! raw values, and [`Note::core_data_modified()`] exposes them for exact comparisons.
 //!
 //! The conversion is done via a CTE: `unixepoch('2001-01-01')`
 //!
+//! DataFrames returned by `query()` carry these timestamps as `Datetime(Microseconds, UTC)`
+//! columns and the `is_*` flags as `Boolean` columns (see [`QueryOptions`]).
+//!
+//! ### Time Zones
+//!
+//! Timestamps are UTC. [`BearDb::with_time_zone()`] sets a [`TimeZone`] for local dates:
+//! the `local_date(timestamp)` and `local_datetime(timestamp)` SQL functions use it, and
+//! DataFrame datetime columns are displayed in it. [`NotesQuery::modified_on()`] and
+//! [`SearchQuery::modified_on()`] filter by a calendar day in any zone.
+//!
 //! ### Schema Discovery
 //!
 //! The library discovers variable schema elements at initialization:
 //! - Junction table column names (e.g., `Z_5NOTES`, `Z_13TAGS`)
 //! - These numbers may vary across Bear versions
 //!
+//! A database without the tables and columns the views read fails with
+//! [`BearError::SchemaUnsupported`].
+//!
 //! For full schema details, see the `SCHEMA.md` documentation file.
 //!
 //! ## Example
@@ -125,21 +162,53 @@
 //! # }
 //! ```
 
+mod authorizer;
+#[cfg(feature = "polars")]
 mod dataframe;
+pub mod datetime;
+pub mod export;
+mod functions;
+mod hashtags;
+mod html;
+mod limits;
 mod models;
+mod outline;
+mod plain_text;
+mod plan;
+mod retry;
+mod rows;
 mod schema;
+mod timezone;
 
+#[cfg(feature = "polars")]
+pub use dataframe::{MixedTypes, QueryOptions};
+pub use hashtags::{InlineTag, NoteTagDrift, TagConsistencyReport};
+pub use html::{HtmlOptions, slugify, tag_slug};
+pub use limits::{CancellationToken, DEFAULT_QUERY_TIMEOUT, QueryLimits, ResultLimit};
 pub use models::{Note, NoteId, Tag, TagId, TagsMap};
+pub use outline::Heading;
+pub use plan::{AccessKind, LARGE_TABLE_ROWS, PlanStep, QueryPlan, TableAccess};
+#[cfg(feature = "polars")]
 pub use polars::prelude as polars_prelude;
-
-use models::{note_from_row, tag_from_row};
+pub use retry::{RetryEvent, RetryPolicy};
+pub use rows::QueryRows;
+pub use rusqlite::types::Value;
+pub use rusqlite::{Params, ToSql, named_params, params};
+pub use timezone::TimeZone;
+
+use models::{Attachment, attachment_from_row, note_from_row, tag_from_row};
+#[cfg(feature = "polars")]
 use polars::prelude::*;
-use rusqlite::{Connection, OpenFlags};
-use std::collections::HashSet;
-use std::path::PathBuf;
+use rusqlite::{Connection, ErrorCode, OpenFlags};
+use serde::de::DeserializeOwned;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::time::Duration;
+use time::Date;
 
-use dataframe::query_to_dataframe;
+#[cfg(feature = "polars")]
+use dataframe::{query_to_dataframe, query_to_dataframes};
 
 /// Specifies the database location for BearDb.
 ///
@@ -152,14 +221,18 @@ enum DatabasePath {
   /// In-memory database for testing (only available with cfg(test))
   #[cfg(test)]
   InMemory,
+  /// In-memory test database with extra fixture SQL run after the test schema is set up
+  #[cfg(test)]
+  InMemoryWith(&'static str),
 }
 
 impl DatabasePath {
   /// Opens a connection based on the database path type.
   /// For RealPath: opens with read-only flags and safety pragmas
   /// For InMemory: creates an in-memory database with test schema
+  /// Custom SQL functions are registered on every connection.
   fn open_connection(&self) -> Result<Connection, BearError> {
-    match self {
+    let conn = match self {
       DatabasePath::RealPath(path) => {
         // Open with maximum read-only protection:
         // - SQLITE_OPEN_READ_ONLY: Opens in read-only mode
@@ -167,7 +240,8 @@ impl DatabasePath {
         let conn = Connection::open_with_flags(
           path,
           OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
-        )?;
+        )
+        .map_err(|error| open_error(path, error))?;
 
         // Set busy timeout to handle database contention
         conn.busy_timeout(Duration::from_millis(5000))?;
@@ -175,15 +249,27 @@ impl DatabasePath {
         // Enable query_only mode as additional safety
         conn.pragma_update(None, "query_only", "ON")?;
 
-        Ok(conn)
+        conn
       }
       #[cfg(test)]
       DatabasePath::InMemory => {
         let conn = Connection::open_in_memory()?;
         schema::setup_test_schema(&conn)?;
-        Ok(conn)
+        conn
       }
-    }
+      #[cfg(test)]
+      DatabasePath::InMemoryWith(fixture) => {
+        let conn = Connection::open_in_memory()?;
+        schema::setup_test_schema(&conn)?;
+        conn.execute_batch(fixture)?;
+        conn
+      }
+    };
+
+    // Make custom SQL functions (e.g. word_count) available to all queries
+    functions::register(&conn)?;
+
+    Ok(conn)
   }
 }
 
@@ -191,16 +277,166 @@ impl DatabasePath {
 pub enum BearError {
   #[error("Unable to load users home directory")]
   NoHomeDirectory,
-  #[error("SQL Error: {source}")]
+  #[error(
+    "SQL Error: {source}{}",
+    sql.as_ref().map(|sql| format!(" (running: {})", sql.trim())).unwrap_or_default()
+  )]
   SqlError {
-    #[from]
     source: rusqlite::Error,
+    /// The SQL that failed, when the error came from a query
+    sql: Option<String>,
   },
+  #[cfg(feature = "polars")]
   #[error("Polars Error: {source}")]
   PolarsError {
     #[from]
     source: PolarsError,
   },
+  #[error("IO Error: {source}")]
+  IoError {
+    #[from]
+    source: std::io::Error,
+  },
+  #[error("JSON Error: {source}")]
+  JsonError {
+    #[from]
+    source: serde_json::Error,
+  },
+  #[error("Deserialize Error: {source}")]
+  DeserializeError {
+    #[from]
+    source: serde::de::value::Error,
+  },
+  #[error(
+    "Forbidden statement: {action}{}",
+    target.as_ref().map(|target| format!(" ({})", target)).unwrap_or_default()
+  )]
+  ForbiddenStatement {
+    /// What was blocked, e.g. `INSERT`, `SCHEMA CHANGE`, `ATTACH`, `PRAGMA`, `FUNCTION`
+    /// or `MULTIPLE STATEMENTS`
+    action: String,
+    /// The table, file, pragma or function it targeted, when there is one
+    target: Option<String>,
+  },
+  #[error("Column '{column}' mixes SQLite types: {}", types.join(", "))]
+  MixedColumnTypes { column: String, types: Vec<String> },
+  #[error("Query timed out after {timeout:?}")]
+  Timeout { timeout: Duration },
+  #[error("Query was cancelled")]
+  Cancelled,
+  #[error("Query result is larger than its limit of {limit}")]
+  ResultTooLarge { limit: ResultLimit },
+  #[error("Unknown time zone: {0}")]
+  UnknownTimeZone(String),
+  #[error("Bear's database was not found at {}", .0.display())]
+  DatabaseNotFound(PathBuf),
+  #[error(
+    "Permission denied opening {}; on macOS, give this program Full Disk Access in System Settings > Privacy & Security",
+    .0.display()
+  )]
+  PermissionDenied(PathBuf),
+  #[error(
+    "Bear's database is busy{}",
+    sql.as_ref().map(|sql| format!(" (running: {})", sql.trim())).unwrap_or_default()
+  )]
+  Busy {
+    /// The SQL that was running, when the database was busy during a query
+    sql: Option<String>,
+  },
+  #[error(
+    "Unsupported Bear database schema (missing tables: [{}], missing columns: [{}])",
+    missing_tables.join(", "),
+    missing_columns.join(", ")
+  )]
+  SchemaUnsupported {
+    missing_tables: Vec<String>,
+    /// Missing columns of existing tables, as `TABLE.COLUMN`
+    missing_columns: Vec<String>,
+  },
+  #[error("Refusing to export to {}: {reason}", path.display())]
+  ExportTargetRefused {
+    path: PathBuf,
+    reason: export::RefusalReason,
+  },
+}
+
+impl BearError {
+  /// Returns true for errors that may go away by running the same operation again,
+  /// such as Bear holding a lock on its database while it writes.
+  ///
+  /// Missing files, denied permissions, unsupported schemas, invalid SQL, forbidden
+  /// statements, timeouts and cancellations are not retryable.
+  ///
+  /// # Examples
+  /// ```no_run
+  /// # use bear_query::{BearDb, NotesQuery};
+  /// # fn main() -> Result<(), bear_query::BearError> {
+  /// let db = BearDb::new()?;
+  ///
+  /// let notes = match db.notes(NotesQuery::new()) {
+  ///   Err(error) if error.is_retryable() => db.notes(NotesQuery::new())?,
+  ///   result => result?,
+  /// };
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn is_retryable(&self) -> bool {
+    match self {
+      BearError::Busy { .. } => true,
+      BearError::IoError { source } => matches!(
+        source.kind(),
+        std::io::ErrorKind::Interrupted
+          | std::io::ErrorKind::WouldBlock
+          | std::io::ErrorKind::TimedOut
+      ),
+      _ => false,
+    }
+  }
+
+  /// Adds the SQL that was running to a `Busy` or `SqlError` error that doesn't name any
+  fn with_sql(
+    self,
+    running: Option<String>,
+  ) -> Self {
+    match self {
+      BearError::Busy { sql: None } => BearError::Busy { sql: running },
+      BearError::SqlError { source, sql: None } => BearError::SqlError {
+        source,
+        sql: running,
+      },
+      error => error,
+    }
+  }
+}
+
+impl From<rusqlite::Error> for BearError {
+  fn from(source: rusqlite::Error) -> Self {
+    match source.sqlite_error_code() {
+      // Bear is writing and the busy timeout ran out
+      Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked) => BearError::Busy { sql: None },
+      _ => BearError::SqlError { source, sql: None },
+    }
+  }
+}
+
+/// Explains a failure to open the database file at `path`: a missing file or one
+/// the process may not read (macOS requires Full Disk Access for Bear's container)
+fn open_error(
+  path: &Path,
+  source: rusqlite::Error,
+) -> BearError {
+  if source.sqlite_error_code() == Some(ErrorCode::CannotOpen) {
+    match std::fs::File::open(path) {
+      Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+        return BearError::DatabaseNotFound(path.to_path_buf());
+      }
+      Err(error) if error.kind() == std::io::ErrorKind::PermissionDenied => {
+        return BearError::PermissionDenied(path.to_path_buf());
+      }
+      _ => {}
+    }
+  }
+  source.into()
 }
 
 /// Query options for filtering notes.
@@ -235,6 +471,8 @@ pub struct NotesQuery {
   limit: Option<u32>,
   include_trashed: bool,
   include_archived: bool,
+  modified_on: Option<LocalDay>,
+  created_on: Option<LocalDay>,
 }
 
 impl Default for NotesQuery {
@@ -243,6 +481,8 @@ impl Default for NotesQuery {
       limit: Some(10),
       include_trashed: false,
       include_archived: false,
+      modified_on: None,
+      created_on: None,
     }
   }
 }
@@ -286,6 +526,124 @@ impl NotesQuery {
     self.include_archived = true;
     self
   }
+
+  /// Only return notes last modified on `date` in time zone `tz`
+  ///
+  /// # Examples
+  /// ```no_run
+  /// # use bear_query::{BearDb, NotesQuery, TimeZone};
+  /// # use time::macros::date;
+  /// # fn main() -> Result<(), bear_query::BearError> {
+  /// let db = BearDb::new()?;
+  ///
+  /// let pacific = TimeZone::named("America/Los_Angeles")?;
+  /// let notes = db.notes(NotesQuery::new().no_limit().modified_on(date!(2024 - 03 - 01), pacific))?;
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn modified_on(
+    mut self,
+    date: Date,
+    tz: impl Into<TimeZone>,
+  ) -> Self {
+    self.modified_on = Some(LocalDay::new(date, tz));
+    self
+  }
+
+  /// Only return notes created on `date` in time zone `tz`
+  pub fn created_on(
+    mut self,
+    date: Date,
+    tz: impl Into<TimeZone>,
+  ) -> Self {
+    self.created_on = Some(LocalDay::new(date, tz));
+    self
+  }
+
+  /// The SELECT of the notes this query returns, and its parameters
+  pub(crate) fn to_sql(&self) -> (String, Vec<Value>) {
+    // Build WHERE clause based on query options
+    let mut where_clauses = Vec::new();
+    let mut params = Vec::new();
+    if !self.include_trashed {
+      where_clauses.push("is_trashed <> 1".to_string());
+    }
+    if !self.include_archived {
+      where_clauses.push("is_archived <> 1".to_string());
+    }
+    if let Some(day) = self.modified_on {
+      day.push_filter("core_data_modified", &mut where_clauses, &mut params);
+    }
+    if let Some(day) = self.created_on {
+      day.push_filter("core_data_created", &mut where_clauses, &mut params);
+    }
+
+    let where_clause = if where_clauses.is_empty() {
+      String::new()
+    } else {
+      format!("WHERE {}", where_clauses.join(" AND "))
+    };
+
+    let limit_clause = self
+      .limit
+      .map(|l| format!("LIMIT {}", l))
+      .unwrap_or_default();
+
+    let sql = format!(
+      r"
+      SELECT
+        id,
+        core_db_id,
+        title,
+        content,
+        modified,
+        created,
+        core_data_modified,
+        core_data_created,
+        is_pinned
+      FROM notes
+      {}
+      ORDER BY modified DESC
+      {}",
+      where_clause, limit_clause
+    );
+
+    (sql, params)
+  }
+}
+
+/// A calendar day in a time zone, for filtering notes by date
+#[derive(Debug, Clone, Copy)]
+struct LocalDay {
+  date: Date,
+  tz: TimeZone,
+}
+
+impl LocalDay {
+  fn new(
+    date: Date,
+    tz: impl Into<TimeZone>,
+  ) -> Self {
+    Self {
+      date,
+      tz: tz.into(),
+    }
+  }
+
+  /// Adds a condition keeping the rows whose raw Core Data timestamp `column` falls
+  /// on this day, with its bounds as parameters
+  fn push_filter(
+    &self,
+    column: &str,
+    where_clauses: &mut Vec<String>,
+    params: &mut Vec<Value>,
+  ) {
+    let (start, end) = self.tz.day_bounds(self.date);
+    where_clauses.push(format!("{column} >= ? AND {column} < ?"));
+    params.extend(
+      [start, end].map(|bound| Value::Integer(bound.unix_timestamp() - datetime::CORE_DATA_EPOCH)),
+    );
+  }
 }
 
 /// What field to sort by.
@@ -327,6 +685,7 @@ impl Default for SortOrder {
 }
 
 impl SortOrder {
+  #[allow(clippy::wrong_self_convention)]
   fn to_sql(&self) -> &'static str {
     match self {
       SortOrder::Desc(SortOn::Modified) => "modified DESC",
@@ -394,6 +753,8 @@ pub struct SearchQuery {
   sort_by: SortOrder,
   include_trashed: bool,
   include_archived: bool,
+  modified_on: Option<LocalDay>,
+  created_on: Option<LocalDay>,
 }
 
 impl SearchQuery {
@@ -415,6 +776,8 @@ impl SearchQuery {
       sort_by: SortOrder::default(),
       include_trashed: false,
       include_archived: false,
+      modified_on: None,
+      created_on: None,
     }
   }
 
@@ -487,6 +850,26 @@ impl SearchQuery {
     self.include_archived = true;
     self
   }
+
+  /// Only return notes last modified on `date` in time zone `tz`
+  pub fn modified_on(
+    mut self,
+    date: Date,
+    tz: impl Into<TimeZone>,
+  ) -> Self {
+    self.modified_on = Some(LocalDay::new(date, tz));
+    self
+  }
+
+  /// Only return notes created on `date` in time zone `tz`
+  pub fn created_on(
+    mut self,
+    date: Date,
+    tz: impl Into<TimeZone>,
+  ) -> Self {
+    self.created_on = Some(LocalDay::new(date, tz));
+    self
+  }
 }
 
 /// Handle to Bear's database. All operations use short-lived connections internally.
@@ -494,6 +877,14 @@ pub struct BearDb {
   db_path: DatabasePath,
   _metadata: schema::BearDbMetadata,
   normalizing_cte: String,
+  /// Bear's `Local Files` directory holding attachment files, when known
+  files_root: Option<PathBuf>,
+  /// Time and size limits applied to every query
+  query_limits: QueryLimits,
+  /// Zone of local dates in SQL functions and DataFrame datetime columns
+  time_zone: TimeZone,
+  /// How to retry operations failing while Bear holds the database, if at all
+  retry_policy: Option<RetryPolicy>,
 }
 
 impl BearDb {
@@ -509,6 +900,13 @@ impl BearDb {
     Self::new_with_path(DatabasePath::RealPath(db_path))
   }
 
+  /// Opens a Bear database at another path, for the benchmarks' synthetic
+  /// databases. Not part of the public API.
+  #[doc(hidden)]
+  pub fn open_for_bench(path: impl AsRef<Path>) -> Result<Self, BearError> {
+    Self::new_with_path(DatabasePath::RealPath(path.as_ref().to_path_buf()))
+  }
+
   /// Create a new BearDb handle with a specific database path.
   /// This is primarily for testing with in-memory databases.
   pub(crate) fn new_with_path(db_path: DatabasePath) -> Result<Self, BearError> {
@@ -524,31 +922,169 @@ impl BearDb {
     // Connection is dropped here, closing it
     drop(connection);
 
+    // Attachments are stored next to the database file
+    let files_root = match &db_path {
+      DatabasePath::RealPath(path) => path.parent().map(|dir| dir.join("Local Files")),
+      #[cfg(test)]
+      _ => None,
+    };
+
     Ok(BearDb {
       db_path,
       _metadata: metadata,
       normalizing_cte,
+      files_root,
+      query_limits: QueryLimits::default(),
+      time_zone: TimeZone::Utc,
+      retry_policy: None,
     })
   }
 
+  /// Sets the limits of every query run through this handle (by default queries are
+  /// interrupted after [`DEFAULT_QUERY_TIMEOUT`] and results aren't limited).
+  ///
+  /// # Examples
+  /// ```no_run
+  /// # use bear_query::{BearDb, QueryLimits};
+  /// # use std::time::Duration;
+  /// # fn main() -> Result<(), bear_query::BearError> {
+  /// let db = BearDb::new()?.with_query_limits(QueryLimits::new().timeout(Duration::from_secs(5)));
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn with_query_limits(
+    mut self,
+    limits: QueryLimits,
+  ) -> Self {
+    self.query_limits = limits;
+    self
+  }
+
+  /// Sets the time zone of local dates (UTC by default): the `local_date()` and
+  /// `local_datetime()` SQL functions use it, and DataFrame datetime columns are
+  /// displayed in it.
+  ///
+  /// DataFrames support IANA zones and whole-hour offsets; other offsets fail with
+  /// `BearError::PolarsError` when a DataFrame has a datetime column.
+  ///
+  /// # Examples
+  /// ```no_run
+  /// # use bear_query::{BearDb, TimeZone};
+  /// # fn main() -> Result<(), bear_query::BearError> {
+  /// let db = BearDb::new()?.with_time_zone(TimeZone::named("America/Los_Angeles")?);
+  ///
+  /// // Edits per local day, not per UTC day
+  /// let edits: Vec<(String, i64)> = db.query_as(
+  ///   "SELECT local_date(modified) AS day, COUNT(*) FROM notes GROUP BY day",
+  ///   [],
+  /// )?;
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn with_time_zone(
+    mut self,
+    time_zone: impl Into<TimeZone>,
+  ) -> Self {
+    self.time_zone = time_zone.into();
+    self
+  }
+
+  /// Retries operations that fail because Bear holds the database for longer than the
+  /// busy timeout (by default they fail at once with `BearError::Busy`).
+  ///
+  /// Each attempt opens a new connection and starts a new query timeout. Errors for
+  /// which `BearError::is_retryable()` is false, such as timeouts and cancellations,
+  /// are never retried. [`BearDb::query_batches()`] and
+  /// [`export::to_jsonl()`] aren't retried, since their callback or writer may
+  /// already have seen some of the results.
+  ///
+  /// # Examples
+  /// ```no_run
+  /// # use bear_query::{BearDb, NotesQuery, RetryEvent, RetryPolicy};
+  /// # use std::sync::Arc;
+  /// # use std::sync::atomic::{AtomicU64, Ordering};
+  /// # fn main() -> Result<(), bear_query::BearError> {
+  /// let retries = Arc::new(AtomicU64::new(0));
+  /// let counter = retries.clone();
+  /// let db = BearDb::new()?.with_retry_policy(RetryPolicy::new().on_retry(move |event| {
+  ///   if let RetryEvent::Retrying { .. } = event {
+  ///     counter.fetch_add(1, Ordering::Relaxed);
+  ///   }
+  /// }));
+  ///
+  /// let notes = db.notes(NotesQuery::default())?;
+  /// println!("{} notes after {} retries", notes.len(), retries.load(Ordering::Relaxed));
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn with_retry_policy(
+    mut self,
+    policy: RetryPolicy,
+  ) -> Self {
+    self.retry_policy = Some(policy);
+    self
+  }
+
   /// Opens a short-lived connection, wraps it in a Queryable with normalizing CTEs,
-  /// executes the closure, and closes the connection.
+  /// executes the closure, and closes the connection. With a retry policy, the
+  /// closure runs again on a new connection after transient errors.
   fn with_connection<F, R>(
     &self,
     f: F,
   ) -> Result<R, BearError>
+  where
+    F: FnMut(&Queryable) -> Result<R, BearError>,
+  {
+    self.with_retries(&self.query_limits, f)
+  }
+
+  /// Like `with_limits()`, retrying the closure according to the retry policy.
+  fn with_retries<F, R>(
+    &self,
+    limits: &QueryLimits,
+    mut f: F,
+  ) -> Result<R, BearError>
+  where
+    F: FnMut(&Queryable) -> Result<R, BearError>,
+  {
+    match &self.retry_policy {
+      Some(policy) => policy.run(|| self.with_limits(limits, &mut f)),
+      None => self.with_limits(limits, f),
+    }
+  }
+
+  /// Like `with_connection()`, interrupting the closure's queries when `limits` are
+  /// exceeded.
+  fn with_limits<F, R>(
+    &self,
+    limits: &QueryLimits,
+    f: F,
+  ) -> Result<R, BearError>
   where
     F: FnOnce(&Queryable) -> Result<R, BearError>,
   {
     // Open connection using DatabasePath's connection handler
     let connection = self.db_path.open_connection()?;
 
+    // Only allow reading Bear's tables, whatever SQL the closure prepares
+    let authorizer = authorizer::Authorizer::install(&connection);
+
+    // Interrupt queries that run too long or are cancelled
+    let interrupter = limits::Interrupter::install(&connection, limits)?;
+
+    // Local dates in SQL follow this handle's time zone
+    functions::register_time_zone(&connection, self.time_zone)?;
+
     // Create Queryable wrapper with normalizing CTE
-    let queryable = Queryable::new(&connection, &self.normalizing_cte);
+    let queryable = Queryable::new(&connection, &self.normalizing_cte, limits.budget());
 
     // Execute the closure with the queryable
     // Connection will be automatically closed when it goes out of scope
-    f(&queryable)
+    f(&queryable).map_err(|error| {
+      interrupter
+        .explain(authorizer.explain(error))
+        .with_sql(queryable.last_sql())
+    })
   }
 
   /// Retrieves all tags from Bear
@@ -559,7 +1095,8 @@ impl BearDb {
       SELECT
         id,
         name,
-        modified
+        modified,
+        core_data_modified
       FROM tags
       ORDER BY name ASC",
       )?;
@@ -607,6 +1144,8 @@ impl BearDb {
         content,
         modified,
         created,
+        core_data_modified,
+        core_data_created,
         is_pinned
       FROM notes
       WHERE id = ?",
@@ -617,7 +1156,7 @@ impl BearDb {
       match result {
         Ok(note) => Ok(Some(note)),
         Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-        Err(e) => Err(BearError::SqlError { source: e }),
+        Err(e) => Err(e.into()),
       }
     })
   }
@@ -646,46 +1185,12 @@ impl BearDb {
     query: NotesQuery,
   ) -> Result<Vec<Note>, BearError> {
     self.with_connection(|queryable| {
-      // Build WHERE clause based on query options
-      let mut where_clauses = Vec::new();
-      if !query.include_trashed {
-        where_clauses.push("is_trashed <> 1");
-      }
-      if !query.include_archived {
-        where_clauses.push("is_archived <> 1");
-      }
-
-      let where_clause = if where_clauses.is_empty() {
-        String::new()
-      } else {
-        format!("WHERE {}", where_clauses.join(" AND "))
-      };
-
-      let limit_clause = query
-        .limit
-        .map(|l| format!("LIMIT {}", l))
-        .unwrap_or_default();
-
-      let query = format!(
-        r"
-      SELECT
-        id,
-        core_db_id,
-        title,
-        content,
-        modified,
-        created,
-        is_pinned
-      FROM notes
-      {}
-      ORDER BY modified DESC
-      {}",
-        where_clause, limit_clause
-      );
+      let (sql, params) = query.to_sql();
+      let mut statement = queryable.prepare(&sql)?;
 
-      let mut statement = queryable.prepare(&query)?;
-
-      let results: rusqlite::Result<Vec<Note>> = statement.query_map([], note_from_row)?.collect();
+      let results: rusqlite::Result<Vec<Note>> = statement
+        .query_map(rusqlite::params_from_iter(params), note_from_row)?
+        .collect();
 
       Ok(results?)
     })
@@ -741,11 +1246,15 @@ impl BearDb {
         format!("%{}%", search.query)
       };
 
+      // Bind the pattern for each search condition
+      let mut params = Vec::new();
       if search.search_title {
         search_conditions.push(format!("title {} ?", like_operator));
+        params.push(Value::Text(pattern.clone()));
       }
       if search.search_content {
         search_conditions.push(format!("content {} ?", like_operator));
+        params.push(Value::Text(pattern));
       }
 
       // If neither title nor content is selected, search nothing (return empty)
@@ -764,6 +1273,12 @@ impl BearDb {
       if !search.include_archived {
         where_clauses.push("is_archived <> 1".to_string());
       }
+      if let Some(day) = search.modified_on {
+        day.push_filter("core_data_modified", &mut where_clauses, &mut params);
+      }
+      if let Some(day) = search.created_on {
+        day.push_filter("core_data_created", &mut where_clauses, &mut params);
+      }
 
       let where_clause = format!("WHERE {}", where_clauses.join(" AND "));
 
@@ -781,6 +1296,8 @@ impl BearDb {
         content,
         modified,
         created,
+        core_data_modified,
+        core_data_created,
         is_pinned
       FROM notes
       {}
@@ -793,18 +1310,9 @@ impl BearDb {
 
       let mut statement = queryable.prepare(&query_sql)?;
 
-      // Bind the pattern for each search condition
-      let results: rusqlite::Result<Vec<Note>> = if search.search_title && search.search_content {
-        // Both title and content: bind pattern twice
-        statement
-          .query_map([pattern.as_str(), pattern.as_str()], note_from_row)?
-          .collect()
-      } else {
-        // Only one field: bind pattern once
-        statement
-          .query_map([pattern.as_str()], note_from_row)?
-          .collect()
-      };
+      let results: rusqlite::Result<Vec<Note>> = statement
+        .query_map(rusqlite::params_from_iter(params), note_from_row)?
+        .collect();
 
       Ok(results?)
     })
@@ -825,6 +1333,8 @@ impl BearDb {
         n.content,
         n.modified,
         n.created,
+        n.core_data_modified,
+        n.core_data_created,
         n.is_pinned
       FROM notes as n
       INNER JOIN note_links as nl ON nl.to_note_id = n.id
@@ -862,84 +1372,625 @@ impl BearDb {
     })
   }
 
-  /// Execute a generic SQL SELECT query and return results as a Polars DataFrame.
+  /// Compares the tags written inline in notes against Bear's `note_tags` index.
   ///
-  /// The query automatically has the normalizing CTEs prepended, so you can query
-  /// against clean table names: `notes`, `tags`, `note_tags`, `note_links`.
-  ///
-  /// # Safety
-  /// This method trusts the read-only connection flags to prevent writes. Only SELECT
-  /// queries should be used, though this is not enforced by the library.
+  /// Use `NotesQuery` to choose which notes are checked. Nested inline tags count
+  /// for all of their ancestors (`#a/b` expects both `a` and `a/b` to be indexed),
+  /// and names are compared case-insensitively. Only notes with drift in either
+  /// direction appear in the report.
   ///
   /// # Examples
   /// ```no_run
-  /// # use bear_query::BearDb;
+  /// # use bear_query::{BearDb, NotesQuery};
   /// # fn main() -> Result<(), bear_query::BearError> {
   /// let db = BearDb::new()?;
   ///
-  /// // Query normalized tables
-  /// let df = db.query("SELECT title, modified FROM notes LIMIT 5")?;
-  ///
-  /// // Join tables
-  /// let df = db.query(r"
-  ///   SELECT n.title, t.name as tag_name
-  ///   FROM notes n
-  ///   JOIN note_tags nt ON n.id = nt.note_id
-  ///   JOIN tags t ON nt.tag_id = t.id
-  ///   WHERE n.is_trashed = 0
-  ///   LIMIT 10
-  /// ")?;
-  ///
-  /// println!("{}", df);
+  /// let report = db.tag_consistency(NotesQuery::new().no_limit())?;
+  /// if !report.is_consistent() {
+  ///     println!("{} notes have tag drift", report.drift().len());
+  /// }
   /// # Ok(())
   /// # }
   /// ```
-  pub fn query(
+  pub fn tag_consistency(
     &self,
-    sql: &str,
-  ) -> Result<DataFrame, BearError> {
-    self.with_connection(|queryable| query_to_dataframe(queryable, sql))
+    query: NotesQuery,
+  ) -> Result<TagConsistencyReport, BearError> {
+    self.with_connection(|queryable| {
+      let (sql, params) = query.to_sql();
+      let mut statement = queryable.prepare(&sql)?;
+      let mut rows = statement.query_map(rusqlite::params_from_iter(params), note_from_row)?;
+
+      // The notes query holds its read transaction until all its rows are read, so
+      // reading the tag index after its first row sees the same state of the database
+      let first = rows.next().transpose()?;
+      let indexed = read_note_tag_names(queryable)?;
+      let notes: rusqlite::Result<Vec<Note>> = first.map(Ok).into_iter().chain(rows).collect();
+
+      Ok(hashtags::reconcile(
+        notes?
+          .iter()
+          .map(|note| (note.id(), note.title(), note.content())),
+        &indexed,
+      ))
+    })
   }
-}
+
+  /// Retrieves the names of every note's indexed tags, keyed by note.
+  pub(crate) fn note_tag_names(&self) -> Result<BTreeMap<NoteId, BTreeSet<String>>, BearError> {
+    self.with_connection(read_note_tag_names)
+  }
+
+  /// Retrieves every link between notes as (from, to) pairs, ordered by source then target.
+  pub(crate) fn note_link_ids(&self) -> Result<Vec<(NoteId, NoteId)>, BearError> {
+    self.with_connection(read_note_link_ids)
+  }
+
+  /// Like `with_connection()`, but never retried: for closures that hand results
+  /// to a caller's writer or callback as they are read, and so must not run twice.
+  pub(crate) fn with_single_attempt<F, R>(
+    &self,
+    f: F,
+  ) -> Result<R, BearError>
+  where
+    F: FnOnce(&Queryable) -> Result<R, BearError>,
+  {
+    self.with_limits(&self.query_limits, f)
+  }
+
+  /// Runs a query against the normalized views and collects the raw values of every row.
+  pub(crate) fn raw_rows(
+    &self,
+    sql: &str,
+  ) -> Result<Vec<Vec<rusqlite::types::Value>>, BearError> {
+    Ok(self.query_rows(sql, [])?.into_rows())
+  }
+
+  /// Returns the path of Bear's database file, if this handle reads from a file.
+  pub(crate) fn database_file(&self) -> Option<&Path> {
+    match &self.db_path {
+      DatabasePath::RealPath(path) => Some(path),
+      #[cfg(test)]
+      _ => None,
+    }
+  }
+
+  /// Retrieves every attachment, grouped by the note it belongs to.
+  pub(crate) fn note_attachments(&self) -> Result<HashMap<NoteId, Vec<Attachment>>, BearError> {
+    self.with_connection(|queryable| {
+      let mut statement = queryable.prepare(
+        r"
+      SELECT
+        id,
+        note_id,
+        filename
+      FROM attachments
+      WHERE id IS NOT NULL AND note_id IS NOT NULL AND filename IS NOT NULL
+      ORDER BY filename ASC",
+      )?;
+
+      let mut attachments: HashMap<NoteId, Vec<Attachment>> = HashMap::new();
+      for attachment in statement.query_map([], attachment_from_row)? {
+        let attachment = attachment?;
+        attachments
+          .entry(attachment.note_id().clone())
+          .or_default()
+          .push(attachment);
+      }
+
+      Ok(attachments)
+    })
+  }
+
+  /// Writes each normalized table (`notes`, `tags`, `note_tags`, `note_links`,
+  /// `attachments`) to `<dir>/<table>.<extension>` in the given format.
+  ///
+  /// The directory is created if needed and existing files are overwritten. Returns
+  /// the written paths, in the order above. Available with any of the `parquet`,
+  /// `ipc` or `csv` cargo features.
+  ///
+  /// # Example
+  ///
+  /// ```no_run
+  /// # #[cfg(feature = "parquet")]
+  /// # fn main() -> Result<(), bear_query::BearError> {
+  /// use bear_query::BearDb;
+  /// use bear_query::export::TableFormat;
+  ///
+  /// let db = BearDb::new()?;
+  /// for path in db.export_tables("bear-tables", TableFormat::Parquet)? {
+  ///     println!("Wrote {}", path.display());
+  /// }
+  /// # Ok(())
+  /// # }
+  /// # #[cfg(not(feature = "parquet"))]
+  /// # fn main() {}
+  /// ```
+  #[cfg(any(feature = "parquet", feature = "ipc", feature = "csv"))]
+  pub fn export_tables(
+    &self,
+    dir: impl AsRef<Path>,
+    format: export::TableFormat,
+  ) -> Result<Vec<PathBuf>, BearError> {
+    let dir = dir.as_ref();
+    std::fs::create_dir_all(dir)?;
+
+    let mut written = Vec::new();
+    for table in ["notes", "tags", "note_tags", "note_links", "attachments"] {
+      let mut df = self.query(&format!("SELECT * FROM {}", table))?;
+      let path = dir.join(format!("{}.{}", table, format.extension()));
+      format.write(&mut df, std::fs::File::create(&path)?)?;
+      written.push(path);
+    }
+
+    Ok(written)
+  }
+
+  /// Returns Bear's `Local Files` directory, where attachment files are stored.
+  pub(crate) fn files_root(&self) -> Option<&Path> {
+    self.files_root.as_deref()
+  }
+
+  /// Finds every note containing a heading at the given path.
+  ///
+  /// The path uses the same `/`-separated syntax as `Note::section()`, so both
+  /// `"Meeting Notes"` and `"Meeting Notes/Action Items"` are accepted. Trashed and
+  /// archived notes are excluded. Results are ordered by most recently modified.
+  ///
+  /// Heading text is compared without its inline markup, so it can't be matched in
+  /// SQL directly. Instead notes are narrowed down in SQL to those whose content
+  /// holds the longest word of every path segment, and only those are parsed. A
+  /// heading with markup inside a word (`## **Act**ion Items`) is therefore not found.
+  ///
+  /// # Examples
+  /// ```no_run
+  /// # use bear_query::BearDb;
+  /// # fn main() -> Result<(), bear_query::BearError> {
+  /// let db = BearDb::new()?;
+  ///
+  /// for note in db.find_sections("Action Items")? {
+  ///     let items = note.section("Action Items").unwrap_or_default();
+  ///     println!("{}:\n{}", note.title(), items.trim());
+  /// }
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn find_sections(
+    &self,
+    heading_path: &str,
+  ) -> Result<Vec<Note>, BearError> {
+    let keywords = outline::path_keywords(heading_path);
+    let keyword_filter = " AND content LIKE '%' || ? || '%'".repeat(keywords.len());
+
+    self.with_connection(|queryable| {
+      let mut statement = queryable.prepare(&format!(
+        r"
+      SELECT
+        id,
+        core_db_id,
+        title,
+        content,
+        modified,
+        created,
+        core_data_modified,
+        core_data_created,
+        is_pinned
+      FROM notes
+      WHERE is_trashed <> 1 AND is_archived <> 1{keyword_filter}
+      ORDER BY modified DESC"
+      ))?;
+
+      let candidates: rusqlite::Result<Vec<Note>> = statement
+        .query_map(rusqlite::params_from_iter(&keywords), note_from_row)?
+        .collect();
+
+      Ok(
+        candidates?
+          .into_iter()
+          .filter(|note| note.section(heading_path).is_some())
+          .collect(),
+      )
+    })
+  }
+
+  /// Execute a generic SQL SELECT query and return results as a Polars DataFrame.
+  ///
+  /// The query automatically has the normalizing CTEs prepended, so you can query
+  /// against clean table names: `notes`, `tags`, `note_tags`, `note_links`.
+  ///
+  /// Custom SQL functions are also available, so text statistics can be added as
+  /// columns: `plain_text(content)` returns the content without Markdown syntax,
+  /// `word_count(content)` returns its Unicode word count and `local_date(modified)`
+  /// returns the date in the handle's time zone.
+  ///
+  /// Result columns named `modified` or `created` are returned as
+  /// `Datetime(Microseconds, <zone>)`, in the zone of [`BearDb::with_time_zone()`], and `is_pinned`, `is_trashed` and `is_archived` as
+  /// `Boolean`. Use [`BearDb::query_with_options()`] to type other columns.
+  ///
+  /// # Safety
+  /// Only reads are allowed. Besides the read-only connection flags, an authorizer
+  /// rejects writes, schema changes, reads of tables other than Bear's, `ATTACH`,
+  /// pragmas that set a value, `load_extension()` and SQL holding more than one
+  /// statement, with `BearError::ForbiddenStatement`. The same applies to every
+  /// generic query method.
+  ///
+  /// # Examples
+  /// ```no_run
+  /// # use bear_query::BearDb;
+  /// # fn main() -> Result<(), bear_query::BearError> {
+  /// let db = BearDb::new()?;
+  ///
+  /// // Query normalized tables
+  /// let df = db.query("SELECT title, modified FROM notes LIMIT 5")?;
+  ///
+  /// // Join tables
+  /// let df = db.query(r"
+  ///   SELECT n.title, t.name as tag_name
+  ///   FROM notes n
+  ///   JOIN note_tags nt ON n.id = nt.note_id
+  ///   JOIN tags t ON nt.tag_id = t.id
+  ///   WHERE n.is_trashed = 0
+  ///   LIMIT 10
+  /// ")?;
+  ///
+  /// println!("{}", df);
+  ///
+  /// // Add a word count column
+  /// let df = db.query("SELECT title, word_count(content) as words FROM notes")?;
+  /// # Ok(())
+  /// # }
+  /// ```
+  #[cfg(feature = "polars")]
+  pub fn query(
+    &self,
+    sql: &str,
+  ) -> Result<DataFrame, BearError> {
+    self.query_with_options(sql, QueryOptions::default())
+  }
+
+  /// Execute a generic SQL SELECT query like [`BearDb::query()`], with explicit
+  /// column types.
+  ///
+  /// # Examples
+  /// ```no_run
+  /// # use bear_query::{BearDb, QueryOptions};
+  /// # fn main() -> Result<(), bear_query::BearError> {
+  /// let db = BearDb::new()?;
+  ///
+  /// let df = db.query_with_options(
+  ///   "SELECT date(modified) AS day, COUNT(*) AS edits FROM notes GROUP BY day",
+  ///   QueryOptions::new().parse_dates(["day"]),
+  /// )?;
+  /// # Ok(())
+  /// # }
+  /// ```
+  #[cfg(feature = "polars")]
+  pub fn query_with_options(
+    &self,
+    sql: &str,
+    options: QueryOptions,
+  ) -> Result<DataFrame, BearError> {
+    let options = options.or_time_zone(self.time_zone);
+    let limits = options.query_limits().unwrap_or(&self.query_limits);
+    self.with_retries(limits, |queryable| {
+      query_to_dataframe(queryable, sql, [], &options)
+    })
+  }
+
+  /// Returns the plan SQLite would use for a generic query, without running it.
+  ///
+  /// The plan is the parsed `EXPLAIN QUERY PLAN` tree of the query with the
+  /// normalizing CTEs prepended ([`QueryPlan::sql()`]), so it names Bear's tables by
+  /// the CTEs' aliases; [`TableAccess::table()`] resolves them. Printing the plan
+  /// shows the tree and flags full scans of tables with at least
+  /// [`LARGE_TABLE_ROWS`] rows.
+  ///
+  /// # Examples
+  /// ```no_run
+  /// # use bear_query::BearDb;
+  /// # fn main() -> Result<(), bear_query::BearError> {
+  /// let db = BearDb::new()?;
+  ///
+  /// let plan = db.explain("SELECT n.title FROM notes n JOIN note_tags nt ON n.id = nt.note_id")?;
+  /// println!("{}", plan);
+  ///
+  /// for scan in plan.large_full_scans() {
+  ///   println!("{:?} scans {} rows", scan.table(), scan.rows().unwrap_or(0));
+  /// }
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn explain(
+    &self,
+    sql: &str,
+  ) -> Result<QueryPlan, BearError> {
+    self.with_connection(|queryable| plan::explain(queryable, sql))
+  }
+
+  /// Execute a generic SQL SELECT query and return the raw SQLite values of its rows.
+  ///
+  /// This is the generic query API without Polars: it works with or without the
+  /// `polars` cargo feature. Values keep their SQLite type (so timestamps are
+  /// `Value::Text` and flags `Value::Integer`), and parameters are bound like in
+  /// [`BearDb::query_with_params()`].
+  ///
+  /// # Examples
+  /// ```no_run
+  /// # use bear_query::{BearDb, Value};
+  /// # fn main() -> Result<(), bear_query::BearError> {
+  /// let db = BearDb::new()?;
+  ///
+  /// let result = db.query_rows("SELECT title, modified FROM notes LIMIT ?1", [5])?;
+  /// for row in result.rows() {
+  ///   if let Value::Text(title) = &row[0] {
+  ///     println!("{}", title);
+  ///   }
+  /// }
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn query_rows(
+    &self,
+    sql: &str,
+    params: impl Params + Clone,
+  ) -> Result<QueryRows, BearError> {
+    self.with_connection(|queryable| rows::query_rows(queryable, sql, params.clone()))
+  }
+
+  /// Execute a generic SQL SELECT query and deserialize each row into `T` with serde.
+  ///
+  /// Columns map onto struct fields by name (extra columns are ignored), onto tuple
+  /// elements by position, or, for a single-column query, onto `T` itself. Integer
+  /// columns deserialize into `bool` fields, and timestamp columns into
+  /// `OffsetDateTime` fields with [`datetime`] (`#[serde(with = "bear_query::datetime")]`).
+  /// Parameters are bound like in [`BearDb::query_with_params()`].
+  ///
+  /// # Examples
+  /// ```no_run
+  /// # use bear_query::BearDb;
+  /// use serde::Deserialize;
+  /// use time::OffsetDateTime;
+  ///
+  /// #[derive(Deserialize)]
+  /// struct PinnedNote {
+  ///   id: String,
+  ///   title: String,
+  ///   #[serde(with = "bear_query::datetime")]
+  ///   modified: OffsetDateTime,
+  ///   is_archived: bool,
+  /// }
+  ///
+  /// # fn main() -> Result<(), bear_query::BearError> {
+  /// let db = BearDb::new()?;
+  ///
+  /// let pinned: Vec<PinnedNote> = db.query_as(
+  ///   "SELECT id, title, modified, is_archived FROM notes WHERE is_pinned = ?1",
+  ///   [true],
+  /// )?;
+  ///
+  /// let titles: Vec<String> = db.query_as("SELECT title FROM notes", [])?;
+  /// let counts: Vec<(String, i64)> = db.query_as(
+  ///   "SELECT name, COUNT(*) FROM tags JOIN note_tags ON tags.id = note_tags.tag_id GROUP BY name",
+  ///   [],
+  /// )?;
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn query_as<T: DeserializeOwned>(
+    &self,
+    sql: &str,
+    params: impl Params + Clone,
+  ) -> Result<Vec<T>, BearError> {
+    self.with_connection(|queryable| rows::query_as(queryable, sql, params.clone()))
+  }
+
+  /// Execute a generic SQL SELECT query like [`BearDb::query()`], binding parameters
+  /// instead of splicing values into the SQL.
+  ///
+  /// Accepts anything implementing rusqlite's [`Params`]: positional parameters
+  /// (`?1`, `?2`) with [`params!`] or an array, and named parameters (`:tag`) with
+  /// [`named_params!`]. Use this for any value that comes from user input.
+  ///
+  /// # Examples
+  /// ```no_run
+  /// # use bear_query::{BearDb, named_params, params};
+  /// # fn main() -> Result<(), bear_query::BearError> {
+  /// let db = BearDb::new()?;
+  ///
+  /// let df = db.query_with_params(
+  ///   "SELECT title FROM notes WHERE title LIKE ?1 LIMIT ?2",
+  ///   params!["%meeting%", 10],
+  /// )?;
+  ///
+  /// let df = db.query_with_params(
+  ///   r"
+  ///   SELECT n.title
+  ///   FROM notes n
+  ///   JOIN note_tags nt ON n.id = nt.note_id
+  ///   JOIN tags t ON nt.tag_id = t.id
+  ///   WHERE t.name = :tag
+  /// ",
+  ///   named_params! { ":tag": "work" },
+  /// )?;
+  /// # Ok(())
+  /// # }
+  /// ```
+  #[cfg(feature = "polars")]
+  pub fn query_with_params(
+    &self,
+    sql: &str,
+    params: impl Params + Clone,
+  ) -> Result<DataFrame, BearError> {
+    let options = QueryOptions::default().or_time_zone(self.time_zone);
+    self.with_connection(|queryable| query_to_dataframe(queryable, sql, params.clone(), &options))
+  }
+
+  /// Execute a generic SQL SELECT query like [`BearDb::query_with_params()`], passing
+  /// the result to `f` in DataFrames of at most `batch_size` rows instead of building
+  /// one DataFrame with every row.
+  ///
+  /// Each batch's column types come from its own rows, so give a `schema()` in
+  /// `options` when a column could be all NULL in some batches. A query returning no
+  /// rows produces no batch. Returning an error from `f` stops the query. The result
+  /// budget of [`QueryLimits`] still counts every row of the query. The query isn't
+  /// retried by a [`RetryPolicy`], since `f` may already have seen some batches.
+  ///
+  /// # Examples
+  /// ```no_run
+  /// # use bear_query::{BearDb, QueryOptions};
+  /// # fn main() -> Result<(), bear_query::BearError> {
+  /// let db = BearDb::new()?;
+  ///
+  /// let mut notes = 0;
+  /// db.query_batches(
+  ///   "SELECT title, content FROM notes",
+  ///   [],
+  ///   1000,
+  ///   QueryOptions::new(),
+  ///   |df| {
+  ///     notes += df.height();
+  ///     println!("{}", df.head(Some(1)));
+  ///     Ok(())
+  ///   },
+  /// )?;
+  /// # Ok(())
+  /// # }
+  /// ```
+  #[cfg(feature = "polars")]
+  pub fn query_batches<F>(
+    &self,
+    sql: &str,
+    params: impl Params,
+    batch_size: usize,
+    options: QueryOptions,
+    f: F,
+  ) -> Result<(), BearError>
+  where
+    F: FnMut(DataFrame) -> Result<(), BearError>,
+  {
+    let options = options.or_time_zone(self.time_zone);
+    let limits = options.query_limits().unwrap_or(&self.query_limits);
+    self.with_limits(limits, |queryable| {
+      query_to_dataframes(queryable, sql, params, batch_size, &options, f)
+    })
+  }
+}
+
+/// Reads every link between notes as (from, to) pairs, ordered by source then target.
+pub(crate) fn read_note_link_ids(
+  queryable: &Queryable
+) -> Result<Vec<(NoteId, NoteId)>, BearError> {
+  let mut statement = queryable.prepare(
+    r"
+      SELECT
+        from_note_id,
+        to_note_id
+      FROM note_links
+      WHERE from_note_id IS NOT NULL AND to_note_id IS NOT NULL
+      ORDER BY from_note_id, to_note_id",
+  )?;
+
+  let links: rusqlite::Result<Vec<(NoteId, NoteId)>> = statement
+    .query_map([], |row| {
+      Ok((
+        NoteId::new(row.get("from_note_id")?),
+        NoteId::new(row.get("to_note_id")?),
+      ))
+    })?
+    .collect();
+
+  Ok(links?)
+}
+
+/// Reads the names of every note's indexed tags, keyed by note.
+pub(crate) fn read_note_tag_names(
+  queryable: &Queryable
+) -> Result<BTreeMap<NoteId, BTreeSet<String>>, BearError> {
+  let mut statement = queryable.prepare(
+    r"
+      SELECT
+        nt.note_id,
+        t.name
+      FROM note_tags nt
+      JOIN tags t ON t.id = nt.tag_id
+      WHERE t.name IS NOT NULL",
+  )?;
+
+  let mut names: BTreeMap<NoteId, BTreeSet<String>> = BTreeMap::new();
+  let rows = statement.query_map([], |row| {
+    Ok((NoteId::new(row.get("note_id")?), row.get("name")?))
+  })?;
+  for row in rows {
+    let (note_id, name) = row?;
+    names.entry(note_id).or_default().insert(name);
+  }
+
+  Ok(names)
+}
 
 /// A wrapper around a database connection that automatically applies normalizing CTEs to queries.
 /// This abstracts away Bear's Core Data quirks (Z_ prefixes, numbered columns, epoch timestamps).
 pub struct Queryable<'a> {
   conn: &'a Connection,
   normalizing_cte: &'a str,
+  budget: limits::ResultBudget,
+  /// The user SQL of the last prepared statement, for errors
+  last_sql: RefCell<Option<String>>,
 }
 
 impl<'a> Queryable<'a> {
-  /// Creates a new Queryable from a connection and pre-generated CTE string
+  /// Creates a new Queryable from a connection, pre-generated CTE string and the
+  /// budget of generic query results
   fn new(
     conn: &'a Connection,
     normalizing_cte: &'a str,
+    budget: limits::ResultBudget,
   ) -> Self {
     Self {
       conn,
       normalizing_cte,
+      budget,
+      last_sql: RefCell::new(None),
     }
   }
 
   /// Test-only constructor for creating Queryable in tests
   ///
   /// This is pub(crate) so tests in other modules can create Queryables
-  #[cfg(test)]
+  #[cfg(all(test, feature = "polars"))]
   pub(crate) fn new_for_test(
     conn: &'a Connection,
     normalizing_cte: &'a str,
   ) -> Self {
-    Self::new(conn, normalizing_cte)
+    Self::new(conn, normalizing_cte, limits::ResultBudget::default())
+  }
+
+  /// The row and byte budget of a generic query's result, with nothing counted yet
+  pub(crate) fn result_budget(&self) -> limits::ResultBudget {
+    self.budget
   }
 
   /// Prepares a statement with the normalizing CTE automatically prepended.
-  /// The user's SQL should query against normalized table names (notes, tags, note_tags, note_links).
+  /// The user's SQL should query against normalized table names (notes, tags, note_tags, note_links, attachments).
   pub fn prepare(
     &self,
     user_sql: &str,
   ) -> rusqlite::Result<rusqlite::Statement<'a>> {
-    let full_sql = format!("{}\n{}", self.normalizing_cte, user_sql);
-    self.conn.prepare(&full_sql)
+    self.last_sql.replace(Some(user_sql.to_string()));
+    self.conn.prepare(&self.full_sql(user_sql))
+  }
+
+  /// The user SQL of the last statement prepared with `prepare()`
+  fn last_sql(&self) -> Option<String> {
+    self.last_sql.borrow().clone()
+  }
+
+  /// Returns the user's SQL with the normalizing CTE prepended
+  pub(crate) fn full_sql(
+    &self,
+    user_sql: &str,
+  ) -> String {
+    format!("{}\n{}", self.normalizing_cte, user_sql)
   }
 }
 
@@ -966,6 +2017,31 @@ mod tests {
       .unwrap();
     assert_eq!(all_notes.len(), 5); // 5 notes total including trashed
 
+    // Test the polars-free generic query API
+    let result = db
+      .query_rows(
+        "SELECT id, is_pinned FROM notes WHERE is_trashed = ?1 ORDER BY core_db_id",
+        [false],
+      )
+      .unwrap();
+    assert_eq!(result.columns(), ["id", "is_pinned"]);
+    assert_eq!(result.column_index("is_pinned"), Some(1));
+    assert_eq!(result.len(), 4);
+    assert_eq!(
+      result.rows()[1],
+      vec![
+        rusqlite::types::Value::Text("note-uuid-2".to_string()),
+        rusqlite::types::Value::Integer(1),
+      ]
+    );
+  }
+
+  /// Integration test of the DataFrame query API with in-memory database
+  #[cfg(feature = "polars")]
+  #[test]
+  fn test_dataframe_query_with_inmemory() {
+    let db = BearDb::new_with_path(DatabasePath::InMemory).unwrap();
+
     // Test the generic SQL query API
     let df = db
       .query("SELECT id, title FROM notes WHERE is_trashed = 0")
@@ -1093,6 +2169,7 @@ mod tests {
   }
 
   /// Test querying for notes with empty title using generic query API
+  #[cfg(feature = "polars")]
   #[test]
   fn test_query_with_empty_title() {
     let db = BearDb::new_with_path(DatabasePath::InMemory).unwrap();
@@ -1106,6 +2183,7 @@ mod tests {
   }
 
   /// Test querying for notes with NULL content
+  #[cfg(feature = "polars")]
   #[test]
   fn test_query_with_null_content() {
     let db = BearDb::new_with_path(DatabasePath::InMemory).unwrap();
@@ -1514,6 +2592,620 @@ mod tests {
     let _results = db.search(query).unwrap();
   }
 
+  /// Notes with nested headings for outline and section tests
+  const SECTION_FIXTURE: &str = r"
+    INSERT INTO ZSFNOTE (Z_PK, ZUNIQUEIDENTIFIER, ZTITLE, ZTEXT, ZMODIFICATIONDATE, ZCREATIONDATE, ZPINNED, ZTRASHED, ZARCHIVED)
+    VALUES
+      (10, 'note-uuid-10', 'Standup', '# Standup
+## Action Items
+- ship it
+', 10, 10, 0, 0, 0),
+      (11, 'note-uuid-11', 'Retro', '# Retro
+Talked about Action Items without a heading
+', 20, 20, 0, 0, 0),
+      (12, 'note-uuid-12', 'Old Standup', '# Old Standup
+## Action Items
+- archived
+', 30, 30, 0, 0, 1),
+      (13, 'note-uuid-13', 'Markup', '# Markup
+## Using `query()`
+### **Next** [Steps](https://bear.app)
+- read the docs
+', 40, 40, 0, 0, 0);
+  ";
+
+  /// Test find_sections only returns notes with a matching heading
+  #[test]
+  fn test_find_sections() {
+    let db = BearDb::new_with_path(DatabasePath::InMemoryWith(SECTION_FIXTURE)).unwrap();
+
+    let notes = db.find_sections("Action Items").unwrap();
+    assert_eq!(notes.len(), 1);
+    assert_eq!(notes[0].title(), "Standup");
+    assert_eq!(
+      notes[0].section("Action Items").map(str::trim),
+      Some("- ship it")
+    );
+
+    let notes = db.find_sections("Standup/Action Items").unwrap();
+    assert_eq!(notes.len(), 1);
+
+    assert!(db.find_sections("Retro/Action Items").unwrap().is_empty());
+
+    // Headings match without their inline code, emphasis and links
+    let notes = db.find_sections("Using query()/Next Steps").unwrap();
+    assert_eq!(notes.len(), 1);
+    assert_eq!(notes[0].title(), "Markup");
+    assert_eq!(
+      notes[0].section("Next Steps").map(str::trim),
+      Some("- read the docs")
+    );
+  }
+
+  /// Notes whose inline tags agree and disagree with the tag index
+  const HASHTAG_FIXTURE: &str = r"
+    INSERT INTO ZSFNOTE (Z_PK, ZUNIQUEIDENTIFIER, ZTITLE, ZTEXT, ZMODIFICATIONDATE, ZCREATIONDATE, ZPINNED, ZTRASHED, ZARCHIVED)
+    VALUES
+      (20, 'note-uuid-20', 'Tagged', '# Tagged
+Filed under #work and #personal', 10, 10, 0, 0, 0);
+
+    INSERT INTO Z_5TAGS (Z_5NOTES, Z_13TAGS)
+    VALUES
+      (20, 1),
+      (20, 2);
+  ";
+
+  /// Test tag_consistency flags index rows that are not written in the note
+  #[test]
+  fn test_tag_consistency() {
+    let db = BearDb::new_with_path(DatabasePath::InMemoryWith(HASHTAG_FIXTURE)).unwrap();
+
+    let tagged = db
+      .note(&NoteId::new("note-uuid-20".to_string()))
+      .unwrap()
+      .unwrap();
+    let inline: Vec<String> = tagged
+      .inline_tags()
+      .iter()
+      .map(|tag| tag.name().to_string())
+      .collect();
+    assert_eq!(inline, vec!["work", "personal"]);
+
+    let report = db
+      .tag_consistency(NotesQuery::new().no_limit().include_all())
+      .unwrap();
+    assert_eq!(report.notes_checked(), 6);
+    assert!(!report.is_consistent());
+
+    // The fixture note is consistent; the base test notes are indexed without inline tags
+    let drifted: Vec<&str> = report
+      .drift()
+      .iter()
+      .map(|drift| drift.note_id().as_str())
+      .collect();
+    assert_eq!(drifted.len(), 2);
+    assert!(drifted.contains(&"note-uuid-1"));
+    assert!(drifted.contains(&"note-uuid-2"));
+
+    let first = report
+      .drift()
+      .iter()
+      .find(|drift| drift.note_id().as_str() == "note-uuid-1")
+      .unwrap();
+    assert!(first.missing_from_index().is_empty());
+    assert!(first.missing_from_content().contains("work"));
+  }
+
+  /// Test plain text statistics on notes
+  #[test]
+  fn test_word_count_column() {
+    let db = BearDb::new_with_path(DatabasePath::InMemory).unwrap();
+
+    let note = db
+      .note(&NoteId::new("note-uuid-1".to_string()))
+      .unwrap()
+      .unwrap();
+    assert_eq!(note.plain_text(), "Content of first note");
+    assert_eq!(note.word_count(), 4);
+    assert_eq!(note.char_count(), 18);
+  }
+
+  /// Test text statistics as a DataFrame column
+  #[cfg(feature = "polars")]
+  #[test]
+  fn test_word_count_query_column() {
+    let db = BearDb::new_with_path(DatabasePath::InMemory).unwrap();
+
+    let df = db
+      .query("SELECT word_count(content) as words FROM notes ORDER BY core_db_id")
+      .unwrap();
+    let words = df.column("words").unwrap().i64().unwrap();
+    assert_eq!(words.get(0), Some(4));
+    // Note 5 has NULL content
+    assert_eq!(words.get(4), None);
+  }
+
+  /// Test binding user input to a generic query
+  #[cfg(feature = "polars")]
+  #[test]
+  fn test_query_with_params() {
+    let db = BearDb::new_with_path(DatabasePath::InMemory).unwrap();
+
+    let sql = r"
+      SELECT n.title
+      FROM notes n
+      JOIN note_tags nt ON n.id = nt.note_id
+      JOIN tags t ON nt.tag_id = t.id
+      WHERE t.name = :tag
+    ";
+    let df = db
+      .query_with_params(sql, named_params! { ":tag": "personal" })
+      .unwrap();
+    let titles = df.column("title").unwrap().str().unwrap();
+    assert_eq!(
+      titles.into_iter().collect::<Vec<_>>(),
+      vec![Some("Second Note")]
+    );
+
+    let df = db
+      .query_with_params(
+        "SELECT id FROM notes WHERE core_db_id > ?1 AND is_trashed = ?2",
+        params![3, false],
+      )
+      .unwrap();
+    assert_eq!(df.height(), 2);
+  }
+
+  /// Test mapping generic query rows onto structs, tuples and single values
+  #[test]
+  fn test_query_as() {
+    use serde::Deserialize;
+    use time::OffsetDateTime;
+
+    #[derive(Debug, Deserialize)]
+    struct Row {
+      id: String,
+      content: Option<String>,
+      #[serde(with = "crate::datetime")]
+      modified: OffsetDateTime,
+      #[serde(with = "crate::datetime")]
+      core_data_modified: OffsetDateTime,
+      is_pinned: bool,
+    }
+
+    let db = BearDb::new_with_path(DatabasePath::InMemory).unwrap();
+
+    let rows: Vec<Row> = db
+      .query_as(
+        "SELECT * FROM notes WHERE core_db_id IN (?1, ?2) ORDER BY core_db_id",
+        params![2, 5],
+      )
+      .unwrap();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0].id, "note-uuid-2");
+    assert_eq!(rows[0].content.as_deref(), Some("Content of second note"));
+    assert_eq!(rows[0].modified.unix_timestamp(), 1_009_843_200);
+    assert_eq!(rows[0].core_data_modified, rows[0].modified);
+    assert!(rows[0].is_pinned);
+    assert_eq!(rows[1].content, None);
+    assert!(!rows[1].is_pinned);
+
+    let pairs: Vec<(String, i64)> = db
+      .query_as(
+        "SELECT name, COUNT(*) FROM tags JOIN note_tags ON tags.id = note_tags.tag_id GROUP BY name ORDER BY name",
+        [],
+      )
+      .unwrap();
+    assert_eq!(
+      pairs,
+      vec![("personal".to_string(), 1), ("work".to_string(), 1)]
+    );
+
+    let titles: Vec<String> = db
+      .query_as(
+        "SELECT title FROM notes WHERE title LIKE :prefix ORDER BY title",
+        named_params! { ":prefix": "F%" },
+      )
+      .unwrap();
+    assert_eq!(titles, vec!["First Note"]);
+  }
+
+  /// Two saves of one note within the same second
+  const SUBSECOND_FIXTURE: &str = r"
+    INSERT INTO ZSFNOTE (Z_PK, ZUNIQUEIDENTIFIER, ZTITLE, ZTEXT, ZMODIFICATIONDATE, ZCREATIONDATE, ZPINNED, ZTRASHED, ZARCHIVED)
+    VALUES (20, 'note-uuid-20', 'Quick Edits', 'Edited twice', 31536000.123456, 31536000.5, 0, 0, 0);
+  ";
+
+  /// Test that timestamps keep their sub-second precision
+  #[test]
+  fn test_subsecond_timestamps() {
+    let db = BearDb::new_with_path(DatabasePath::InMemoryWith(SUBSECOND_FIXTURE)).unwrap();
+
+    let note = db
+      .note(&NoteId::new("note-uuid-20".to_string()))
+      .unwrap()
+      .unwrap();
+    assert_eq!(note.core_data_modified(), 31_536_000.123_456);
+    assert_eq!(note.core_data_created(), 31_536_000.5);
+    assert_eq!(note.modified().unix_timestamp(), 1_009_843_200);
+    assert_eq!(note.modified().microsecond(), 123_456);
+    assert_eq!(note.created().millisecond(), 500);
+    assert!(note.modified() < note.created());
+
+    let modified: Vec<(String, f64)> = db
+      .query_as(
+        "SELECT modified, core_data_modified FROM notes WHERE id = 'note-uuid-20'",
+        [],
+      )
+      .unwrap();
+    assert_eq!(
+      modified,
+      vec![("2002-01-01 00:00:00.123".to_string(), 31_536_000.123_456)]
+    );
+
+    let tags = db.tags().unwrap();
+    let tag = tags.iter().find(|tag| tag.name() == Some("work")).unwrap();
+    assert_eq!(
+      tag.modified().map(|modified| modified.unix_timestamp()),
+      tag
+        .core_data_modified()
+        .map(|seconds| seconds as i64 + 978_307_200)
+    );
+  }
+
+  /// Test filtering notes by local date and the local date SQL functions
+  #[test]
+  fn test_time_zones() {
+    use time::macros::date;
+
+    let pacific = TimeZone::named("America/Los_Angeles").unwrap();
+    let db = BearDb::new_with_path(DatabasePath::InMemory)
+      .unwrap()
+      .with_time_zone(pacific);
+    let ids = |notes: Vec<Note>| {
+      let mut ids: Vec<String> = notes
+        .iter()
+        .map(|note| note.id().as_str().to_string())
+        .collect();
+      ids.sort();
+      ids
+    };
+
+    // Core Data 0 is midnight UTC on 2001-01-01, still 2000-12-31 in California
+    let utc_day = NotesQuery::new().modified_on(date!(2001 - 01 - 01), TimeZone::Utc);
+    assert_eq!(
+      ids(db.notes(utc_day).unwrap()),
+      vec!["note-uuid-1", "note-uuid-4", "note-uuid-5"]
+    );
+    let pacific_day = NotesQuery::new().modified_on(date!(2001 - 01 - 01), pacific);
+    assert!(db.notes(pacific_day).unwrap().is_empty());
+    let pacific_day = NotesQuery::new().modified_on(date!(2000 - 12 - 31), pacific);
+    assert_eq!(db.notes(pacific_day).unwrap().len(), 3);
+
+    let created = NotesQuery::new().include_all().created_on(
+      date!(2001 - 12 - 31),
+      time::UtcOffset::from_hms(-8, 0, 0).unwrap(),
+    );
+    assert_eq!(ids(db.notes(created).unwrap()), vec!["note-uuid-2"]);
+
+    let search = SearchQuery::new("Note").modified_on(date!(2001 - 12 - 31), pacific);
+    assert_eq!(ids(db.search(search).unwrap()), vec!["note-uuid-2"]);
+
+    let days: Vec<(String, String)> = db
+      .query_as(
+        "SELECT local_date(modified), local_date(modified, 'UTC') FROM notes WHERE id = 'note-uuid-2'",
+        [],
+      )
+      .unwrap();
+    assert_eq!(
+      days,
+      vec![("2001-12-31".to_string(), "2002-01-01".to_string())]
+    );
+  }
+
+  /// Test the structured errors and which of them are retryable
+  #[test]
+  fn test_error_variants() {
+    let dir = tempfile::tempdir().unwrap();
+    let missing = dir.path().join("database.sqlite");
+    match BearDb::new_with_path(DatabasePath::RealPath(missing.clone())) {
+      Err(BearError::DatabaseNotFound(path)) => assert_eq!(path, missing),
+      other => panic!("expected DatabaseNotFound, got {:?}", other.err()),
+    }
+
+    // A file that isn't a Bear database
+    let empty = dir.path().join("empty.sqlite");
+    Connection::open(&empty).unwrap();
+    assert!(matches!(
+      BearDb::new_with_path(DatabasePath::RealPath(empty)),
+      Err(BearError::SchemaUnsupported { .. })
+    ));
+
+    let db = BearDb::new_with_path(DatabasePath::InMemory).unwrap();
+    // Lookups of unknown notes aren't errors
+    let unknown = NoteId::new("nonexistent-uuid".to_string());
+    assert!(db.note(&unknown).unwrap().is_none());
+    assert!(db.note_links(&unknown).unwrap().is_empty());
+    assert!(db.note_tags(&unknown).unwrap().is_empty());
+
+    let busy =
+      rusqlite::Error::SqliteFailure(rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_BUSY), None);
+    let error = BearError::from(busy).with_sql(Some("SELECT * FROM notes".to_string()));
+    assert!(matches!(&error, BearError::Busy { sql: Some(sql) } if sql == "SELECT * FROM notes"));
+    assert_eq!(
+      error.to_string(),
+      "Bear's database is busy (running: SELECT * FROM notes)"
+    );
+    assert!(error.is_retryable());
+
+    // Failed queries name the SQL that failed, without the normalizing CTEs
+    let error = db.query_rows("SELECT nope FROM notes", []).unwrap_err();
+    assert!(
+      matches!(&error, BearError::SqlError { sql: Some(sql), .. } if sql == "SELECT nope FROM notes")
+    );
+    assert!(
+      error
+        .to_string()
+        .ends_with("(running: SELECT nope FROM notes)"),
+      "{}",
+      error
+    );
+
+    for error in [
+      BearError::DatabaseNotFound(missing),
+      BearError::Cancelled,
+      db.query_rows("SELECT nope FROM notes", []).unwrap_err(),
+    ] {
+      assert!(!error.is_retryable(), "{:?}", error);
+    }
+  }
+
+  /// Test that a retry policy reruns operations failing with transient errors
+  #[test]
+  fn test_retry_policy() {
+    use std::sync::{Arc, Mutex};
+
+    let retries = Arc::new(Mutex::new(Vec::new()));
+    let log = retries.clone();
+    let db = BearDb::new_with_path(DatabasePath::InMemory)
+      .unwrap()
+      .with_retry_policy(
+        RetryPolicy::new()
+          .max_attempts(3)
+          .backoff(Duration::from_millis(1), Duration::from_millis(2))
+          .on_retry(move |event| {
+            if let RetryEvent::Retrying { attempt, error, .. } = event {
+              log.lock().unwrap().push((*attempt, error.is_retryable()));
+            }
+          }),
+      );
+
+    // Each attempt gets a working connection
+    let mut attempts = 0;
+    let count = db
+      .with_connection(|queryable| {
+        attempts += 1;
+        if attempts < 3 {
+          return Err(BearError::Busy { sql: None });
+        }
+        let mut statement = queryable.prepare("SELECT COUNT(*) FROM notes")?;
+        let count: i64 = statement.query_row([], |row| row.get(0))?;
+        Ok(count)
+      })
+      .unwrap();
+    assert_eq!(count, 5);
+    assert_eq!(*retries.lock().unwrap(), [(1, true), (2, true)]);
+
+    let mut attempts = 0;
+    let result: Result<(), _> = db.with_connection(|_| {
+      attempts += 1;
+      Err(BearError::Busy { sql: None })
+    });
+    assert!(matches!(result, Err(BearError::Busy { .. })));
+    assert_eq!(attempts, 3);
+
+    // Errors in the query itself fail at once
+    retries.lock().unwrap().clear();
+    assert!(db.query_rows("SELECT nope FROM notes", []).is_err());
+    assert!(retries.lock().unwrap().is_empty());
+  }
+
+  /// Test that row mapping errors name the row and column
+  #[test]
+  fn test_query_as_errors() {
+    #[derive(Debug, serde::Deserialize)]
+    struct Row {
+      #[allow(dead_code)]
+      title: i64,
+    }
+
+    let db = BearDb::new_with_path(DatabasePath::InMemory).unwrap();
+
+    let error = db
+      .query_as::<Row>("SELECT title FROM notes ORDER BY core_db_id", [])
+      .unwrap_err();
+    assert!(matches!(error, BearError::DeserializeError { .. }));
+    let message = error.to_string();
+    assert!(message.contains("row 0"), "{}", message);
+    assert!(message.contains("column 'title'"), "{}", message);
+
+    let error = db.query_as::<Row>("SELECT id FROM notes", []).unwrap_err();
+    assert!(
+      error.to_string().contains("missing field `title`"),
+      "{}",
+      error
+    );
+
+    let error = db
+      .query_as::<String>("SELECT id, title FROM notes", [])
+      .unwrap_err();
+    assert!(error.to_string().contains("single column"), "{}", error);
+  }
+
+  /// Test that generic queries can only read Bear's tables
+  #[test]
+  fn test_generic_queries_are_read_only() {
+    let db = BearDb::new_with_path(DatabasePath::InMemory).unwrap();
+
+    let forbidden = |sql: &str| match db.query_rows(sql, []) {
+      Err(BearError::ForbiddenStatement { action, target }) => (action, target),
+      result => panic!("Expected ForbiddenStatement for {}, got: {:?}", sql, result),
+    };
+
+    assert_eq!(
+      forbidden("DELETE FROM ZSFNOTE"),
+      ("DELETE".to_string(), Some("ZSFNOTE".to_string()))
+    );
+    assert_eq!(
+      forbidden("SELECT id FROM notes; SELECT 1"),
+      ("MULTIPLE STATEMENTS".to_string(), None)
+    );
+    assert_eq!(
+      forbidden("SELECT id FROM notes; DROP TABLE ZSFNOTE"),
+      ("SCHEMA CHANGE".to_string(), None)
+    );
+    assert_eq!(
+      forbidden("SELECT load_extension('/tmp/evil')"),
+      ("FUNCTION".to_string(), Some("load_extension".to_string()))
+    );
+
+    let error = db.query_as::<String>("UPDATE ZSFNOTE SET ZTITLE = 'x'", []);
+    assert_eq!(
+      error.unwrap_err().to_string(),
+      "Forbidden statement: UPDATE (ZSFNOTE)"
+    );
+
+    // The notes are untouched and reads still work
+    assert_eq!(db.query_rows("SELECT id FROM notes", []).unwrap().len(), 5);
+  }
+
+  #[test]
+  fn test_explain() {
+    let db = BearDb::new_with_path(DatabasePath::InMemory).unwrap();
+
+    let plan = db
+      .explain("SELECT title FROM notes WHERE is_trashed = 0")
+      .unwrap();
+    assert!(plan.sql().contains("FROM ZSFNOTE"));
+    assert!(
+      plan
+        .sql()
+        .ends_with("SELECT title FROM notes WHERE is_trashed = 0")
+    );
+
+    let scans = plan.full_scans();
+    assert_eq!(scans.len(), 1);
+    assert_eq!(scans[0].kind(), AccessKind::Scan);
+    assert_eq!(scans[0].table(), Some("ZSFNOTE"));
+    assert_eq!(scans[0].rows(), Some(5));
+    // The test database is too small to be worth a warning
+    assert!(plan.large_full_scans().is_empty());
+    assert!(plan.to_string().starts_with("QUERY PLAN\n"));
+    assert!(!plan.to_string().contains("warning"));
+
+    // Looking a note up by primary key searches instead of scanning
+    let plan = db
+      .explain("SELECT title FROM notes WHERE core_db_id = 1")
+      .unwrap();
+    assert!(plan.full_scans().is_empty());
+    let search = plan
+      .steps()
+      .iter()
+      .find_map(|step| step.access())
+      .filter(|access| access.table() == Some("ZSFNOTE"))
+      .unwrap();
+    assert_eq!(search.kind(), AccessKind::Search);
+
+    // Explaining doesn't get around the authorizer
+    assert!(matches!(
+      db.explain("DELETE FROM ZSFNOTE"),
+      Err(BearError::ForbiddenStatement { .. })
+    ));
+  }
+
+  #[test]
+  fn test_query_limits() {
+    // Billions of rows, far more than the timeouts allow
+    let endless_sql = format!(
+      "SELECT count(*) FROM {}",
+      (0..14)
+        .map(|i| format!("notes n{}", i))
+        .collect::<Vec<_>>()
+        .join(", ")
+    );
+    let timeout = Duration::from_millis(50);
+
+    let db = BearDb::new_with_path(DatabasePath::InMemory)
+      .unwrap()
+      .with_query_limits(QueryLimits::new().timeout(timeout));
+    assert!(matches!(
+      db.query_rows(&endless_sql, []),
+      Err(BearError::Timeout { timeout: t }) if t == timeout
+    ));
+    assert_eq!(db.query_rows("SELECT id FROM notes", []).unwrap().len(), 5);
+
+    let token = CancellationToken::new();
+    token.cancel();
+    let db = db.with_query_limits(QueryLimits::new().cancellation(token));
+    assert!(matches!(
+      db.notes(NotesQuery::default()),
+      Err(BearError::Cancelled)
+    ));
+
+    // Result budgets
+    let db = db.with_query_limits(QueryLimits::new().max_rows(2));
+    assert!(matches!(
+      db.query_rows("SELECT id FROM notes", []),
+      Err(BearError::ResultTooLarge {
+        limit: ResultLimit::Rows(2)
+      })
+    ));
+    assert!(matches!(
+      db.query_as::<String>("SELECT id FROM notes", []),
+      Err(BearError::ResultTooLarge { .. })
+    ));
+    assert_eq!(
+      db.query_rows("SELECT id FROM notes LIMIT 2", [])
+        .unwrap()
+        .len(),
+      2
+    );
+    // Typed methods aren't budgeted
+    assert_eq!(db.notes(NotesQuery::new().no_limit()).unwrap().len(), 4);
+    let db = db.with_query_limits(QueryLimits::new().max_bytes(10));
+    assert!(matches!(
+      db.query_rows("SELECT content FROM notes", []),
+      Err(BearError::ResultTooLarge {
+        limit: ResultLimit::Bytes(10)
+      })
+    ));
+
+    // Limits of a single query replace those of the database
+    #[cfg(feature = "polars")]
+    assert!(matches!(
+      db.query_with_options(
+        &endless_sql,
+        QueryOptions::new().limits(QueryLimits::new().timeout(timeout))
+      ),
+      Err(BearError::Timeout { .. })
+    ));
+    #[cfg(feature = "polars")]
+    {
+      assert!(matches!(
+        db.query("SELECT content FROM notes"),
+        Err(BearError::ResultTooLarge { .. })
+      ));
+      let options = QueryOptions::new().limits(QueryLimits::new());
+      let mut rows = 0;
+      db.query_batches("SELECT content FROM notes", [], 2, options, |df| {
+        rows += df.height();
+        Ok(())
+      })
+      .unwrap();
+      assert_eq!(rows, 5);
+    }
+  }
+
   /// Test search with different SortOrder variants
   #[test]
   fn test_search_all_sort_orders() {
@@ -0,0 +1,436 @@
+/// Time and size limits and cancellation for queries against Bear's database
+///
+/// A long query holds a read lock on Bear's database, blocking Bear's writes until it
+/// finishes. This module installs a SQLite progress handler on each query connection
+/// that interrupts the running statement once its timeout has passed or its
+/// `CancellationToken` is cancelled, surfacing as `BearError::Timeout` or
+/// `BearError::Cancelled`. Generic queries also count the rows and bytes they read
+/// against a budget, failing with `BearError::ResultTooLarge` once it is exceeded.
+use crate::BearError;
+use rusqlite::types::ValueRef;
+use rusqlite::{Connection, ErrorCode, Row};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long a query may run by default before it is interrupted
+pub const DEFAULT_QUERY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Number of SQLite virtual machine instructions between checks of the limits
+const PROGRESS_INTERVAL: i32 = 1000;
+
+/// A handle for cancelling queries from another thread.
+///
+/// Clones share the same state: cancelling any of them cancels every query running
+/// with, or later started with, one of the clones.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use bear_query::{BearDb, CancellationToken, QueryLimits};
+/// # fn main() -> Result<(), bear_query::BearError> {
+/// let token = CancellationToken::new();
+/// let db = BearDb::new()?.with_query_limits(QueryLimits::new().cancellation(token.clone()));
+///
+/// let watcher = token.clone();
+/// std::thread::spawn(move || {
+///   std::thread::sleep(std::time::Duration::from_secs(1));
+///   watcher.cancel();
+/// });
+///
+/// // Fails with `BearError::Cancelled` if still running after a second
+/// let result = db.query_rows("SELECT count(*) FROM notes a, notes b, notes c", []);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+  cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+  /// Creates a token that isn't cancelled
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Cancels the queries using this token
+  pub fn cancel(&self) {
+    self.cancelled.store(true, Ordering::Relaxed);
+  }
+
+  /// Returns true once `cancel()` has been called on this token or a clone
+  pub fn is_cancelled(&self) -> bool {
+    self.cancelled.load(Ordering::Relaxed)
+  }
+}
+
+/// Limits on how long queries may run and how much generic queries may return.
+///
+/// By default a query is interrupted after [`DEFAULT_QUERY_TIMEOUT`], to protect
+/// Bear from a query holding its database for minutes, and results aren't limited.
+/// Row and byte budgets cap what the generic query methods (`query()`, `query_rows()`,
+/// `query_as()`, ...) read into memory; the byte count is the size of the SQLite
+/// values read (text and BLOB length, 8 bytes per number). Set the limits of every
+/// query with [`BearDb::with_query_limits()`](crate::BearDb::with_query_limits), or
+/// of a single DataFrame query with `QueryOptions::limits()`.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use bear_query::{BearDb, QueryLimits};
+/// # use std::time::Duration;
+/// # fn main() -> Result<(), bear_query::BearError> {
+/// let db = BearDb::new()?.with_query_limits(QueryLimits::new().timeout(Duration::from_secs(2)));
+///
+/// // Fails with `BearError::Timeout` after two seconds
+/// let result = db.query_rows("SELECT count(*) FROM notes a, notes b, notes c", []);
+///
+/// // Fails with `BearError::ResultTooLarge` instead of reading more than 64 MiB
+/// let db = db.with_query_limits(QueryLimits::new().max_rows(10_000).max_bytes(64 << 20));
+/// let result = db.query_rows("SELECT content FROM notes", []);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct QueryLimits {
+  timeout: Option<Duration>,
+  cancellation: Option<CancellationToken>,
+  budget: ResultBudget,
+}
+
+impl Default for QueryLimits {
+  fn default() -> Self {
+    Self {
+      timeout: Some(DEFAULT_QUERY_TIMEOUT),
+      cancellation: None,
+      budget: ResultBudget::default(),
+    }
+  }
+}
+
+impl QueryLimits {
+  /// Creates limits with the default timeout, no cancellation token and no result
+  /// budget
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Interrupts queries running longer than this
+  pub fn timeout(
+    mut self,
+    timeout: Duration,
+  ) -> Self {
+    self.timeout = Some(timeout);
+    self
+  }
+
+  /// Lets queries run for as long as they take
+  pub fn no_timeout(mut self) -> Self {
+    self.timeout = None;
+    self
+  }
+
+  /// Interrupts queries once this token is cancelled
+  pub fn cancellation(
+    mut self,
+    token: CancellationToken,
+  ) -> Self {
+    self.cancellation = Some(token);
+    self
+  }
+
+  /// Fails generic queries returning more than this many rows
+  pub fn max_rows(
+    mut self,
+    rows: usize,
+  ) -> Self {
+    self.budget.max_rows = Some(rows);
+    self
+  }
+
+  /// Fails generic queries reading more than this many bytes of values
+  pub fn max_bytes(
+    mut self,
+    bytes: usize,
+  ) -> Self {
+    self.budget.max_bytes = Some(bytes);
+    self
+  }
+
+  /// The row and byte budget of a query, with nothing counted yet
+  pub(crate) fn budget(&self) -> ResultBudget {
+    self.budget
+  }
+
+  /// Returns why a query running since `started` should stop, if it should
+  fn exceeded(
+    &self,
+    started: Instant,
+  ) -> Option<Stop> {
+    if self
+      .cancellation
+      .as_ref()
+      .is_some_and(CancellationToken::is_cancelled)
+    {
+      return Some(Stop::Cancelled);
+    }
+    self
+      .timeout
+      .filter(|timeout| started.elapsed() >= *timeout)
+      .map(Stop::Timeout)
+  }
+}
+
+/// The budget a query result exceeded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResultLimit {
+  /// More rows than `QueryLimits::max_rows()`
+  Rows(usize),
+  /// More bytes than `QueryLimits::max_bytes()`
+  Bytes(usize),
+}
+
+impl fmt::Display for ResultLimit {
+  fn fmt(
+    &self,
+    f: &mut fmt::Formatter<'_>,
+  ) -> fmt::Result {
+    match self {
+      ResultLimit::Rows(rows) => write!(f, "{} rows", rows),
+      ResultLimit::Bytes(bytes) => write!(f, "{} bytes", bytes),
+    }
+  }
+}
+
+/// Counts the rows and bytes a query reads against its limits
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ResultBudget {
+  max_rows: Option<usize>,
+  max_bytes: Option<usize>,
+  rows: usize,
+  bytes: usize,
+}
+
+impl ResultBudget {
+  /// Counts a row, failing with `BearError::ResultTooLarge` once over budget
+  pub(crate) fn add_row(
+    &mut self,
+    row: &Row<'_>,
+    column_count: usize,
+  ) -> Result<(), BearError> {
+    self.rows += 1;
+    if let Some(max_rows) = self.max_rows.filter(|max_rows| self.rows > *max_rows) {
+      return Err(BearError::ResultTooLarge {
+        limit: ResultLimit::Rows(max_rows),
+      });
+    }
+
+    if let Some(max_bytes) = self.max_bytes {
+      for i in 0..column_count {
+        self.bytes += value_size(row.get_ref(i)?);
+      }
+      if self.bytes > max_bytes {
+        return Err(BearError::ResultTooLarge {
+          limit: ResultLimit::Bytes(max_bytes),
+        });
+      }
+    }
+    Ok(())
+  }
+}
+
+/// The number of bytes a value takes in a result
+fn value_size(value: ValueRef<'_>) -> usize {
+  match value {
+    ValueRef::Null => 0,
+    ValueRef::Integer(_) | ValueRef::Real(_) => 8,
+    ValueRef::Text(bytes) | ValueRef::Blob(bytes) => bytes.len(),
+  }
+}
+
+/// Why the progress handler interrupted a query
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Stop {
+  Timeout(Duration),
+  Cancelled,
+}
+
+impl From<Stop> for BearError {
+  fn from(stop: Stop) -> Self {
+    match stop {
+      Stop::Timeout(timeout) => BearError::Timeout { timeout },
+      Stop::Cancelled => BearError::Cancelled,
+    }
+  }
+}
+
+/// Records why the progress handler of a connection interrupted its query, to explain
+/// the SQL error SQLite returns for it.
+pub(crate) struct Interrupter {
+  stopped: Arc<Mutex<Option<Stop>>>,
+}
+
+impl Interrupter {
+  /// Installs a progress handler enforcing `limits` on a connection, counting time
+  /// from now. Fails right away if the cancellation token is already cancelled.
+  pub(crate) fn install(
+    conn: &Connection,
+    limits: &QueryLimits,
+  ) -> Result<Self, BearError> {
+    let started = Instant::now();
+    if let Some(stop) = limits.exceeded(started) {
+      return Err(stop.into());
+    }
+
+    let stopped = Arc::new(Mutex::new(None));
+    let record = Arc::clone(&stopped);
+    let limits = limits.clone();
+
+    conn.progress_handler(
+      PROGRESS_INTERVAL,
+      Some(move || match limits.exceeded(started) {
+        Some(stop) => {
+          if let Ok(mut stopped) = record.lock() {
+            stopped.get_or_insert(stop);
+          }
+          true
+        }
+        None => false,
+      }),
+    );
+
+    Ok(Self { stopped })
+  }
+
+  /// Turns the error of a query the progress handler interrupted into
+  /// `BearError::Timeout` or `BearError::Cancelled`.
+  pub(crate) fn explain(
+    &self,
+    error: BearError,
+  ) -> BearError {
+    let stopped = self.stopped.lock().ok().and_then(|stopped| *stopped);
+    match (stopped, error) {
+      (Some(stop), BearError::SqlError { source, .. })
+        if source.sqlite_error_code() == Some(ErrorCode::OperationInterrupted) =>
+      {
+        stop.into()
+      }
+      (_, error) => error,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Counts far enough to run for much longer than the tests' timeouts
+  const ENDLESS_SQL: &str = "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 10000000000) SELECT count(*) FROM n";
+
+  /// Runs `sql` on an in-memory database with `limits` enforced
+  fn run(
+    sql: &str,
+    limits: &QueryLimits,
+  ) -> Result<i64, BearError> {
+    let conn = Connection::open_in_memory().unwrap();
+    let interrupter = Interrupter::install(&conn, limits)?;
+    conn
+      .query_row(sql, [], |row| row.get(0))
+      .map_err(|error| interrupter.explain(error.into()))
+  }
+
+  #[test]
+  fn test_timeout() {
+    let limits = QueryLimits::new().timeout(Duration::from_millis(50));
+    match run(ENDLESS_SQL, &limits) {
+      Err(BearError::Timeout { timeout }) => assert_eq!(timeout, Duration::from_millis(50)),
+      result => panic!("Expected Timeout, got: {:?}", result),
+    }
+
+    // Quick queries aren't affected
+    assert_eq!(run("SELECT 42", &limits).unwrap(), 42);
+  }
+
+  #[test]
+  fn test_cancellation() {
+    let token = CancellationToken::new();
+    let limits = QueryLimits::new().no_timeout().cancellation(token.clone());
+
+    let canceller = token.clone();
+    let handle = std::thread::spawn(move || {
+      std::thread::sleep(Duration::from_millis(50));
+      canceller.cancel();
+    });
+    let result = run(ENDLESS_SQL, &limits);
+    handle.join().unwrap();
+    assert!(
+      matches!(result, Err(BearError::Cancelled)),
+      "Expected Cancelled, got: {:?}",
+      result
+    );
+
+    // A cancelled token stops queries before they start
+    assert!(token.is_cancelled());
+    assert!(matches!(
+      run("SELECT 42", &limits),
+      Err(BearError::Cancelled)
+    ));
+  }
+
+  /// Reads every row of `sql` against the budget of `limits`
+  fn count(
+    sql: &str,
+    limits: &QueryLimits,
+  ) -> Result<usize, BearError> {
+    let conn = Connection::open_in_memory().unwrap();
+    let mut stmt = conn.prepare(sql)?;
+    let column_count = stmt.column_count();
+    let mut budget = limits.budget();
+    let mut rows = stmt.query([])?;
+    let mut count = 0;
+    while let Some(row) = rows.next()? {
+      budget.add_row(row, column_count)?;
+      count += 1;
+    }
+    Ok(count)
+  }
+
+  #[test]
+  fn test_result_budget() {
+    const TEN_ROWS: &str = "WITH RECURSIVE n(i, text) AS (SELECT 1, 'abcd' UNION ALL SELECT i + 1, text FROM n WHERE i < 10) SELECT i, text, NULL FROM n";
+
+    assert_eq!(count(TEN_ROWS, &QueryLimits::new()).unwrap(), 10);
+    assert_eq!(
+      count(TEN_ROWS, &QueryLimits::new().max_rows(10)).unwrap(),
+      10
+    );
+    assert!(matches!(
+      count(TEN_ROWS, &QueryLimits::new().max_rows(9)),
+      Err(BearError::ResultTooLarge {
+        limit: ResultLimit::Rows(9)
+      })
+    ));
+
+    // Each row is an 8 byte integer, 4 bytes of text and a NULL
+    assert_eq!(
+      count(TEN_ROWS, &QueryLimits::new().max_bytes(120)).unwrap(),
+      10
+    );
+    let error = count(TEN_ROWS, &QueryLimits::new().max_bytes(119)).unwrap_err();
+    assert_eq!(
+      error.to_string(),
+      "Query result is larger than its limit of 119 bytes"
+    );
+  }
+
+  #[test]
+  fn test_other_errors_are_kept() {
+    let limits = QueryLimits::new();
+    assert!(matches!(
+      run("SELECT * FROM missing", &limits),
+      Err(BearError::SqlError { .. })
+    ));
+  }
+}
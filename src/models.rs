@@ -3,15 +3,23 @@
 //! This module contains all the types representing Bear's database entities:
 //! notes, tags, and their identifiers.
 
+use crate::hashtags::{self, InlineTag};
+use crate::html::{self, HtmlOptions};
+use crate::outline::{self, Heading};
+use crate::plain_text;
 use rusqlite::types::{FromSql, FromSqlResult, ToSqlOutput, ValueRef};
 use rusqlite::{Row, ToSql};
+use serde::Serialize;
 use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use time::OffsetDateTime;
 
 /// Internal database ID wrapper.
 ///
 /// This wraps SQLite's INTEGER PRIMARY KEY values.
-#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash, Serialize)]
+#[serde(transparent)]
 pub(crate) struct CoreDbId(pub(crate) i64);
 
 /// Internal Core Data note identifier.
@@ -49,7 +57,8 @@ impl CoreDbNoteId {
 /// - Matching notes in sync operations
 ///
 /// This is Bear's primary identifier for notes.
-#[derive(Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+#[derive(Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash, Serialize)]
+#[serde(transparent)]
 pub struct NoteId(String);
 
 impl NoteId {
@@ -89,7 +98,8 @@ impl NoteId {
 /// Unique identifier for a Bear tag.
 ///
 /// This wraps the tag's SQLite primary key.
-#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash, Serialize)]
+#[serde(transparent)]
 pub struct TagId(pub(crate) CoreDbId);
 
 impl TagId {
@@ -145,6 +155,14 @@ impl ToSql for CoreDbNoteId {
 /// - **`modified`**: Timestamp of last modification. May be `None` for tags that have
 ///   never been explicitly modified.
 ///
+/// Timestamps keep the full precision of Bear's Core Data values; the raw value is
+/// available from `core_data_modified()`.
+///
+/// # Serialization
+///
+/// Tags implement `serde::Serialize` with fields `id` (integer), `name` and
+/// `modified` (RFC 3339 string or null).
+///
 /// # Example
 ///
 /// ```no_run
@@ -163,11 +181,14 @@ impl ToSql for CoreDbNoteId {
 /// # Ok(())
 /// # }
 /// ```
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Tag {
   id: TagId,
   name: Option<String>,
+  #[serde(with = "time::serde::rfc3339::option")]
   modified: Option<OffsetDateTime>,
+  #[serde(skip)]
+  core_data_modified: Option<f64>,
 }
 
 impl Tag {
@@ -196,14 +217,40 @@ impl Tag {
   pub fn modified(&self) -> Option<OffsetDateTime> {
     self.modified
   }
+
+  /// Returns the raw Core Data timestamp of the tag's last modification (seconds
+  /// since 2001-01-01 UTC), for exact comparisons.
+  pub fn core_data_modified(&self) -> Option<f64> {
+    self.core_data_modified
+  }
 }
 
 /// Helper to construct Tag from a database row
 pub(crate) fn tag_from_row(row: &Row) -> rusqlite::Result<Tag> {
+  let core_data_modified: Option<f64> = row.get("core_data_modified")?;
   Ok(Tag {
     id: row.get("id")?,
     name: row.get("name")?,
-    modified: row.get("modified")?,
+    modified: core_data_modified
+      .map(|seconds| core_data_timestamp(row, "core_data_modified", seconds))
+      .transpose()?,
+    core_data_modified,
+  })
+}
+
+/// Converts a Core Data timestamp read from `column`, failing like rusqlite's own
+/// conversions when it is out of range
+fn core_data_timestamp(
+  row: &Row,
+  column: &str,
+  seconds: f64,
+) -> rusqlite::Result<OffsetDateTime> {
+  crate::datetime::from_core_data(seconds).ok_or_else(|| {
+    rusqlite::Error::FromSqlConversionFailure(
+      row.as_ref().column_index(column).unwrap_or_default(),
+      rusqlite::types::Type::Real,
+      format!("Core Data timestamp out of range: {}", seconds).into(),
+    )
   })
 }
 
@@ -284,7 +331,8 @@ impl TagsMap {
 /// - **`title`**: All notes have titles (may be empty string, but never NULL)
 /// - **`unique_id`**: Bear's UUID identifier (always present)
 /// - **`id`**: Primary key (always present)
-/// - **`modified`**, **`created`**: Timestamps (always present)
+/// - **`modified`**, **`created`**: Timestamps (always present), with the full precision
+///   of Bear's Core Data values
 /// - **`is_pinned`**: Boolean flag (always present)
 ///
 /// # Identifiers
@@ -301,6 +349,11 @@ impl TagsMap {
 ///
 /// The internal Core Data primary key is not exposed in the public API.
 ///
+/// # Serialization
+///
+/// Notes implement `serde::Serialize` with fields `id`, `title`, `content`, `modified`,
+/// `created` and `is_pinned`. Timestamps are serialized as RFC 3339 strings.
+///
 /// # Example
 ///
 /// ```no_run
@@ -321,14 +374,21 @@ impl TagsMap {
 /// # Ok(())
 /// # }
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct Note {
+  #[serde(skip)]
   _core_db_id: CoreDbNoteId,
   id: NoteId,
   title: String,
   content: Option<String>,
+  #[serde(with = "time::serde::rfc3339")]
   modified: OffsetDateTime,
+  #[serde(with = "time::serde::rfc3339")]
   created: OffsetDateTime,
+  #[serde(skip)]
+  core_data_modified: f64,
+  #[serde(skip)]
+  core_data_created: f64,
   is_pinned: bool,
 }
 
@@ -431,23 +491,256 @@ impl Note {
     self.created
   }
 
+  /// Returns the raw Core Data timestamp of the note's last modification (seconds
+  /// since 2001-01-01 UTC), for exact comparisons such as detecting edits.
+  ///
+  /// Convert it with [`datetime::from_core_data()`](crate::datetime::from_core_data).
+  pub fn core_data_modified(&self) -> f64 {
+    self.core_data_modified
+  }
+
+  /// Returns the raw Core Data timestamp of the note's creation (seconds since
+  /// 2001-01-01 UTC).
+  pub fn core_data_created(&self) -> f64 {
+    self.core_data_created
+  }
+
   /// Returns whether the note is pinned.
   ///
   /// This is always present (never NULL).
   pub fn is_pinned(&self) -> bool {
     self.is_pinned
   }
+
+  /// Returns the heading tree of the note's content.
+  ///
+  /// Top-level entries are the shallowest headings; deeper headings are nested
+  /// under them via `Heading::children()`. Notes without content have no outline.
+  ///
+  /// # Example
+  ///
+  /// ```no_run
+  /// # use bear_query::{BearDb, NotesQuery};
+  /// # fn main() -> Result<(), bear_query::BearError> {
+  /// # let db = BearDb::new()?;
+  /// # let notes = db.notes(NotesQuery::default())?;
+  /// # let note = &notes[0];
+  /// for heading in note.outline() {
+  ///     println!("{} (bytes {:?})", heading.text(), heading.range());
+  ///     for child in heading.children() {
+  ///         println!("  {}", child.text());
+  ///     }
+  /// }
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn outline(&self) -> Vec<Heading> {
+    self.content().map(outline::outline).unwrap_or_default()
+  }
+
+  /// Returns the Markdown under the heading at the given path, if present.
+  ///
+  /// The path is a `/`-separated list of heading texts, e.g. `"Setup/Linux"`; a
+  /// `/` inside a heading is escaped as `\/`, as in `"Input\\/Output/Files"`. The
+  /// first segment may match a heading at any depth (so the note's `# Title`
+  /// heading can be omitted); each following segment must be nested under the
+  /// previous one. When several headings match, the first one under which the
+  /// whole path resolves wins. The returned section excludes the heading line
+  /// itself but includes any nested subsections.
+  ///
+  /// # Example
+  ///
+  /// ```no_run
+  /// # use bear_query::{BearDb, NotesQuery};
+  /// # fn main() -> Result<(), bear_query::BearError> {
+  /// # let db = BearDb::new()?;
+  /// # let notes = db.notes(NotesQuery::default())?;
+  /// # let note = &notes[0];
+  /// if let Some(install) = note.section("Setup/Linux") {
+  ///     println!("{}", install.trim());
+  /// }
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn section(
+    &self,
+    path: &str,
+  ) -> Option<&str> {
+    outline::section(self.content()?, path)
+  }
+
+  /// Returns the tags written inline in the note's content, in order of appearance.
+  ///
+  /// Handles `#tag`, `#nested/tag` and `#multi word tags#`, and skips escaped `\#`,
+  /// headings, URL fragments and anything inside code. The same tag may appear
+  /// more than once. Use `BearDb::note_tags()` for the tags Bear has indexed.
+  ///
+  /// # Example
+  ///
+  /// ```no_run
+  /// # use bear_query::{BearDb, NotesQuery};
+  /// # fn main() -> Result<(), bear_query::BearError> {
+  /// # let db = BearDb::new()?;
+  /// # let notes = db.notes(NotesQuery::default())?;
+  /// # let note = &notes[0];
+  /// for tag in note.inline_tags() {
+  ///     println!("#{}", tag.name());
+  /// }
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn inline_tags(&self) -> Vec<InlineTag> {
+    self.content().map(hashtags::extract).unwrap_or_default()
+  }
+
+  /// Renders the note's content to sanitized HTML.
+  ///
+  /// Bear's Markdown extensions are supported: `[[wiki links]]`, `#tags`,
+  /// `==highlights==` and `- [ ]` todos. Raw HTML in the note is escaped. See
+  /// `HtmlOptions` for configuring link URLs and attachment references.
+  /// Notes without content render to an empty string.
+  ///
+  /// # Example
+  ///
+  /// ```no_run
+  /// # use bear_query::{BearDb, HtmlOptions, NotesQuery};
+  /// # fn main() -> Result<(), bear_query::BearError> {
+  /// # let db = BearDb::new()?;
+  /// # let notes = db.notes(NotesQuery::default())?;
+  /// # let note = &notes[0];
+  /// let options = HtmlOptions::new()
+  ///     .wiki_links_by_slug("/wiki/{}")
+  ///     .tag_links("/wiki/tags/{}");
+  ///
+  /// println!("{}", note.to_html(&options));
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn to_html(
+    &self,
+    options: &HtmlOptions,
+  ) -> String {
+    self
+      .content()
+      .map(|content| html::render(content, options))
+      .unwrap_or_default()
+  }
+
+  /// Returns the note's content as plain text.
+  ///
+  /// Markdown syntax, inline `#tags`, link brackets and URLs, and todo checkboxes
+  /// are removed; link text, code and line structure are kept. Notes without
+  /// content return an empty string.
+  ///
+  /// # Example
+  ///
+  /// ```no_run
+  /// # use bear_query::{BearDb, NotesQuery};
+  /// # fn main() -> Result<(), bear_query::BearError> {
+  /// # let db = BearDb::new()?;
+  /// # let notes = db.notes(NotesQuery::default())?;
+  /// # let note = &notes[0];
+  /// let text = note.plain_text();
+  /// println!("{} words, {} characters", note.word_count(), note.char_count());
+  /// # Ok(())
+  /// # }
+  /// ```
+  pub fn plain_text(&self) -> String {
+    self
+      .content()
+      .map(plain_text::plain_text)
+      .unwrap_or_default()
+  }
+
+  /// Returns the number of words in the note's plain text.
+  ///
+  /// Words are found with Unicode word boundaries (UAX #29), so punctuation is not
+  /// counted and CJK text is counted per ideograph.
+  pub fn word_count(&self) -> usize {
+    plain_text::word_count(&self.plain_text())
+  }
+
+  /// Returns the number of characters in the note's plain text, excluding whitespace.
+  ///
+  /// Characters are user-perceived characters (grapheme clusters), so an accented
+  /// letter or an emoji with a skin-tone modifier counts once.
+  pub fn char_count(&self) -> usize {
+    plain_text::char_count(&self.plain_text())
+  }
+
+  /// Returns the estimated time to read the note, at 200 words per minute.
+  pub fn reading_time(&self) -> Duration {
+    plain_text::reading_time(self.word_count())
+  }
+}
+
+/// A file or image attached to a note.
+///
+/// Bear stores attachment files outside the database, under its `Local Files`
+/// directory, in a folder named after the attachment's UUID.
+#[derive(Debug, Clone)]
+pub(crate) struct Attachment {
+  id: String,
+  note_id: NoteId,
+  filename: String,
+}
+
+impl Attachment {
+  /// Returns the note this attachment belongs to.
+  pub(crate) fn note_id(&self) -> &NoteId {
+    &self.note_id
+  }
+
+  /// Returns the attachment's UUID.
+  pub(crate) fn id(&self) -> &str {
+    &self.id
+  }
+
+  /// Returns the attachment's file name, as referenced from the note content.
+  pub(crate) fn filename(&self) -> &str {
+    &self.filename
+  }
+
+  /// Locates the attachment file under Bear's `Local Files` directory, if it exists.
+  ///
+  /// Images live under `Note Images/<uuid>/` and other files under `Note Files/<uuid>/`.
+  /// Only the last component of the stored id and file name is used, so names like
+  /// `../x` can't point outside `Local Files`.
+  pub(crate) fn source_path(
+    &self,
+    files_root: &Path,
+  ) -> Option<PathBuf> {
+    let id = Path::new(&self.id).file_name()?;
+    let filename = Path::new(&self.filename).file_name()?;
+    ["Note Images", "Note Files"]
+      .iter()
+      .map(|folder| files_root.join(folder).join(id).join(filename))
+      .find(|path| path.is_file())
+  }
+}
+
+/// Helper to construct Attachment from a database row
+pub(crate) fn attachment_from_row(row: &Row) -> rusqlite::Result<Attachment> {
+  Ok(Attachment {
+    id: row.get("id")?,
+    note_id: NoteId::new(row.get("note_id")?),
+    filename: row.get("filename")?,
+  })
 }
 
 /// Helper to construct Note from a database row
 pub(crate) fn note_from_row(row: &Row) -> rusqlite::Result<Note> {
+  let core_data_modified: f64 = row.get("core_data_modified")?;
+  let core_data_created: f64 = row.get("core_data_created")?;
   Ok(Note {
     _core_db_id: row.get("core_db_id")?,
     id: NoteId::new(row.get("id")?),
     title: row.get("title")?,
     content: row.get("content")?,
-    created: row.get("created")?,
-    modified: row.get("modified")?,
+    created: core_data_timestamp(row, "core_data_created", core_data_created)?,
+    modified: core_data_timestamp(row, "core_data_modified", core_data_modified)?,
+    core_data_modified,
+    core_data_created,
     is_pinned: row.get("is_pinned")?,
   })
 }
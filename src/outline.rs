@@ -0,0 +1,396 @@
+//! Heading outline and section extraction for note content.
+//!
+//! Bear notes are Markdown documents whose first line is usually a `# Title` heading,
+//! followed by any number of nested `##`/`###` sections. This module parses the
+//! headings into a tree and resolves slash-separated heading paths
+//! (e.g. `"Setup/Linux"`) to the Markdown under that heading.
+//!
+//! Parsing is done with `pulldown-cmark`, so `#` lines inside fenced code blocks and
+//! Bear's inline `#tags` (which have no space after the `#`) are not mistaken for
+//! headings.
+
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+use std::ops::Range;
+
+/// A heading in a note's outline.
+///
+/// Headings form a tree: every heading following this one with a deeper level,
+/// up to the next heading of the same or shallower level, is a descendant.
+///
+/// # Example
+///
+/// ```no_run
+/// # use bear_query::{BearDb, NotesQuery};
+/// # fn main() -> Result<(), bear_query::BearError> {
+/// let db = BearDb::new()?;
+///
+/// for note in db.notes(NotesQuery::default())? {
+///     for heading in note.outline() {
+///         println!("{} {}", "#".repeat(heading.level() as usize), heading.text());
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Heading {
+  level: u8,
+  text: String,
+  range: Range<usize>,
+  section_range: Range<usize>,
+  children: Vec<Heading>,
+}
+
+impl Heading {
+  /// Returns the heading level, from 1 (`#`) to 6 (`######`).
+  pub fn level(&self) -> u8 {
+    self.level
+  }
+
+  /// Returns the heading text with Markdown syntax removed.
+  pub fn text(&self) -> &str {
+    &self.text
+  }
+
+  /// Returns the byte range of the heading line itself within the note content.
+  pub fn range(&self) -> Range<usize> {
+    self.range.clone()
+  }
+
+  /// Returns the byte range of the Markdown under this heading.
+  ///
+  /// The section starts after the heading line and ends at the next heading of the
+  /// same or shallower level (or the end of the note). Nested subsections are included.
+  pub fn section_range(&self) -> Range<usize> {
+    self.section_range.clone()
+  }
+
+  /// Returns the headings nested directly under this one.
+  pub fn children(&self) -> &[Heading] {
+    &self.children
+  }
+}
+
+/// Collects every heading with the given text among `headings` and their
+/// descendants, in document order.
+fn find_all<'h>(
+  headings: &'h [Heading],
+  text: &str,
+  found: &mut Vec<&'h Heading>,
+) {
+  for heading in headings {
+    if heading.text == text {
+      found.push(heading);
+    }
+    find_all(&heading.children, text, found);
+  }
+}
+
+/// Returns the first candidate under which the remaining path segments resolve,
+/// trying the next candidate whenever one leads to a dead end.
+fn resolve<'h>(
+  candidates: Vec<&'h Heading>,
+  rest: &[String],
+) -> Option<&'h Heading> {
+  candidates
+    .into_iter()
+    .find_map(|heading| match rest.split_first() {
+      None => Some(heading),
+      Some((segment, rest)) => {
+        let mut nested = Vec::new();
+        find_all(&heading.children, segment, &mut nested);
+        resolve(nested, rest)
+      }
+    })
+}
+
+/// A heading as it appears in the document, before nesting.
+struct FlatHeading {
+  level: u8,
+  text: String,
+  range: Range<usize>,
+}
+
+/// Parses the headings of a Markdown document into a tree.
+pub(crate) fn outline(content: &str) -> Vec<Heading> {
+  let flat = flat_headings(content);
+
+  // Each section runs until the next heading of the same or shallower level
+  let section_ends: Vec<usize> = flat
+    .iter()
+    .enumerate()
+    .map(|(i, heading)| {
+      flat[i + 1..]
+        .iter()
+        .find(|next| next.level <= heading.level)
+        .map(|next| next.range.start)
+        .unwrap_or(content.len())
+    })
+    .collect();
+
+  let mut roots: Vec<Heading> = Vec::new();
+  // Stack of headings whose children are still being collected
+  let mut open: Vec<Heading> = Vec::new();
+
+  for (heading, section_end) in flat.into_iter().zip(section_ends) {
+    let node = Heading {
+      level: heading.level,
+      section_range: heading.range.end.min(section_end)..section_end,
+      text: heading.text,
+      range: heading.range,
+      children: Vec::new(),
+    };
+
+    close_until(&mut open, &mut roots, node.level);
+    open.push(node);
+  }
+
+  close_until(&mut open, &mut roots, 0);
+  roots
+}
+
+/// Pops open headings at `level` or deeper, attaching each to its parent.
+fn close_until(
+  open: &mut Vec<Heading>,
+  roots: &mut Vec<Heading>,
+  level: u8,
+) {
+  while open.last().is_some_and(|top| top.level >= level) {
+    let done = open.pop().expect("checked by loop condition");
+    match open.last_mut() {
+      Some(parent) => parent.children.push(done),
+      None => roots.push(done),
+    }
+  }
+}
+
+/// Collects headings in document order with their text and line ranges.
+fn flat_headings(content: &str) -> Vec<FlatHeading> {
+  let mut headings = Vec::new();
+  let mut current: Option<FlatHeading> = None;
+
+  for (event, range) in Parser::new_ext(content, Options::empty()).into_offset_iter() {
+    match event {
+      Event::Start(Tag::Heading { level, .. }) => {
+        current = Some(FlatHeading {
+          level: level as u8,
+          text: String::new(),
+          range,
+        });
+      }
+      Event::End(TagEnd::Heading(_)) => {
+        if let Some(mut heading) = current.take() {
+          heading.text = heading.text.trim().to_string();
+          headings.push(heading);
+        }
+      }
+      Event::Text(text) | Event::Code(text) => {
+        if let Some(heading) = current.as_mut() {
+          heading.text.push_str(&text);
+        }
+      }
+      Event::SoftBreak | Event::HardBreak => {
+        if let Some(heading) = current.as_mut() {
+          heading.text.push(' ');
+        }
+      }
+      _ => {}
+    }
+  }
+
+  headings
+}
+
+/// Splits a heading path into its trimmed segments.
+///
+/// Segments are separated by `/`; `\/` stands for a literal slash and `\\` for a
+/// literal backslash, so `"Input\/Output/Files"` is `["Input/Output", "Files"]`.
+/// Any other backslash is kept as written.
+pub(crate) fn path_segments(path: &str) -> Vec<String> {
+  let mut segments = vec![String::new()];
+  let mut chars = path.chars();
+  while let Some(c) = chars.next() {
+    let current = segments.last_mut().expect("segments is never empty");
+    match c {
+      '\\' => match chars.next() {
+        Some(escaped @ ('/' | '\\')) => current.push(escaped),
+        Some(other) => {
+          current.push('\\');
+          current.push(other);
+        }
+        None => current.push('\\'),
+      },
+      '/' => segments.push(String::new()),
+      c => current.push(c),
+    }
+  }
+  segments
+    .into_iter()
+    .map(|segment| segment.trim().to_string())
+    .collect()
+}
+
+/// Returns the longest run of letters and digits in each segment of a heading path,
+/// used to narrow notes down in SQL before their outlines are parsed.
+///
+/// Inline markup and escapes surround words rather than splitting them, so a note
+/// with the heading holds these words in its raw Markdown unless markup splits a
+/// word (`**Act**ion`). Segments without letters or digits contribute nothing.
+pub(crate) fn path_keywords(path: &str) -> Vec<String> {
+  path_segments(path)
+    .iter()
+    .filter_map(|segment| {
+      segment
+        .split(|c: char| !c.is_alphanumeric())
+        .max_by_key(|word| word.chars().count())
+        .filter(|word| !word.is_empty())
+        .map(str::to_string)
+    })
+    .collect()
+}
+
+/// Resolves a slash-separated heading path to the Markdown under that heading.
+///
+/// The first path segment may match a heading at any depth; each following segment
+/// must match a heading nested somewhere under the previous match. When several
+/// headings match a segment, each is tried in document order until the rest of the
+/// path resolves. Segments are compared against the heading text after trimming
+/// whitespace; see `path_segments()` for escaping `/`.
+pub(crate) fn section<'a>(
+  content: &'a str,
+  path: &str,
+) -> Option<&'a str> {
+  let headings = outline(content);
+  let segments = path_segments(path);
+  let (first, rest) = segments.split_first()?;
+
+  let mut candidates = Vec::new();
+  find_all(&headings, first, &mut candidates);
+  let found = resolve(candidates, rest)?;
+
+  Some(&content[found.section_range()])
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const NOTE: &str = "# Project Plan\n\
+    Intro paragraph.\n\
+    \n\
+    ## Setup\n\
+    Install things.\n\
+    \n\
+    ### Linux\n\
+    apt install bear\n\
+    \n\
+    ### macOS\n\
+    brew install bear\n\
+    \n\
+    ## Usage\n\
+    ```sh\n\
+    # not a heading\n\
+    ```\n\
+    #tag is not a heading either\n";
+
+  #[test]
+  fn test_outline_builds_tree() {
+    let headings = outline(NOTE);
+
+    assert_eq!(headings.len(), 1);
+    let title = &headings[0];
+    assert_eq!(title.level(), 1);
+    assert_eq!(title.text(), "Project Plan");
+
+    let sections: Vec<&str> = title.children().iter().map(|h| h.text()).collect();
+    assert_eq!(sections, vec!["Setup", "Usage"]);
+
+    let setup = &title.children()[0];
+    let platforms: Vec<&str> = setup.children().iter().map(|h| h.text()).collect();
+    assert_eq!(platforms, vec!["Linux", "macOS"]);
+    assert!(title.children()[1].children().is_empty());
+  }
+
+  #[test]
+  fn test_outline_ranges() {
+    let headings = outline(NOTE);
+    let setup = &headings[0].children()[0];
+
+    assert!(NOTE[setup.range()].starts_with("## Setup"));
+    let body = &NOTE[setup.section_range()];
+    assert!(body.starts_with("Install things."));
+    assert!(body.contains("brew install bear"));
+    assert!(!body.contains("## Usage"));
+  }
+
+  #[test]
+  fn test_section_by_path() {
+    assert_eq!(
+      section(NOTE, "Setup/Linux").map(str::trim),
+      Some("apt install bear")
+    );
+    assert_eq!(
+      section(NOTE, "Project Plan/macOS").map(str::trim),
+      Some("brew install bear")
+    );
+    assert!(section(NOTE, "Usage").unwrap().contains("# not a heading"));
+    assert_eq!(section(NOTE, "Linux/Setup"), None);
+    assert_eq!(section(NOTE, "Missing"), None);
+  }
+
+  #[test]
+  fn test_section_tries_every_matching_heading() {
+    // The first "Release" has no "Checklist"; the second does
+    let note = "# Notes\n\
+      ## Release\n\
+      Draft.\n\
+      ## Archive\n\
+      ### Release\n\
+      #### Checklist\n\
+      Ship it.\n";
+    assert_eq!(
+      section(note, "Release/Checklist").map(str::trim),
+      Some("Ship it.")
+    );
+    assert_eq!(section(note, "Release").map(str::trim), Some("Draft."));
+  }
+
+  #[test]
+  fn test_escaped_slashes_in_paths() {
+    let note = "# Guide\n## Input/Output\n### Files\nopen()\n## C:\\dir\nwindows\n";
+    assert_eq!(
+      section(note, "Input\\/Output/Files").map(str::trim),
+      Some("open()")
+    );
+    assert_eq!(section(note, "Input/Output/Files"), None);
+    assert_eq!(section(note, "C:\\dir").map(str::trim), Some("windows"));
+    assert_eq!(
+      path_segments(" a\\/b / c\\\\ /d\\"),
+      vec!["a/b", "c\\", "d\\"]
+    );
+  }
+
+  #[test]
+  fn test_path_keywords() {
+    assert_eq!(
+      path_keywords("Using query() safely/Next Steps/🎉"),
+      vec!["safely", "Steps"]
+    );
+    assert!(path_keywords("").is_empty());
+  }
+
+  #[test]
+  fn test_inline_markup_is_stripped_from_text() {
+    let headings = outline("## Using `query()` *safely*\nbody\n");
+    assert_eq!(headings[0].text(), "Using query() safely");
+  }
+
+  #[test]
+  fn test_skipped_levels_nest_under_nearest_parent() {
+    let headings = outline("# Top\n### Deep\n## Mid\n");
+
+    assert_eq!(headings.len(), 1);
+    let children: Vec<&str> = headings[0].children().iter().map(|h| h.text()).collect();
+    assert_eq!(children, vec!["Deep", "Mid"]);
+  }
+}
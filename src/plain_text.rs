@@ -0,0 +1,171 @@
+//! Plain-text rendering and text statistics for note content.
+//!
+//! The plain-text view drops all Markdown syntax (emphasis markers, heading `#`s,
+//! list bullets, link brackets and URLs, todo checkboxes) and Bear's inline `#tags`,
+//! keeping only the words a reader would see. Word counts use Unicode word
+//! boundaries (UAX #29), so text without spaces between words, such as Chinese or
+//! Japanese, is counted per ideograph rather than as a single word.
+
+use pulldown_cmark::{Event, Parser, TagEnd};
+use std::ops::Range;
+use std::time::Duration;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{hashtags, html};
+
+/// Average adult silent reading speed used for `Note::reading_time()`.
+pub(crate) const WORDS_PER_MINUTE: u64 = 200;
+
+/// Renders a Markdown document as plain text.
+///
+/// Blocks are separated by blank lines, line breaks inside a block are kept, and
+/// runs of spaces left behind by removed syntax are collapsed.
+pub(crate) fn plain_text(content: &str) -> String {
+  let tag_ranges: Vec<Range<usize>> = hashtags::extract(content)
+    .iter()
+    .map(|tag| tag.range())
+    .collect();
+
+  let mut text = String::with_capacity(content.len());
+
+  for (event, range) in Parser::new_ext(content, html::parser_options()).into_offset_iter() {
+    match event {
+      Event::Text(run) => {
+        if content.get(range.clone()) == Some(&*run) {
+          push_without_tags(&mut text, &run, range.start, &tag_ranges);
+        } else {
+          text.push_str(&run);
+        }
+      }
+      Event::Code(code) => text.push_str(&code),
+      Event::SoftBreak | Event::HardBreak => text.push('\n'),
+      Event::End(TagEnd::TableCell) => text.push(' '),
+      Event::End(TagEnd::Item | TagEnd::TableHead | TagEnd::TableRow) => text.push('\n'),
+      Event::End(
+        TagEnd::Paragraph
+        | TagEnd::Heading(_)
+        | TagEnd::CodeBlock
+        | TagEnd::BlockQuote(_)
+        | TagEnd::List(_)
+        | TagEnd::Table,
+      )
+      | Event::Rule => text.push_str("\n\n"),
+      _ => {}
+    }
+  }
+
+  normalize_whitespace(&text)
+}
+
+/// Appends a text run with any inline tags inside it removed.
+fn push_without_tags(
+  text: &mut String,
+  run: &str,
+  offset: usize,
+  tag_ranges: &[Range<usize>],
+) {
+  let end = offset + run.len();
+  let mut pos = 0;
+
+  for range in tag_ranges
+    .iter()
+    .filter(|range| range.start >= offset && range.end <= end)
+  {
+    text.push_str(&run[pos..range.start - offset]);
+    pos = range.end - offset;
+  }
+
+  text.push_str(&run[pos..]);
+}
+
+/// Collapses spaces within lines, trims lines and keeps at most one blank line in a row.
+fn normalize_whitespace(text: &str) -> String {
+  let mut lines: Vec<String> = Vec::new();
+
+  for line in text.lines() {
+    let line = line.split_whitespace().collect::<Vec<_>>().join(" ");
+    if line.is_empty() && lines.last().is_none_or(|last| last.is_empty()) {
+      continue;
+    }
+    lines.push(line);
+  }
+
+  while lines.last().is_some_and(|last| last.is_empty()) {
+    lines.pop();
+  }
+
+  lines.join("\n")
+}
+
+/// Counts words using Unicode word boundaries.
+pub(crate) fn word_count(text: &str) -> usize {
+  text.unicode_words().count()
+}
+
+/// Counts user-perceived characters (grapheme clusters), excluding whitespace.
+pub(crate) fn char_count(text: &str) -> usize {
+  text
+    .graphemes(true)
+    .filter(|grapheme| !grapheme.chars().all(char::is_whitespace))
+    .count()
+}
+
+/// Estimates reading time at `WORDS_PER_MINUTE`, rounded up to the next second.
+pub(crate) fn reading_time(words: usize) -> Duration {
+  Duration::from_secs((words as u64 * 60).div_ceil(WORDS_PER_MINUTE))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_strips_markdown_syntax() {
+    let content = "# Title\n\nSome **bold** and _italic_ text with a [link](https://example.com).\n\n- [ ] todo item\n- [x] done item\n";
+    assert_eq!(
+      plain_text(content),
+      "Title\n\nSome bold and italic text with a link.\n\ntodo item\ndone item"
+    );
+  }
+
+  #[test]
+  fn test_strips_tags_and_wiki_brackets() {
+    let content = "See [[Other Note]] for #work/projects and #multi word tag# details.";
+    assert_eq!(plain_text(content), "See Other Note for and details.");
+  }
+
+  #[test]
+  fn test_keeps_code_and_escaped_hashes() {
+    let content = "Run `cargo test` then:\n\n```\n#!/bin/sh\necho hi\n```\n\nNot a \\#tag";
+    assert_eq!(
+      plain_text(content),
+      "Run cargo test then:\n\n#!/bin/sh\necho hi\n\nNot a #tag"
+    );
+  }
+
+  #[test]
+  fn test_word_count_latin() {
+    assert_eq!(word_count("The quick brown fox, jumps!"), 5);
+    assert_eq!(word_count("don't stop"), 2);
+  }
+
+  #[test]
+  fn test_word_count_cjk() {
+    // Each ideograph is its own word under UAX #29
+    assert_eq!(word_count("我爱北京"), 4);
+    assert_eq!(word_count("Rust 编程"), 3);
+  }
+
+  #[test]
+  fn test_char_count_uses_graphemes() {
+    assert_eq!(char_count("héllo wörld"), 10);
+    assert_eq!(char_count("👍🏽 ok"), 3);
+  }
+
+  #[test]
+  fn test_reading_time() {
+    assert_eq!(reading_time(0), Duration::ZERO);
+    assert_eq!(reading_time(200), Duration::from_secs(60));
+    assert_eq!(reading_time(201), Duration::from_secs(61));
+  }
+}
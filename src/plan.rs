@@ -0,0 +1,406 @@
+/// Query plan inspection for generic queries
+///
+/// The normalized views are CTEs over Core Data tables, so the plan SQLite picks for
+/// a query refers to Bear's tables under the aliases of the CTEs (`SCAN n` for
+/// `ZSFNOTE as n`). This module runs `EXPLAIN QUERY PLAN` on the CTE-expanded SQL,
+/// parses the result into a tree, resolves aliases back to Bear's tables and flags
+/// full scans of large ones.
+use crate::{BearError, Queryable};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Tables with at least this many rows are reported when a query scans them in full
+pub const LARGE_TABLE_ROWS: u64 = 1000;
+
+/// The plan SQLite chose for a generic query, returned by
+/// [`BearDb::explain()`](crate::BearDb::explain).
+///
+/// Its `Display` implementation is a human-readable report: the plan tree, like the
+/// `sqlite3` shell's `.eqp` output, followed by a warning for each full scan of a
+/// large table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryPlan {
+  sql: String,
+  steps: Vec<PlanStep>,
+}
+
+impl QueryPlan {
+  /// Returns the SQL that was planned: the query with the normalizing CTEs prepended
+  pub fn sql(&self) -> &str {
+    &self.sql
+  }
+
+  /// Returns the top-level steps of the plan
+  pub fn steps(&self) -> &[PlanStep] {
+    &self.steps
+  }
+
+  /// Returns every step that scans a Bear table in full, in plan order
+  pub fn full_scans(&self) -> Vec<&TableAccess> {
+    let mut scans = Vec::new();
+    collect_full_scans(&self.steps, &mut scans);
+    scans
+  }
+
+  /// Returns the full scans of tables with at least [`LARGE_TABLE_ROWS`] rows
+  pub fn large_full_scans(&self) -> Vec<&TableAccess> {
+    self
+      .full_scans()
+      .into_iter()
+      .filter(|scan| scan.rows.is_some_and(|rows| rows >= LARGE_TABLE_ROWS))
+      .collect()
+  }
+}
+
+fn collect_full_scans<'a>(
+  steps: &'a [PlanStep],
+  scans: &mut Vec<&'a TableAccess>,
+) {
+  for step in steps {
+    if let Some(access) = &step.access
+      && access.is_full_scan()
+    {
+      scans.push(access);
+    }
+    collect_full_scans(&step.children, scans);
+  }
+}
+
+impl fmt::Display for QueryPlan {
+  fn fmt(
+    &self,
+    f: &mut fmt::Formatter<'_>,
+  ) -> fmt::Result {
+    writeln!(f, "QUERY PLAN")?;
+    write_steps(f, &self.steps, "")?;
+
+    for scan in self.large_full_scans() {
+      writeln!(
+        f,
+        "warning: full scan of {} ({} rows) as {}",
+        scan.table.as_deref().unwrap_or(&scan.name),
+        scan.rows.unwrap_or_default(),
+        scan.name
+      )?;
+    }
+    Ok(())
+  }
+}
+
+/// Writes steps as a tree, like the `sqlite3` shell
+fn write_steps(
+  f: &mut fmt::Formatter<'_>,
+  steps: &[PlanStep],
+  indent: &str,
+) -> fmt::Result {
+  for (i, step) in steps.iter().enumerate() {
+    let last = i + 1 == steps.len();
+    writeln!(
+      f,
+      "{}{}{}",
+      indent,
+      if last { "`--" } else { "|--" },
+      step.detail
+    )?;
+    let child_indent = format!("{}{}", indent, if last { "   " } else { "|  " });
+    write_steps(f, &step.children, &child_indent)?;
+  }
+  Ok(())
+}
+
+/// One step of a query plan, with the steps nested under it
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlanStep {
+  id: i64,
+  detail: String,
+  access: Option<TableAccess>,
+  children: Vec<PlanStep>,
+}
+
+impl PlanStep {
+  /// Returns SQLite's id of the step
+  pub fn id(&self) -> i64 {
+    self.id
+  }
+
+  /// Returns SQLite's description of the step, e.g. `SCAN n` or
+  /// `SEARCH t USING INTEGER PRIMARY KEY (rowid=?)`
+  pub fn detail(&self) -> &str {
+    &self.detail
+  }
+
+  /// Returns the table the step reads, if it's a `SCAN` or `SEARCH`
+  pub fn access(&self) -> Option<&TableAccess> {
+    self.access.as_ref()
+  }
+
+  /// Returns the steps nested under this one
+  pub fn children(&self) -> &[PlanStep] {
+    &self.children
+  }
+}
+
+/// How a query reads a table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+  /// Reads every row (`SCAN`), possibly in the order of an index
+  Scan,
+  /// Looks rows up with an index or the primary key (`SEARCH`)
+  Search,
+}
+
+/// A `SCAN` or `SEARCH` step of a query plan
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableAccess {
+  kind: AccessKind,
+  name: String,
+  table: Option<String>,
+  using: Option<String>,
+  rows: Option<u64>,
+}
+
+impl TableAccess {
+  /// Returns whether the step scans or searches
+  pub fn kind(&self) -> AccessKind {
+    self.kind
+  }
+
+  /// Returns the name in the plan, usually an alias from the normalizing CTEs
+  /// (`n` for `ZSFNOTE as n`)
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  /// Returns the Bear table read, when `name` could be resolved to one
+  pub fn table(&self) -> Option<&str> {
+    self.table.as_deref()
+  }
+
+  /// Returns the index used, e.g. `INTEGER PRIMARY KEY (rowid=?)` or
+  /// `COVERING INDEX Z_5TAGS_INDEX`
+  pub fn using(&self) -> Option<&str> {
+    self.using.as_deref()
+  }
+
+  /// Returns the number of rows in the table, when it was resolved
+  pub fn rows(&self) -> Option<u64> {
+    self.rows
+  }
+
+  /// Returns true if this step reads every row of a Bear table
+  pub fn is_full_scan(&self) -> bool {
+    self.kind == AccessKind::Scan && self.table.is_some()
+  }
+}
+
+/// One row of `EXPLAIN QUERY PLAN`
+struct PlanRow {
+  id: i64,
+  parent: i64,
+  detail: String,
+}
+
+/// Plans a query and parses the plan.
+///
+/// # Arguments
+/// * `queryable` - The Queryable wrapper that prepends normalizing CTEs
+/// * `sql` - The user's SQL query (will have CTEs prepended automatically)
+pub fn explain(
+  queryable: &Queryable,
+  sql: &str,
+) -> Result<QueryPlan, BearError> {
+  let full_sql = queryable.full_sql(sql);
+  let mut stmt = queryable
+    .conn
+    .prepare(&format!("EXPLAIN QUERY PLAN {}", full_sql))?;
+  let rows = stmt
+    .query_map([], |row| {
+      Ok(PlanRow {
+        id: row.get(0)?,
+        parent: row.get(1)?,
+        detail: row.get(3)?,
+      })
+    })?
+    .collect::<rusqlite::Result<Vec<_>>>()?;
+
+  let tables = resolve_aliases(queryable, &full_sql)?;
+  let mut row_counts = HashMap::new();
+  let mut steps = Vec::with_capacity(rows.len());
+  for row in &rows {
+    let mut access = parse_access(&row.detail);
+    if let Some(access) = &mut access {
+      access.table = tables.get(&access.name.to_lowercase()).cloned();
+      if let Some(table) = &access.table {
+        if !row_counts.contains_key(table) {
+          let count: u64 = queryable.conn.query_row(
+            &format!("SELECT count(*) FROM \"{}\"", table.replace('"', "\"\"")),
+            [],
+            |row| row.get(0),
+          )?;
+          row_counts.insert(table.clone(), count);
+        }
+        access.rows = row_counts.get(table).copied();
+      }
+    }
+    let step = PlanStep {
+      id: row.id,
+      detail: row.detail.clone(),
+      access,
+      children: Vec::new(),
+    };
+    steps.push((row.parent, step));
+  }
+
+  Ok(QueryPlan {
+    sql: full_sql,
+    steps: build_tree(&steps, 0),
+  })
+}
+
+/// Nests the steps whose parent is `parent`, with their own children
+fn build_tree(
+  steps: &[(i64, PlanStep)],
+  parent: i64,
+) -> Vec<PlanStep> {
+  steps
+    .iter()
+    .filter(|(step_parent, _)| *step_parent == parent)
+    .map(|(_, step)| PlanStep {
+      children: build_tree(steps, step.id),
+      ..step.clone()
+    })
+    .collect()
+}
+
+/// Parses `SCAN <name> [USING ...]` and `SEARCH <name> [USING ...]` steps
+fn parse_access(detail: &str) -> Option<TableAccess> {
+  let (kind, rest) = if let Some(rest) = detail.strip_prefix("SCAN ") {
+    (AccessKind::Scan, rest)
+  } else if let Some(rest) = detail.strip_prefix("SEARCH ") {
+    (AccessKind::Search, rest)
+  } else {
+    return None;
+  };
+  // `SCAN CONSTANT ROW` reads no table
+  if rest.starts_with("CONSTANT ROW") {
+    return None;
+  }
+
+  let (name, using) = match rest.split_once(" USING ") {
+    Some((name, using)) => (name, Some(using.to_string())),
+    None => (rest, None),
+  };
+  Some(TableAccess {
+    kind,
+    name: name.trim().to_string(),
+    table: None,
+    using,
+    rows: None,
+  })
+}
+
+/// Maps the lowercased names and aliases of the tables in `sql` to the table names.
+///
+/// Finds `<table> [AS] <alias>` in the SQL for every table in the database, which
+/// covers the aliases of the normalizing CTEs and typical user queries.
+fn resolve_aliases(
+  queryable: &Queryable,
+  sql: &str,
+) -> Result<HashMap<String, String>, BearError> {
+  let mut stmt = queryable
+    .conn
+    .prepare("SELECT name FROM sqlite_master WHERE type = 'table'")?;
+  let tables = stmt
+    .query_map([], |row| row.get::<_, String>(0))?
+    .collect::<rusqlite::Result<Vec<_>>>()?;
+
+  let mut aliases: HashMap<String, String> = tables
+    .iter()
+    .map(|table| (table.to_lowercase(), table.clone()))
+    .collect();
+
+  let words: Vec<&str> = sql
+    .split(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+    .filter(|word| !word.is_empty())
+    .collect();
+  for (i, word) in words.iter().enumerate() {
+    let Some(table) = tables.iter().find(|table| table.eq_ignore_ascii_case(word)) else {
+      continue;
+    };
+    let alias = match words.get(i + 1) {
+      Some(next) if next.eq_ignore_ascii_case("as") => words.get(i + 2),
+      next => next,
+    };
+    if let Some(alias) = alias {
+      aliases
+        .entry(alias.to_lowercase())
+        .or_insert_with(|| table.clone());
+    }
+  }
+  Ok(aliases)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_access() {
+    let scan = parse_access("SCAN n").unwrap();
+    assert_eq!(scan.kind(), AccessKind::Scan);
+    assert_eq!(scan.name(), "n");
+    assert_eq!(scan.using(), None);
+
+    let search = parse_access("SEARCH t USING INTEGER PRIMARY KEY (rowid=?)").unwrap();
+    assert_eq!(search.kind(), AccessKind::Search);
+    assert_eq!(search.name(), "t");
+    assert_eq!(search.using(), Some("INTEGER PRIMARY KEY (rowid=?)"));
+
+    assert_eq!(parse_access("SCAN CONSTANT ROW"), None);
+    assert_eq!(parse_access("USE TEMP B-TREE FOR ORDER BY"), None);
+  }
+
+  #[test]
+  fn test_report() {
+    let scan = |name: &str, table: &str, rows: u64| TableAccess {
+      kind: AccessKind::Scan,
+      name: name.to_string(),
+      table: Some(table.to_string()),
+      using: None,
+      rows: Some(rows),
+    };
+    let step =
+      |id: i64, detail: &str, access: Option<TableAccess>, children: Vec<PlanStep>| PlanStep {
+        id,
+        detail: detail.to_string(),
+        access,
+        children,
+      };
+    let plan = QueryPlan {
+      sql: String::new(),
+      steps: vec![
+        step(
+          3,
+          "MATERIALIZE core_data",
+          None,
+          vec![step(5, "SCAN CONSTANT ROW", None, vec![])],
+        ),
+        step(17, "SCAN nt", Some(scan("nt", "Z_5TAGS", 10)), vec![]),
+        step(19, "SCAN n", Some(scan("n", "ZSFNOTE", 20_000)), vec![]),
+      ],
+    };
+
+    assert_eq!(plan.full_scans().len(), 2);
+    assert_eq!(plan.large_full_scans().len(), 1);
+    assert_eq!(
+      plan.to_string(),
+      "QUERY PLAN
+|--MATERIALIZE core_data
+|  `--SCAN CONSTANT ROW
+|--SCAN nt
+`--SCAN n
+warning: full scan of ZSFNOTE (20000 rows) as n
+"
+    );
+  }
+}
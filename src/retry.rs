@@ -0,0 +1,342 @@
+//! Retrying operations that fail while Bear holds its database.
+//!
+//! Each connection already waits up to five seconds for Bear's locks (SQLite's busy
+//! timeout). A long write can outlast that, failing with `BearError::Busy`. A
+//! [`RetryPolicy`] set with [`BearDb::with_retry_policy()`](crate::BearDb::with_retry_policy)
+//! runs such operations again on a fresh connection, waiting longer after each
+//! attempt. Only errors for which `BearError::is_retryable()` is true are retried.
+use crate::BearError;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// A hook receiving each [`RetryEvent`]
+type RetryHook = Arc<dyn Fn(&RetryEvent<'_>) + Send + Sync>;
+
+/// How often and how long to retry operations that fail with a transient error.
+///
+/// By default an operation is attempted up to 4 times, waiting 250 milliseconds
+/// after the first failure and doubling the wait after each one, up to 4 seconds.
+/// Waits are jittered (between half and all of the computed wait) so that several
+/// processes blocked by the same write don't retry in lockstep. There is no total
+/// deadline unless one is set.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use bear_query::{BearDb, RetryEvent, RetryPolicy};
+/// # use std::time::Duration;
+/// # fn main() -> Result<(), bear_query::BearError> {
+/// let policy = RetryPolicy::new()
+///   .max_attempts(6)
+///   .backoff(Duration::from_millis(500), Duration::from_secs(8))
+///   .deadline(Duration::from_secs(60))
+///   .on_retry(|event| {
+///     if let RetryEvent::Retrying { attempt, delay, error } = event {
+///       eprintln!("attempt {} failed ({}), retrying in {:?}", attempt, error, delay);
+///     }
+///   });
+/// let db = BearDb::new()?.with_retry_policy(policy);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct RetryPolicy {
+  max_attempts: u32,
+  initial_backoff: Duration,
+  max_backoff: Duration,
+  jitter: bool,
+  deadline: Option<Duration>,
+  on_retry: Option<RetryHook>,
+}
+
+/// What happened to an operation run with a [`RetryPolicy`], passed to its
+/// `on_retry()` hook for metrics and logging.
+#[derive(Debug)]
+pub enum RetryEvent<'a> {
+  /// Attempt number `attempt` (starting at 1) failed with a retryable `error`; the
+  /// next attempt starts after `delay`
+  Retrying {
+    attempt: u32,
+    delay: Duration,
+    error: &'a BearError,
+  },
+  /// The operation succeeded after `retries` failed attempts
+  Recovered { retries: u32 },
+  /// The operation failed with a retryable `error` on its last allowed attempt, or
+  /// waiting again would pass the deadline; `error` is returned to the caller
+  Exhausted { attempts: u32, error: &'a BearError },
+}
+
+impl Default for RetryPolicy {
+  fn default() -> Self {
+    Self {
+      max_attempts: 4,
+      initial_backoff: Duration::from_millis(250),
+      max_backoff: Duration::from_secs(4),
+      jitter: true,
+      deadline: None,
+      on_retry: None,
+    }
+  }
+}
+
+impl RetryPolicy {
+  /// Creates a policy with the default attempts and backoff
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Attempts an operation at most this many times, including the first (at least 1)
+  pub fn max_attempts(
+    mut self,
+    attempts: u32,
+  ) -> Self {
+    self.max_attempts = attempts.max(1);
+    self
+  }
+
+  /// Waits `initial` after the first failure, doubling the wait after each further
+  /// failure up to `max`
+  pub fn backoff(
+    mut self,
+    initial: Duration,
+    max: Duration,
+  ) -> Self {
+    self.initial_backoff = initial;
+    self.max_backoff = max.max(initial);
+    self
+  }
+
+  /// Waits exactly the computed backoff, without randomizing it
+  pub fn no_jitter(mut self) -> Self {
+    self.jitter = false;
+    self
+  }
+
+  /// Stops retrying once the next attempt would start more than `deadline` after
+  /// the first one started
+  pub fn deadline(
+    mut self,
+    deadline: Duration,
+  ) -> Self {
+    self.deadline = Some(deadline);
+    self
+  }
+
+  /// Calls `hook` with every retry, recovery and exhausted operation
+  pub fn on_retry<F>(
+    mut self,
+    hook: F,
+  ) -> Self
+  where
+    F: Fn(&RetryEvent<'_>) + Send + Sync + 'static,
+  {
+    self.on_retry = Some(Arc::new(hook));
+    self
+  }
+
+  /// The wait after failed attempt number `attempt`, before jitter
+  fn backoff_after(
+    &self,
+    attempt: u32,
+  ) -> Duration {
+    let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+    self
+      .initial_backoff
+      .saturating_mul(factor)
+      .min(self.max_backoff)
+  }
+
+  /// The randomized wait after failed attempt number `attempt`
+  fn delay_after(
+    &self,
+    attempt: u32,
+  ) -> Duration {
+    let backoff = self.backoff_after(attempt);
+    if self.jitter {
+      backoff.mul_f64(0.5 + random_fraction() / 2.0)
+    } else {
+      backoff
+    }
+  }
+
+  fn report(
+    &self,
+    event: RetryEvent<'_>,
+  ) {
+    if let Some(hook) = &self.on_retry {
+      hook(&event);
+    }
+  }
+
+  /// Runs `attempt` until it succeeds, fails with an error that isn't retryable, or
+  /// the policy runs out of attempts or time
+  pub(crate) fn run<R>(
+    &self,
+    mut attempt: impl FnMut() -> Result<R, BearError>,
+  ) -> Result<R, BearError> {
+    let started = Instant::now();
+    let mut attempts = 0;
+    loop {
+      attempts += 1;
+      let error = match attempt() {
+        Ok(result) => {
+          if attempts > 1 {
+            self.report(RetryEvent::Recovered {
+              retries: attempts - 1,
+            });
+          }
+          return Ok(result);
+        }
+        Err(error) if error.is_retryable() => error,
+        Err(error) => return Err(error),
+      };
+
+      let delay = self.delay_after(attempts);
+      let past_deadline = self
+        .deadline
+        .is_some_and(|deadline| started.elapsed() + delay > deadline);
+      if attempts >= self.max_attempts || past_deadline {
+        self.report(RetryEvent::Exhausted {
+          attempts,
+          error: &error,
+        });
+        return Err(error);
+      }
+
+      self.report(RetryEvent::Retrying {
+        attempt: attempts,
+        delay,
+        error: &error,
+      });
+      std::thread::sleep(delay);
+    }
+  }
+}
+
+impl fmt::Debug for RetryPolicy {
+  fn fmt(
+    &self,
+    f: &mut fmt::Formatter<'_>,
+  ) -> fmt::Result {
+    f.debug_struct("RetryPolicy")
+      .field("max_attempts", &self.max_attempts)
+      .field("initial_backoff", &self.initial_backoff)
+      .field("max_backoff", &self.max_backoff)
+      .field("jitter", &self.jitter)
+      .field("deadline", &self.deadline)
+      .field("on_retry", &self.on_retry.is_some())
+      .finish()
+  }
+}
+
+/// A random number in `[0, 1)`, from the randomly seeded keys of `RandomState`
+fn random_fraction() -> f64 {
+  let bits = RandomState::new().build_hasher().finish();
+  (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::Mutex;
+
+  fn busy() -> BearError {
+    BearError::Busy { sql: None }
+  }
+
+  /// Runs `policy` on an operation failing with `errors` in turn, then succeeding
+  fn run_failing(
+    policy: &RetryPolicy,
+    errors: Vec<BearError>,
+  ) -> (Result<u32, BearError>, u32) {
+    let mut errors = errors.into_iter();
+    let mut attempts = 0;
+    let result = policy.run(|| {
+      attempts += 1;
+      match errors.next() {
+        Some(error) => Err(error),
+        None => Ok(attempts),
+      }
+    });
+    (result, attempts)
+  }
+
+  #[test]
+  fn test_backoff() {
+    let policy = RetryPolicy::new()
+      .backoff(Duration::from_millis(100), Duration::from_millis(500))
+      .no_jitter();
+    let delays: Vec<_> = (1..=5).map(|attempt| policy.delay_after(attempt)).collect();
+    assert_eq!(delays, [100, 200, 400, 500, 500].map(Duration::from_millis));
+    assert_eq!(policy.backoff_after(100), Duration::from_millis(500));
+
+    let policy = RetryPolicy::new().backoff(Duration::from_millis(100), Duration::from_secs(1));
+    for _ in 0..100 {
+      let delay = policy.delay_after(2);
+      assert!(
+        delay >= Duration::from_millis(100) && delay <= Duration::from_millis(200),
+        "{:?}",
+        delay
+      );
+    }
+  }
+
+  #[test]
+  fn test_retries_transient_errors() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let log = events.clone();
+    let policy = RetryPolicy::new()
+      .backoff(Duration::from_millis(1), Duration::from_millis(1))
+      .no_jitter()
+      .on_retry(move |event| {
+        log.lock().unwrap().push(match event {
+          RetryEvent::Retrying { attempt, .. } => format!("retrying {}", attempt),
+          RetryEvent::Recovered { retries } => format!("recovered {}", retries),
+          RetryEvent::Exhausted { attempts, .. } => format!("exhausted {}", attempts),
+        })
+      });
+
+    let (result, attempts) = run_failing(&policy, vec![busy(), busy()]);
+    assert_eq!(result.unwrap(), 3);
+    assert_eq!(attempts, 3);
+    assert_eq!(
+      events.lock().unwrap().drain(..).collect::<Vec<_>>(),
+      ["retrying 1", "retrying 2", "recovered 2"]
+    );
+
+    // Out of attempts
+    let (result, attempts) = run_failing(&policy, (0..4).map(|_| busy()).collect());
+    assert!(matches!(result, Err(BearError::Busy { .. })));
+    assert_eq!(attempts, 4);
+    assert_eq!(
+      events.lock().unwrap().drain(..).collect::<Vec<_>>(),
+      ["retrying 1", "retrying 2", "retrying 3", "exhausted 4"]
+    );
+
+    // Errors that aren't transient are returned at once, without events
+    let (result, attempts) = run_failing(&policy, vec![BearError::Cancelled, busy()]);
+    assert!(matches!(result, Err(BearError::Cancelled)));
+    assert_eq!(attempts, 1);
+    assert!(events.lock().unwrap().is_empty());
+  }
+
+  #[test]
+  fn test_deadline() {
+    let policy = RetryPolicy::new()
+      .max_attempts(100)
+      .backoff(Duration::from_millis(20), Duration::from_millis(20))
+      .no_jitter()
+      .deadline(Duration::from_millis(50));
+
+    let started = Instant::now();
+    let (result, attempts) = run_failing(&policy, (0..100).map(|_| busy()).collect());
+    assert!(matches!(result, Err(BearError::Busy { .. })));
+    // Two waits of 20ms fit in the deadline, a third would pass it
+    assert_eq!(attempts, 3);
+    assert!(started.elapsed() < Duration::from_millis(50));
+  }
+}
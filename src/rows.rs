@@ -0,0 +1,381 @@
+/// Row results for generic queries
+///
+/// This module collects rusqlite rows as raw values, or deserializes them with serde,
+/// mapping columns onto struct fields by name (or onto tuple elements by position),
+/// without going through Polars.
+use crate::{BearError, Queryable};
+use rusqlite::Params;
+use rusqlite::types::Value;
+use serde::de::value::Error;
+use serde::de::{
+  DeserializeOwned, DeserializeSeed, Deserializer, Error as _, IntoDeserializer, MapAccess,
+  SeqAccess, Visitor,
+};
+
+/// The column names and raw SQLite values of a generic query's rows.
+///
+/// Returned by [`BearDb::query_rows()`](crate::BearDb::query_rows); each row has one
+/// value per column, in column order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryRows {
+  columns: Vec<String>,
+  rows: Vec<Vec<Value>>,
+}
+
+impl QueryRows {
+  /// Returns the column names, in order
+  pub fn columns(&self) -> &[String] {
+    &self.columns
+  }
+
+  /// Returns the position of the column with this name
+  pub fn column_index(
+    &self,
+    name: &str,
+  ) -> Option<usize> {
+    self.columns.iter().position(|column| column == name)
+  }
+
+  /// Returns the rows
+  pub fn rows(&self) -> &[Vec<Value>] {
+    &self.rows
+  }
+
+  /// Consumes the result, returning the rows
+  pub fn into_rows(self) -> Vec<Vec<Value>> {
+    self.rows
+  }
+
+  /// Returns the number of rows
+  pub fn len(&self) -> usize {
+    self.rows.len()
+  }
+
+  /// Returns true if the query returned no rows
+  pub fn is_empty(&self) -> bool {
+    self.rows.is_empty()
+  }
+}
+
+/// Runs a query and collects the raw values of every row.
+///
+/// # Arguments
+/// * `queryable` - The Queryable wrapper that prepends normalizing CTEs
+/// * `sql` - The user's SQL query (will have CTEs prepended automatically)
+/// * `params` - Values bound to the query's `?1`/`:name` parameters
+pub fn query_rows(
+  queryable: &Queryable,
+  sql: &str,
+  params: impl Params,
+) -> Result<QueryRows, BearError> {
+  let mut stmt = queryable.prepare(sql)?;
+  let columns: Vec<String> = stmt
+    .column_names()
+    .into_iter()
+    .map(|s| s.to_string())
+    .collect();
+
+  let mut budget = queryable.result_budget();
+  let mut rows = stmt.query(params)?;
+  let mut values = Vec::new();
+  while let Some(row) = rows.next()? {
+    budget.add_row(row, columns.len())?;
+    values.push(
+      (0..columns.len())
+        .map(|i| row.get(i))
+        .collect::<rusqlite::Result<Vec<Value>>>()?,
+    );
+  }
+
+  Ok(QueryRows {
+    columns,
+    rows: values,
+  })
+}
+
+/// Runs a query and deserializes every row into `T`.
+///
+/// # Arguments
+/// * `queryable` - The Queryable wrapper that prepends normalizing CTEs
+/// * `sql` - The user's SQL query (will have CTEs prepended automatically)
+/// * `params` - Values bound to the query's `?1`/`:name` parameters
+pub fn query_as<T: DeserializeOwned>(
+  queryable: &Queryable,
+  sql: &str,
+  params: impl Params,
+) -> Result<Vec<T>, BearError> {
+  let mut stmt = queryable.prepare(sql)?;
+  let columns: Vec<String> = stmt
+    .column_names()
+    .into_iter()
+    .map(|s| s.to_string())
+    .collect();
+
+  let mut budget = queryable.result_budget();
+  let mut rows = stmt.query(params)?;
+  let mut results = Vec::new();
+  while let Some(row) = rows.next()? {
+    budget.add_row(row, columns.len())?;
+    let values = (0..columns.len())
+      .map(|i| row.get::<_, Value>(i))
+      .collect::<rusqlite::Result<Vec<_>>>()?;
+    let deserializer = RowDeserializer {
+      columns: &columns,
+      values,
+    };
+    let value = T::deserialize(deserializer)
+      .map_err(|e| Error::custom(format_args!("row {}: {}", results.len(), e)))?;
+    results.push(value);
+  }
+
+  Ok(results)
+}
+
+/// Deserializes one row: as a map of column names for structs and maps, as a
+/// sequence for tuples, or as its only column for anything else
+struct RowDeserializer<'a> {
+  columns: &'a [String],
+  values: Vec<Value>,
+}
+
+impl<'a> RowDeserializer<'a> {
+  /// The row's only column, for deserializing a single value per row
+  fn single_column(self) -> Result<ValueDeserializer, Error> {
+    match (self.columns, self.values.into_iter().next()) {
+      ([_], Some(value)) => Ok(ValueDeserializer(value)),
+      (columns, _) => Err(Error::custom(format_args!(
+        "expected a single column, got {}",
+        columns.len()
+      ))),
+    }
+  }
+
+  fn access(self) -> RowAccess<'a> {
+    RowAccess {
+      columns: self.columns,
+      values: self.values.into_iter(),
+      next: 0,
+    }
+  }
+}
+
+/// Forwards deserializer methods to the row's only column
+macro_rules! forward_to_single_column {
+  ($($method:ident)*) => {
+    $(
+      fn $method<V: Visitor<'de>>(
+        self,
+        visitor: V,
+      ) -> Result<V::Value, Error> {
+        self.single_column()?.$method(visitor)
+      }
+    )*
+  };
+}
+
+impl<'de> Deserializer<'de> for RowDeserializer<'_> {
+  type Error = Error;
+
+  fn deserialize_any<V: Visitor<'de>>(
+    self,
+    visitor: V,
+  ) -> Result<V::Value, Error> {
+    self.deserialize_map(visitor)
+  }
+
+  fn deserialize_map<V: Visitor<'de>>(
+    self,
+    visitor: V,
+  ) -> Result<V::Value, Error> {
+    visitor.visit_map(self.access())
+  }
+
+  fn deserialize_struct<V: Visitor<'de>>(
+    self,
+    _name: &'static str,
+    _fields: &'static [&'static str],
+    visitor: V,
+  ) -> Result<V::Value, Error> {
+    self.deserialize_map(visitor)
+  }
+
+  fn deserialize_seq<V: Visitor<'de>>(
+    self,
+    visitor: V,
+  ) -> Result<V::Value, Error> {
+    visitor.visit_seq(self.access())
+  }
+
+  fn deserialize_tuple<V: Visitor<'de>>(
+    self,
+    _len: usize,
+    visitor: V,
+  ) -> Result<V::Value, Error> {
+    self.deserialize_seq(visitor)
+  }
+
+  fn deserialize_tuple_struct<V: Visitor<'de>>(
+    self,
+    _name: &'static str,
+    _len: usize,
+    visitor: V,
+  ) -> Result<V::Value, Error> {
+    self.deserialize_seq(visitor)
+  }
+
+  fn deserialize_newtype_struct<V: Visitor<'de>>(
+    self,
+    _name: &'static str,
+    visitor: V,
+  ) -> Result<V::Value, Error> {
+    visitor.visit_newtype_struct(self)
+  }
+
+  fn deserialize_unit_struct<V: Visitor<'de>>(
+    self,
+    name: &'static str,
+    visitor: V,
+  ) -> Result<V::Value, Error> {
+    self.single_column()?.deserialize_unit_struct(name, visitor)
+  }
+
+  fn deserialize_enum<V: Visitor<'de>>(
+    self,
+    name: &'static str,
+    variants: &'static [&'static str],
+    visitor: V,
+  ) -> Result<V::Value, Error> {
+    self
+      .single_column()?
+      .deserialize_enum(name, variants, visitor)
+  }
+
+  forward_to_single_column! {
+    deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+    deserialize_i128 deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64
+    deserialize_u128 deserialize_f32 deserialize_f64 deserialize_char deserialize_str
+    deserialize_string deserialize_bytes deserialize_byte_buf deserialize_option
+    deserialize_unit deserialize_identifier deserialize_ignored_any
+  }
+}
+
+/// Walks a row's columns, as map entries or sequence elements
+struct RowAccess<'a> {
+  columns: &'a [String],
+  values: std::vec::IntoIter<Value>,
+  next: usize,
+}
+
+impl<'de> MapAccess<'de> for RowAccess<'_> {
+  type Error = Error;
+
+  fn next_key_seed<K: DeserializeSeed<'de>>(
+    &mut self,
+    seed: K,
+  ) -> Result<Option<K::Value>, Error> {
+    match self.columns.get(self.next) {
+      Some(column) => seed
+        .deserialize(column.as_str().into_deserializer())
+        .map(Some),
+      None => Ok(None),
+    }
+  }
+
+  fn next_value_seed<V: DeserializeSeed<'de>>(
+    &mut self,
+    seed: V,
+  ) -> Result<V::Value, Error> {
+    self
+      .next_element_seed(seed)?
+      .ok_or_else(|| Error::custom("missing column value"))
+  }
+}
+
+impl<'de> SeqAccess<'de> for RowAccess<'_> {
+  type Error = Error;
+
+  fn next_element_seed<T: DeserializeSeed<'de>>(
+    &mut self,
+    seed: T,
+  ) -> Result<Option<T::Value>, Error> {
+    let (Some(column), Some(value)) = (self.columns.get(self.next), self.values.next()) else {
+      return Ok(None);
+    };
+    self.next += 1;
+    seed
+      .deserialize(ValueDeserializer(value))
+      .map(Some)
+      .map_err(|e| Error::custom(format_args!("column '{}': {}", column, e)))
+  }
+
+  fn size_hint(&self) -> Option<usize> {
+    Some(self.columns.len() - self.next)
+  }
+}
+
+/// Deserializes one SQLite value
+struct ValueDeserializer(Value);
+
+impl<'de> Deserializer<'de> for ValueDeserializer {
+  type Error = Error;
+
+  fn deserialize_any<V: Visitor<'de>>(
+    self,
+    visitor: V,
+  ) -> Result<V::Value, Error> {
+    match self.0 {
+      Value::Null => visitor.visit_unit(),
+      Value::Integer(i) => visitor.visit_i64(i),
+      Value::Real(f) => visitor.visit_f64(f),
+      Value::Text(s) => visitor.visit_string(s),
+      Value::Blob(b) => visitor.visit_byte_buf(b),
+    }
+  }
+
+  /// SQLite has no boolean type, so integers stand in for them (`is_pinned` etc.)
+  fn deserialize_bool<V: Visitor<'de>>(
+    self,
+    visitor: V,
+  ) -> Result<V::Value, Error> {
+    match self.0 {
+      Value::Integer(i) => visitor.visit_bool(i != 0),
+      _ => self.deserialize_any(visitor),
+    }
+  }
+
+  fn deserialize_option<V: Visitor<'de>>(
+    self,
+    visitor: V,
+  ) -> Result<V::Value, Error> {
+    match self.0 {
+      Value::Null => visitor.visit_none(),
+      _ => visitor.visit_some(self),
+    }
+  }
+
+  fn deserialize_newtype_struct<V: Visitor<'de>>(
+    self,
+    _name: &'static str,
+    visitor: V,
+  ) -> Result<V::Value, Error> {
+    visitor.visit_newtype_struct(self)
+  }
+
+  /// Text values name unit variants of an enum
+  fn deserialize_enum<V: Visitor<'de>>(
+    self,
+    _name: &'static str,
+    _variants: &'static [&'static str],
+    visitor: V,
+  ) -> Result<V::Value, Error> {
+    match self.0 {
+      Value::Text(s) => visitor.visit_enum(s.into_deserializer()),
+      _ => self.deserialize_any(visitor),
+    }
+  }
+
+  serde::forward_to_deserialize_any! {
+    i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+    bytes byte_buf unit unit_struct seq tuple tuple_struct map struct identifier ignored_any
+  }
+}
@@ -3,7 +3,7 @@
 //! This module handles the variable parts of Bear's schema that may change across versions,
 //! particularly the numbered junction tables (e.g., Z_5TAGS) and their column names.
 
-use rusqlite::Connection;
+use rusqlite::{Connection, OptionalExtension};
 
 use crate::BearError;
 
@@ -41,6 +41,16 @@ pub fn setup_test_schema(conn: &Connection) -> Result<(), BearError> {
         ZLINKINGTO INTEGER
       );
 
+      CREATE TABLE ZSFNOTEFILE (
+        Z_PK INTEGER PRIMARY KEY,
+        ZUNIQUEIDENTIFIER TEXT,
+        ZNOTE INTEGER,
+        ZFILENAME TEXT,
+        ZFILESIZE INTEGER,
+        ZCREATIONDATE REAL,
+        ZMODIFICATIONDATE REAL
+      );
+
       -- Insert sample test data
       -- Core Data epoch: 2001-01-01, so timestamp 0 = 2001-01-01
       INSERT INTO ZSFNOTE (Z_PK, ZUNIQUEIDENTIFIER, ZTITLE, ZTEXT, ZMODIFICATIONDATE, ZCREATIONDATE, ZPINNED, ZTRASHED, ZARCHIVED)
@@ -70,7 +80,7 @@ pub fn setup_test_schema(conn: &Connection) -> Result<(), BearError> {
         (1, 2);
     ",
     )
-    .map_err(|e| BearError::SqlError { source: e })?;
+    .map_err(|e| BearError::SqlError { source: e, sql: None })?;
 
   Ok(())
 }
@@ -85,43 +95,159 @@ pub struct BearDbMetadata {
   pub junction_notes_column: String,
   /// Column name in junction table that references tags (e.g., "Z_13TAGS")
   pub junction_tags_column: String,
+  /// Whether the attachments table (`ZSFNOTEFILE`) exists with the expected columns
+  pub has_attachments: bool,
 }
 
+/// Tables and columns the normalizing CTE reads, besides the junction table
+const REQUIRED_COLUMNS: [(&str, &[&str]); 3] = [
+  (
+    "ZSFNOTE",
+    &[
+      "Z_PK",
+      "ZUNIQUEIDENTIFIER",
+      "ZTITLE",
+      "ZTEXT",
+      "ZMODIFICATIONDATE",
+      "ZCREATIONDATE",
+      "ZPINNED",
+      "ZTRASHED",
+      "ZARCHIVED",
+    ],
+  ),
+  ("ZSFNOTETAG", &["Z_PK", "ZTITLE", "ZMODIFICATIONDATE"]),
+  ("ZSFNOTEBACKLINK", &["ZLINKEDBY", "ZLINKINGTO"]),
+];
+
+/// Columns of `ZSFNOTEFILE` referenced by the `attachments` view
+const ATTACHMENT_COLUMNS: [&str; 6] = [
+  "ZUNIQUEIDENTIFIER",
+  "ZNOTE",
+  "ZFILENAME",
+  "ZFILESIZE",
+  "ZCREATIONDATE",
+  "ZMODIFICATIONDATE",
+];
+
 /// Discovers variable schema information from Bear's database
+///
+/// Fails with `BearError::SchemaUnsupported`, listing everything missing, when a table
+/// or column the normalizing CTE reads doesn't exist.
 pub fn discover_metadata(conn: &Connection) -> Result<BearDbMetadata, BearError> {
-  // First, find the junction table - it should match the pattern Z_<number>TAGS
-  let junction_table_name = find_junction_table(conn)?;
-
-  // Query the junction table to find its column names
-  let query = format!("PRAGMA table_info({})", junction_table_name);
-  let mut stmt = conn.prepare(&query)?;
-  let columns: Vec<String> = stmt
-    .query_map([], |row| row.get::<_, String>("name"))?
+  let mut missing_tables = Vec::new();
+  let mut missing_columns = Vec::new();
+
+  for (table, required) in REQUIRED_COLUMNS {
+    let columns = table_columns(conn, table)?;
+    if columns.is_empty() {
+      missing_tables.push(table.to_string());
+      continue;
+    }
+    missing_columns.extend(
+      required
+        .iter()
+        .filter(|column| !columns.iter().any(|name| name == *column))
+        .map(|column| format!("{}.{}", table, column)),
+    );
+  }
+
+  // Find the junction table - it should match the pattern Z_<number>TAGS
+  let mut junction = None;
+  match find_junction_table(conn)? {
+    None => missing_tables.push("Z_<n>TAGS".to_string()),
+    Some(junction_table_name) => {
+      // Find the columns that reference notes and tags
+      // They follow the pattern Z_<number>NOTES and Z_<number>TAGS
+      let columns = table_columns(conn, &junction_table_name)?;
+      let find = |suffix: &str| columns.iter().find(|name| name.ends_with(suffix)).cloned();
+      match (find("NOTES"), find("TAGS")) {
+        (Some(notes_column), Some(tags_column)) => {
+          junction = Some((junction_table_name, notes_column, tags_column));
+        }
+        (notes_column, tags_column) => {
+          if notes_column.is_none() {
+            missing_columns.push(format!("{}.Z_<n>NOTES", junction_table_name));
+          }
+          if tags_column.is_none() {
+            missing_columns.push(format!("{}.Z_<n>TAGS", junction_table_name));
+          }
+        }
+      }
+    }
+  }
+
+  match junction {
+    Some((junction_table_name, junction_notes_column, junction_tags_column))
+      if missing_tables.is_empty() && missing_columns.is_empty() =>
+    {
+      Ok(BearDbMetadata {
+        junction_table_name,
+        junction_notes_column,
+        junction_tags_column,
+        has_attachments: has_attachments_table(conn)?,
+      })
+    }
+    _ => Err(BearError::SchemaUnsupported {
+      missing_tables,
+      missing_columns,
+    }),
+  }
+}
+
+/// Returns the column names of `table`, or none if it doesn't exist
+fn table_columns(
+  conn: &Connection,
+  table: &str,
+) -> Result<Vec<String>, BearError> {
+  let mut stmt = conn.prepare("SELECT name FROM pragma_table_info(?1)")?;
+  let columns = stmt
+    .query_map([table], |row| row.get(0))?
     .collect::<Result<Vec<_>, _>>()?;
+  Ok(columns)
+}
+
+/// Checks whether `ZSFNOTEFILE` exists and has every column the `attachments` view needs.
+/// Older or newer Bear versions without it get an empty `attachments` view instead.
+fn has_attachments_table(conn: &Connection) -> Result<bool, BearError> {
+  let columns = table_columns(conn, "ZSFNOTEFILE")?;
+
+  Ok(
+    ATTACHMENT_COLUMNS
+      .iter()
+      .all(|required| columns.iter().any(|column| column == required)),
+  )
+}
 
-  // Find the columns that reference notes and tags
-  // They follow the pattern Z_<number>NOTES and Z_<number>TAGS
-  let junction_notes_column = columns
-    .iter()
-    .find(|name| name.ends_with("NOTES"))
-    .ok_or_else(|| rusqlite::Error::QueryReturnedNoRows)?
-    .clone();
-
-  let junction_tags_column = columns
-    .iter()
-    .find(|name| name.ends_with("TAGS"))
-    .ok_or_else(|| rusqlite::Error::QueryReturnedNoRows)?
-    .clone();
-
-  Ok(BearDbMetadata {
-    junction_table_name,
-    junction_notes_column,
-    junction_tags_column,
-  })
+/// Generates the `attachments` view, or an empty view with the same columns when
+/// the attachments table is unavailable.
+fn attachments_view(metadata: &BearDbMetadata) -> &'static str {
+  if metadata.has_attachments {
+    r#"
+    SELECT
+      f.ZUNIQUEIDENTIFIER as id,
+      n.ZUNIQUEIDENTIFIER as note_id,
+      f.ZFILENAME as filename,
+      f.ZFILESIZE as size,
+      strftime('%Y-%m-%d %H:%M:%f', f.ZMODIFICATIONDATE + cd.epoch, 'unixepoch') as modified,
+      strftime('%Y-%m-%d %H:%M:%f', f.ZCREATIONDATE + cd.epoch, 'unixepoch') as created
+    FROM ZSFNOTEFILE as f
+    JOIN ZSFNOTE as n ON n.Z_PK = f.ZNOTE
+    CROSS JOIN core_data as cd"#
+  } else {
+    r#"
+    SELECT
+      NULL as id,
+      NULL as note_id,
+      NULL as filename,
+      NULL as size,
+      NULL as modified,
+      NULL as created
+    WHERE 0"#
+  }
 }
 
 /// Finds the junction table name by querying sqlite_master for tables matching Z_<number>TAGS
-fn find_junction_table(conn: &Connection) -> Result<String, BearError> {
+fn find_junction_table(conn: &Connection) -> Result<Option<String>, BearError> {
   let mut stmt = conn.prepare(
     r"
     SELECT name
@@ -132,12 +258,16 @@ fn find_junction_table(conn: &Connection) -> Result<String, BearError> {
   ",
   )?;
 
-  let table_name: String = stmt.query_row([], |row| row.get(0))?;
+  let table_name = stmt.query_row([], |row| row.get(0)).optional()?;
 
   Ok(table_name)
 }
 
-/// Generates the normalizing CTE SQL that abstracts Bear's Core Data schema
+/// Generates the normalizing CTE SQL that abstracts Bear's Core Data schema.
+///
+/// `note_tags` and `note_links` resolve note UUIDs with LEFT JOINs on `Z_PK` rather
+/// than a correlated subquery per row, which SQLite plans much better for tag-heavy
+/// queries; junction rows whose note is missing still yield a NULL UUID.
 pub fn generate_normalizing_cte(metadata: &BearDbMetadata) -> String {
   format!(
     r#"
@@ -151,8 +281,10 @@ WITH
       n.Z_PK as core_db_id,
       n.ZTITLE as title,
       n.ZTEXT as content,
-      datetime(n.ZMODIFICATIONDATE + cd.epoch, 'unixepoch') as modified,
-      datetime(n.ZCREATIONDATE + cd.epoch, 'unixepoch') as created,
+      strftime('%Y-%m-%d %H:%M:%f', n.ZMODIFICATIONDATE + cd.epoch, 'unixepoch') as modified,
+      strftime('%Y-%m-%d %H:%M:%f', n.ZCREATIONDATE + cd.epoch, 'unixepoch') as created,
+      n.ZMODIFICATIONDATE as core_data_modified,
+      n.ZCREATIONDATE as core_data_created,
       n.ZPINNED as is_pinned,
       n.ZTRASHED as is_trashed,
       n.ZARCHIVED as is_archived
@@ -162,23 +294,32 @@ WITH
     SELECT
       t.Z_PK as id,
       t.ZTITLE as name,
-      datetime(t.ZMODIFICATIONDATE + cd.epoch, 'unixepoch') as modified
+      strftime('%Y-%m-%d %H:%M:%f', t.ZMODIFICATIONDATE + cd.epoch, 'unixepoch') as modified,
+      t.ZMODIFICATIONDATE as core_data_modified
     FROM ZSFNOTETAG as t, core_data as cd
   ),
   note_tags AS (
     SELECT
-      (SELECT n.ZUNIQUEIDENTIFIER FROM ZSFNOTE n WHERE n.Z_PK = nt.{}) as note_id,
-      nt.{} as tag_id
-    FROM {} as nt
+      n.ZUNIQUEIDENTIFIER as note_id,
+      nt.{1} as tag_id
+    FROM {2} as nt
+    LEFT JOIN ZSFNOTE as n ON n.Z_PK = nt.{0}
   ),
   note_links AS (
     SELECT
-      (SELECT n.ZUNIQUEIDENTIFIER FROM ZSFNOTE n WHERE n.Z_PK = nl.ZLINKEDBY) as from_note_id,
-      (SELECT n.ZUNIQUEIDENTIFIER FROM ZSFNOTE n WHERE n.Z_PK = nl.ZLINKINGTO) as to_note_id
+      from_note.ZUNIQUEIDENTIFIER as from_note_id,
+      to_note.ZUNIQUEIDENTIFIER as to_note_id
     FROM ZSFNOTEBACKLINK as nl
+    LEFT JOIN ZSFNOTE as from_note ON from_note.Z_PK = nl.ZLINKEDBY
+    LEFT JOIN ZSFNOTE as to_note ON to_note.Z_PK = nl.ZLINKINGTO
+  ),
+  attachments AS ({3}
   )
 "#,
-    metadata.junction_notes_column, metadata.junction_tags_column, metadata.junction_table_name
+    metadata.junction_notes_column,
+    metadata.junction_tags_column,
+    metadata.junction_table_name,
+    attachments_view(metadata)
   )
 }
 
@@ -186,11 +327,29 @@ WITH
 mod tests {
   use super::*;
 
+  /// Bear's tables other than the junction table, without rows
+  const CORE_TABLES: &str = r"
+    CREATE TABLE ZSFNOTE (
+      Z_PK INTEGER PRIMARY KEY,
+      ZUNIQUEIDENTIFIER TEXT,
+      ZTITLE TEXT,
+      ZTEXT TEXT,
+      ZMODIFICATIONDATE REAL,
+      ZCREATIONDATE REAL,
+      ZPINNED INTEGER,
+      ZTRASHED INTEGER,
+      ZARCHIVED INTEGER
+    );
+    CREATE TABLE ZSFNOTETAG (Z_PK INTEGER PRIMARY KEY, ZTITLE TEXT, ZMODIFICATIONDATE REAL);
+    CREATE TABLE ZSFNOTEBACKLINK (ZLINKEDBY INTEGER, ZLINKINGTO INTEGER);
+  ";
+
   #[test]
   fn test_discover_metadata_with_test_schema() {
     let conn = Connection::open_in_memory().unwrap();
 
     // Set up a Bear-like schema with Z_5TAGS junction table
+    conn.execute_batch(CORE_TABLES).unwrap();
     conn
       .execute_batch(
         r"
@@ -207,6 +366,109 @@ mod tests {
     assert_eq!(metadata.junction_table_name, "Z_5TAGS");
     assert_eq!(metadata.junction_notes_column, "Z_5NOTES");
     assert_eq!(metadata.junction_tags_column, "Z_13TAGS");
+    assert!(!metadata.has_attachments);
+  }
+
+  #[test]
+  fn test_discover_attachments_table() {
+    let conn = Connection::open_in_memory().unwrap();
+    setup_test_schema(&conn).unwrap();
+
+    let metadata = discover_metadata(&conn).unwrap();
+    assert!(metadata.has_attachments);
+
+    conn
+      .execute_batch(
+        r"
+      INSERT INTO ZSFNOTEFILE (Z_PK, ZUNIQUEIDENTIFIER, ZNOTE, ZFILENAME, ZFILESIZE, ZCREATIONDATE, ZMODIFICATIONDATE)
+      VALUES (1, 'file-uuid-1', 2, 'diagram.png', 1024, 0, 0);
+    ",
+      )
+      .unwrap();
+
+    let cte = generate_normalizing_cte(&metadata);
+    let (note_id, filename): (String, String) = conn
+      .query_row(
+        &format!("{}\nSELECT note_id, filename FROM attachments", cte),
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+      )
+      .unwrap();
+    assert_eq!(note_id, "note-uuid-2");
+    assert_eq!(filename, "diagram.png");
+  }
+
+  #[test]
+  fn test_missing_attachments_table_yields_empty_view() {
+    let conn = Connection::open_in_memory().unwrap();
+    setup_test_schema(&conn).unwrap();
+    conn.execute_batch("DROP TABLE ZSFNOTEFILE").unwrap();
+
+    let metadata = discover_metadata(&conn).unwrap();
+    assert!(!metadata.has_attachments);
+
+    let cte = generate_normalizing_cte(&metadata);
+    let count: i64 = conn
+      .query_row(
+        &format!("{}\nSELECT COUNT(*) FROM attachments", cte),
+        [],
+        |row| row.get(0),
+      )
+      .unwrap();
+    assert_eq!(count, 0);
+  }
+
+  /// The join-based `note_tags` and `note_links` return the same rows as the
+  /// correlated subqueries they replaced, including for junction rows whose note is gone
+  #[test]
+  fn test_junction_views_match_correlated_subqueries() {
+    let conn = Connection::open_in_memory().unwrap();
+    setup_test_schema(&conn).unwrap();
+    conn
+      .execute_batch(
+        r"
+      INSERT INTO Z_5TAGS (Z_5NOTES, Z_13TAGS) VALUES (1, 2), (99, 1);
+      INSERT INTO ZSFNOTEBACKLINK (ZLINKEDBY, ZLINKINGTO) VALUES (2, 1), (1, 99), (98, 3);
+    ",
+      )
+      .unwrap();
+    let cte = generate_normalizing_cte(&discover_metadata(&conn).unwrap());
+
+    let rows = |sql: &str| -> Vec<(Option<String>, Option<String>)> {
+      let mut stmt = conn.prepare(sql).unwrap();
+      let mut rows = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+      rows.sort();
+      rows
+    };
+
+    assert_eq!(
+      rows(&format!(
+        "{}\nSELECT CAST(tag_id AS TEXT), note_id FROM note_tags",
+        cte
+      )),
+      rows(
+        "SELECT
+          CAST(nt.Z_13TAGS AS TEXT),
+          (SELECT n.ZUNIQUEIDENTIFIER FROM ZSFNOTE n WHERE n.Z_PK = nt.Z_5NOTES)
+        FROM Z_5TAGS as nt"
+      )
+    );
+    assert_eq!(
+      rows(&format!(
+        "{}\nSELECT from_note_id, to_note_id FROM note_links",
+        cte
+      )),
+      rows(
+        "SELECT
+          (SELECT n.ZUNIQUEIDENTIFIER FROM ZSFNOTE n WHERE n.Z_PK = nl.ZLINKEDBY),
+          (SELECT n.ZUNIQUEIDENTIFIER FROM ZSFNOTE n WHERE n.Z_PK = nl.ZLINKINGTO)
+        FROM ZSFNOTEBACKLINK as nl"
+      )
+    );
   }
 
   #[test]
@@ -214,6 +476,7 @@ mod tests {
     let conn = Connection::open_in_memory().unwrap();
 
     // Test with different numbers (simulating a different Bear version)
+    conn.execute_batch(CORE_TABLES).unwrap();
     conn
       .execute_batch(
         r"
@@ -232,21 +495,67 @@ mod tests {
     assert_eq!(metadata.junction_tags_column, "Z_15TAGS");
   }
 
+  #[test]
+  fn test_unsupported_schema() {
+    let conn = Connection::open_in_memory().unwrap();
+    conn
+      .execute_batch(
+        r"
+      CREATE TABLE ZSFNOTE (Z_PK INTEGER PRIMARY KEY, ZUNIQUEIDENTIFIER TEXT, ZTITLE TEXT);
+      CREATE TABLE ZSFNOTETAG (Z_PK INTEGER PRIMARY KEY, ZTITLE TEXT, ZMODIFICATIONDATE REAL);
+      CREATE TABLE Z_5TAGS (Z_5NOTES INTEGER);
+    ",
+      )
+      .unwrap();
+
+    match discover_metadata(&conn) {
+      Err(BearError::SchemaUnsupported {
+        missing_tables,
+        missing_columns,
+      }) => {
+        assert_eq!(missing_tables, vec!["ZSFNOTEBACKLINK"]);
+        assert_eq!(
+          missing_columns,
+          vec![
+            "ZSFNOTE.ZTEXT",
+            "ZSFNOTE.ZMODIFICATIONDATE",
+            "ZSFNOTE.ZCREATIONDATE",
+            "ZSFNOTE.ZPINNED",
+            "ZSFNOTE.ZTRASHED",
+            "ZSFNOTE.ZARCHIVED",
+            "Z_5TAGS.Z_<n>TAGS",
+          ]
+        );
+      }
+      other => panic!("expected SchemaUnsupported, got {:?}", other),
+    }
+
+    // Not a Bear database at all
+    let conn = Connection::open_in_memory().unwrap();
+    let error = discover_metadata(&conn).unwrap_err();
+    assert_eq!(
+      error.to_string(),
+      "Unsupported Bear database schema (missing tables: [ZSFNOTE, ZSFNOTETAG, ZSFNOTEBACKLINK, Z_<n>TAGS], missing columns: [])"
+    );
+  }
+
   #[test]
   fn test_generate_normalizing_cte() {
     let metadata = BearDbMetadata {
       junction_table_name: "Z_5TAGS".to_string(),
       junction_notes_column: "Z_5NOTES".to_string(),
       junction_tags_column: "Z_13TAGS".to_string(),
+      has_attachments: true,
     };
 
     let cte = generate_normalizing_cte(&metadata);
 
     // Verify the CTE contains the correct table and column references
     assert!(cte.contains("FROM Z_5TAGS as nt"));
-    assert!(cte.contains("nt.Z_5NOTES")); // Referenced in subquery
-    assert!(cte.contains("nt.Z_13TAGS")); // Referenced in subquery
+    assert!(cte.contains("n.Z_PK = nt.Z_5NOTES")); // Joined to resolve note UUIDs
+    assert!(cte.contains("nt.Z_13TAGS as tag_id"));
     assert!(cte.contains("ZUNIQUEIDENTIFIER as id")); // Notes use UUID as id
     assert!(cte.contains("Z_PK as core_db_id")); // Internal ID renamed to core_db_id
+    assert!(cte.contains("FROM ZSFNOTEFILE as f")); // Attachments view when available
   }
 }
@@ -0,0 +1,250 @@
+//! Time zones for reading Bear's UTC timestamps as local dates and times.
+//!
+//! Bear stores every timestamp in UTC, and the normalized views and `Note` return them
+//! that way. A [`TimeZone`] turns them into local dates for filters like
+//! [`NotesQuery::modified_on()`](crate::NotesQuery::modified_on), the `local_date()`
+//! SQL function and DataFrame datetime columns (see
+//! [`BearDb::with_time_zone()`](crate::BearDb::with_time_zone)).
+use crate::BearError;
+use std::fmt;
+use std::str::FromStr;
+use time::format_description::well_known::iso8601::{self, Iso8601};
+use time::{Date, Duration, OffsetDateTime, UtcOffset};
+use time_tz::{Offset, OffsetDateTimeExt, OffsetResult, PrimitiveDateTimeExt, Tz};
+
+/// A time zone: UTC, a fixed offset from UTC or an IANA zone like
+/// `America/Los_Angeles`, which follows daylight saving time.
+///
+/// Parses from `"UTC"`, an offset like `"-08:00"` or an IANA zone name.
+///
+/// # Examples
+///
+/// ```
+/// use bear_query::TimeZone;
+/// use time::macros::{datetime, offset};
+///
+/// let pacific: TimeZone = "America/Los_Angeles".parse().unwrap();
+/// let modified = datetime!(2024-03-02 05:30 UTC);
+/// assert_eq!(pacific.local_date(modified).to_string(), "2024-03-01");
+///
+/// let fixed = TimeZone::from(offset!(+8));
+/// assert_eq!(fixed.to_local(modified), datetime!(2024-03-02 13:30 +8));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimeZone {
+  /// Coordinated Universal Time (default)
+  #[default]
+  Utc,
+  /// A fixed offset from UTC
+  Offset(UtcOffset),
+  /// A zone of the IANA time zone database
+  Named(&'static Tz),
+}
+
+impl TimeZone {
+  /// Looks up a zone of the IANA time zone database by name, such as
+  /// `"Europe/Paris"`.
+  ///
+  /// Fails with `BearError::UnknownTimeZone` if there is no such zone.
+  pub fn named(name: &str) -> Result<Self, BearError> {
+    if name == "UTC" {
+      return Ok(TimeZone::Utc);
+    }
+    time_tz::timezones::get_by_name(name)
+      .map(TimeZone::Named)
+      .ok_or_else(|| BearError::UnknownTimeZone(name.to_string()))
+  }
+
+  /// The zone's name: `UTC`, an offset like `-08:00` or the IANA name
+  pub fn name(&self) -> String {
+    self.to_string()
+  }
+
+  /// The zone's offset from UTC at `instant`
+  pub fn offset_at(
+    &self,
+    instant: OffsetDateTime,
+  ) -> UtcOffset {
+    match self {
+      TimeZone::Utc => UtcOffset::UTC,
+      TimeZone::Offset(offset) => *offset,
+      TimeZone::Named(tz) => time_tz::TimeZone::get_offset_utc(*tz, &instant).to_utc(),
+    }
+  }
+
+  /// Converts `instant` to this zone's local time
+  pub fn to_local(
+    &self,
+    instant: OffsetDateTime,
+  ) -> OffsetDateTime {
+    match self {
+      TimeZone::Named(tz) => instant.to_timezone(*tz),
+      zone => instant.to_offset(zone.offset_at(instant)),
+    }
+  }
+
+  /// The local date of `instant` in this zone
+  pub fn local_date(
+    &self,
+    instant: OffsetDateTime,
+  ) -> Date {
+    self.to_local(instant).date()
+  }
+
+  /// The first instant of `date` in this zone
+  pub fn start_of_day(
+    &self,
+    date: Date,
+  ) -> OffsetDateTime {
+    let midnight = date.midnight();
+    match self {
+      TimeZone::Utc => midnight.assume_utc(),
+      TimeZone::Offset(offset) => midnight.assume_offset(*offset),
+      TimeZone::Named(tz) => match midnight.assume_timezone(*tz) {
+        OffsetResult::Some(start) | OffsetResult::Ambiguous(start, _) => start,
+        // Midnight was skipped by a daylight saving change, so the day starts at the
+        // change: when the previous day's offset reaches midnight
+        OffsetResult::None => {
+          let previous = self.offset_at((midnight - Duration::DAY).assume_utc());
+          midnight.assume_offset(previous)
+        }
+      },
+    }
+  }
+
+  /// The UTC instants `[start, end)` covering `date` in this zone, which are 23 or 25
+  /// hours apart on days with a daylight saving change
+  pub fn day_bounds(
+    &self,
+    date: Date,
+  ) -> (OffsetDateTime, OffsetDateTime) {
+    let end = date
+      .next_day()
+      .map(|next| self.start_of_day(next))
+      .unwrap_or_else(|| self.start_of_day(date) + Duration::DAY);
+    (self.start_of_day(date), end)
+  }
+}
+
+impl From<UtcOffset> for TimeZone {
+  fn from(offset: UtcOffset) -> Self {
+    if offset.is_utc() {
+      TimeZone::Utc
+    } else {
+      TimeZone::Offset(offset)
+    }
+  }
+}
+
+impl From<&'static Tz> for TimeZone {
+  fn from(tz: &'static Tz) -> Self {
+    TimeZone::Named(tz)
+  }
+}
+
+impl FromStr for TimeZone {
+  type Err = BearError;
+
+  fn from_str(name: &str) -> Result<Self, Self::Err> {
+    if name.starts_with(['+', '-']) {
+      return parse_offset(name)
+        .map(TimeZone::from)
+        .ok_or_else(|| BearError::UnknownTimeZone(name.to_string()));
+    }
+    Self::named(name)
+  }
+}
+
+impl fmt::Display for TimeZone {
+  fn fmt(
+    &self,
+    f: &mut fmt::Formatter<'_>,
+  ) -> fmt::Result {
+    match self {
+      TimeZone::Utc => f.write_str("UTC"),
+      TimeZone::Offset(offset) => {
+        let (hours, minutes, _) = offset.as_hms();
+        let sign = if offset.is_negative() { '-' } else { '+' };
+        write!(f, "{}{:02}:{:02}", sign, hours.abs(), minutes.abs())
+      }
+      TimeZone::Named(tz) => f.write_str(time_tz::TimeZone::name(*tz)),
+    }
+  }
+}
+
+/// Parses an ISO 8601 offset like `-08:00`, `+0530` or `+08`
+fn parse_offset(text: &str) -> Option<UtcOffset> {
+  const OFFSET: u128 = iso8601::Config::DEFAULT.encode();
+  UtcOffset::parse(text, &Iso8601::<OFFSET>).ok()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use time::macros::{date, datetime, offset};
+
+  fn pacific() -> TimeZone {
+    TimeZone::named("America/Los_Angeles").unwrap()
+  }
+
+  #[test]
+  fn test_parse() {
+    assert_eq!("UTC".parse::<TimeZone>().unwrap(), TimeZone::Utc);
+    assert_eq!(
+      "-08:00".parse::<TimeZone>().unwrap(),
+      TimeZone::Offset(offset!(-8))
+    );
+    assert_eq!(
+      "+0530".parse::<TimeZone>().unwrap(),
+      TimeZone::Offset(offset!(+5:30))
+    );
+    assert_eq!("+00:00".parse::<TimeZone>().unwrap(), TimeZone::Utc);
+    assert_eq!(pacific().name(), "America/Los_Angeles");
+    assert_eq!(TimeZone::from(offset!(-3:30)).to_string(), "-03:30");
+
+    assert!(matches!(
+      "Mars/Olympus_Mons".parse::<TimeZone>(),
+      Err(BearError::UnknownTimeZone(name)) if name == "Mars/Olympus_Mons"
+    ));
+    assert!("+08:70".parse::<TimeZone>().is_err());
+  }
+
+  #[test]
+  fn test_local_date() {
+    // 05:30 UTC is still the previous evening in California
+    let modified = datetime!(2024-03-02 05:30 UTC);
+    assert_eq!(TimeZone::Utc.local_date(modified), date!(2024 - 03 - 02));
+    assert_eq!(pacific().local_date(modified), date!(2024 - 03 - 01));
+    assert_eq!(pacific().offset_at(modified), offset!(-8));
+    assert_eq!(
+      pacific().offset_at(datetime!(2024-07-01 00:00 UTC)),
+      offset!(-7)
+    );
+  }
+
+  #[test]
+  fn test_day_bounds() {
+    assert_eq!(
+      TimeZone::from(offset!(-8)).day_bounds(date!(2024 - 03 - 01)),
+      (
+        datetime!(2024-03-01 08:00 UTC),
+        datetime!(2024-03-02 08:00 UTC)
+      )
+    );
+
+    // Daylight saving time starts on 2024-03-10, a 23 hour day
+    let (start, end) = pacific().day_bounds(date!(2024 - 03 - 10));
+    assert_eq!(start, datetime!(2024-03-10 08:00 UTC));
+    assert_eq!(end, datetime!(2024-03-11 07:00 UTC));
+
+    // and ends on 2024-11-03, a 25 hour day
+    let (start, end) = pacific().day_bounds(date!(2024 - 11 - 03));
+    assert_eq!(end - start, Duration::hours(25));
+
+    // Cuba moves its clocks forward at midnight, so 2024-03-10 starts at 01:00
+    let havana = TimeZone::named("America/Havana").unwrap();
+    let (start, end) = havana.day_bounds(date!(2024 - 03 - 10));
+    assert_eq!(start, datetime!(2024-03-10 05:00 UTC));
+    assert_eq!(end, datetime!(2024-03-11 04:00 UTC));
+  }
+}